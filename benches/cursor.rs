@@ -0,0 +1,55 @@
+#[macro_use]
+extern crate bencher;
+extern crate btreedb;
+extern crate tempdir;
+
+use bencher::{black_box, Bencher};
+use tempdir::TempDir;
+
+use btreedb::{Cursor, Database, Transaction, WriteFlags};
+
+/// Number of items in the database the benchmarks scan.
+const ITEMS: usize = 10000;
+
+fn setup() -> (TempDir, Database) {
+    let dir = TempDir::new("bench").unwrap();
+    let db = Database::new().open(&dir.path().join("bench")).unwrap();
+    db.update(|txn| {
+        for i in 0..ITEMS {
+            let key = format!("key{:05}", i);
+            txn.put(&db, &key, &[0; 100], WriteFlags::empty())?;
+        }
+        Ok(())
+    })
+    .unwrap();
+    (dir, db)
+}
+
+fn bench_iter(b: &mut Bencher) {
+    let (_dir, db) = setup();
+    let txn = db.begin_ro_txn().unwrap();
+    b.iter(|| {
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        let mut len = 0;
+        for (key, data) in cursor.iter() {
+            len += key.len() + data.len();
+        }
+        black_box(len)
+    });
+}
+
+fn bench_iter_ref(b: &mut Bencher) {
+    let (_dir, db) = setup();
+    let txn = db.begin_ro_txn().unwrap();
+    b.iter(|| {
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        let mut len = 0;
+        cursor.iter_ref().for_each(|key, data| {
+            len += key.len() + data.len();
+        });
+        black_box(len)
+    });
+}
+
+benchmark_group!(benches, bench_iter, bench_iter_ref);
+benchmark_main!(benches);
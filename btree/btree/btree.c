@@ -49,6 +49,8 @@
 #endif
 
 #define PAGESIZE	 4096
+#define MINPAGESIZE	 512
+#define MAXPAGESIZE	 32768
 #define BT_MINKEYS	 4
 #define BT_MAGIC	 0xB3DBB3DB
 #define BT_VERSION	 4
@@ -102,6 +104,11 @@ struct bt_head {				/* header page content */
 	uint32_t	 version;
 	uint32_t	 flags;
 	uint32_t	 psize;			/* page size */
+	/* Added without a version bump: the header page has always been
+	 * zero-filled past the fields above, so older files read as 0, and
+	 * older code ignores the field.
+	 */
+	int64_t		 created_at;		/* 0 if not recorded */
 } __packed;
 
 struct bt_meta {				/* meta (footer) page content */
@@ -172,6 +179,7 @@ struct cursor {
 	struct page_stack	 stack;		/* stack of parent pages */
 	short			 initialized;	/* 1 if initialized */
 	short			 eof;		/* 1 if end is reached */
+	short			 deleted;	/* 1 if current item was deleted */
 };
 
 #define METAHASHLEN	 offsetof(struct bt_meta, hash)
@@ -206,6 +214,7 @@ struct btree {
 #define BT_FIXPADDING		 0x01		/* internal */
 	unsigned int		 flags;
 	bt_cmp_func		 cmp;		/* user compare function */
+	void			*cmp_arg;	/* argument passed to cmp */
 	struct bt_head		 head;
 	struct bt_meta		 meta;
 	struct page_cache	*page_cache;
@@ -218,6 +227,15 @@ struct btree {
 
 #define NODESIZE	 offsetof(struct node, data)
 
+/* Largest size of a node, including its index slot, that still fits
+ * BT_MINKEYS nodes to a page.  Page splits rely on it.
+ */
+#define NODEMAX(bt)	 (((bt)->head.psize - PAGEHDRSZ) / BT_MINKEYS)
+
+/* Largest key that fits a node along with an overflow page number. */
+#define MAXKEY(bt)	 MINIMUM(MAXKEYSIZE, NODEMAX(bt) - NODESIZE - \
+			     sizeof(pgno_t) - sizeof(indx_t))
+
 #define INDXSIZE(k)	 (NODESIZE + ((k) == NULL ? 0 : (k)->size))
 #define LEAFSIZE(k, d)	 (NODESIZE + (k)->size + (d)->size)
 #define NODEPTRP(p, i)	 ((struct node *)((char *)(p) + (p)->ptrs[i]))
@@ -229,10 +247,12 @@ struct btree {
 
 #define BT_COMMIT_PAGES	 64	/* max number of pages to write in one commit */
 #define BT_MAXCACHE_DEF	 1024	/* max number of pages to keep in cache  */
+#define BT_MAXDEPTH	 32	/* max depth of a tree addressing all pages */
 
 static int		 btree_read_page(struct btree *bt, pgno_t pgno,
 			    struct page *page);
 static struct mpage	*btree_get_mpage(struct btree *bt, pgno_t pgno);
+static int		 btree_is_rightmost(struct mpage *mp);
 static int		 btree_search_page_root(struct btree *bt,
 			    struct mpage *root, struct btval *key,
 			    struct cursor *cursor, int modify,
@@ -243,6 +263,7 @@ static int		 btree_search_page(struct btree *bt,
 			    struct mpage **mpp);
 
 static int		 btree_write_header(struct btree *bt, int fd);
+static int		 valid_page_size(unsigned int psize);
 static int		 btree_read_header(struct btree *bt);
 static int		 btree_is_meta_page(struct page *p);
 static int		 btree_read_meta(struct btree *bt, pgno_t *p_next);
@@ -289,6 +310,10 @@ static int		 btree_cursor_set(struct cursor *cursor,
 			    struct btval *key, struct btval *data, int *exactp);
 static int		 btree_cursor_first(struct cursor *cursor,
 			    struct btval *key, struct btval *data);
+static int		 btree_cursor_last(struct cursor *cursor,
+			    struct btval *key, struct btval *data);
+static int		 btree_cursor_current(struct cursor *cursor,
+			    struct btval *key, struct btval *data);
 
 static void		 bt_reduce_separator(struct btree *bt, struct node *min,
 			    struct btval *sep);
@@ -309,10 +334,32 @@ static size_t		 bt_branch_size(struct btree *bt, struct btval *key);
 static pgno_t		 btree_compact_tree(struct btree *bt, pgno_t pgno,
 			    struct btree *btc);
 
+static void		 btree_verify_corrupt(struct btree_verify *v,
+			    pgno_t pgno);
+static int		 btree_verify_read(struct btree *bt,
+			    struct btree_verify *v, pgno_t pgno,
+			    struct page *p, uint32_t flags);
+static int		 btree_verify_layout(struct btree *bt, struct page *p);
+static int		 btree_verify_key(struct btree *bt, struct page *p,
+			    struct btkey *pfx, indx_t i, struct btkey *key);
+static int		 btree_verify_cmp(struct btree *bt, struct btkey *a,
+			    struct btkey *b);
+static int		 btree_verify_overflow(struct btree *bt,
+			    struct btree_verify *v, pgno_t pgno,
+			    struct node *leaf, struct page *p);
+static int		 btree_verify_tree(struct btree *bt,
+			    struct btree_verify *v, pgno_t pgno,
+			    struct btkey *lbound, struct btkey *ubound,
+			    unsigned int depth);
+
 static int		 memncmp(const void *s1, size_t n1,
 				 const void *s2, size_t n2);
 static int		 memnrcmp(const void *s1, size_t n1,
 				  const void *s2, size_t n2);
+static int		 bt_cmp(struct btree *bt, const struct btval *key1,
+			    const struct btval *key2, struct btkey *pfx);
+static int		 bt_user_cmp(struct btree *bt, const struct btval *a,
+			    const struct btval *b);
 
 static int
 memncmp(const void *s1, size_t n1, const void *s2, size_t n2)
@@ -354,10 +401,34 @@ memnrcmp(const void *s1, size_t n1, const void *s2, size_t n2)
 	return *p1 - *p2;
 }
 
+/* Calls the user compare function.  If it fails, the write transaction
+ * is flagged so that it can't be committed, and the keys compare equal.
+ */
+static int
+bt_user_cmp(struct btree *bt, const struct btval *a, const struct btval *b)
+{
+	int		 rc;
+
+	rc = bt->cmp(a, b, bt->cmp_arg);
+	if (rc == BT_CMP_FAIL) {
+		DPRINTF("user compare function failed");
+		if (bt->txn != NULL)
+			bt->txn->flags |= BT_TXN_ERROR;
+		rc = 0;
+	}
+	return rc;
+}
+
 int
 btree_cmp(struct btree *bt, const struct btval *a, const struct btval *b)
 {
-	return bt->cmp(a, b);
+	struct btkey	 pfx;
+
+	if (bt->cmp != NULL)
+		return bt_user_cmp(bt, a, b);
+
+	pfx.len = 0;
+	return bt_cmp(bt, a, b, &pfx);
 }
 
 static void
@@ -691,11 +762,97 @@ btree_txn_begin(struct btree *bt, int rdonly)
 	return txn;
 }
 
+/* Begins a read-only transaction on the tree as it was after the given
+ * revision was committed. The file is scanned backwards for the meta page
+ * of that revision, so older revisions take longer to find.
+ */
+struct btree_txn *
+btree_txn_begin_at(struct btree *bt, unsigned int revision)
+{
+	struct btree_txn	*txn;
+	struct page		*p;
+	struct bt_meta		*meta;
+	pgno_t			 pgno;
+
+	if ((txn = btree_txn_begin(bt, 1)) == NULL)
+		return NULL;
+
+	if (revision == bt->meta.revisions && revision != 0)
+		return txn;
+
+	if ((p = malloc(bt->head.psize)) == NULL) {
+		btree_txn_abort(txn);
+		return NULL;
+	}
+
+	/* Read the pages directly rather than through the page cache, as
+	 * most of them are of no further use.  Pages of overflow data fail
+	 * to read as their page numbers don't match, and are skipped.
+	 */
+	pgno = bt->size / bt->head.psize;
+	while (revision != 0 && revision < bt->meta.revisions && pgno-- > 1) {
+		if (btree_read_page(bt, pgno, p) != BT_SUCCESS) {
+			if (errno == EINVAL)
+				continue;
+			free(p);
+			btree_txn_abort(txn);
+			return NULL;
+		}
+		if (!btree_is_meta_page(p))
+			continue;
+		meta = METADATA(p);
+		if (meta->revisions < revision)
+			break;
+		if (meta->revisions == revision) {
+			txn->root = meta->root;
+			DPRINTF("begin transaction on btree %p at revision %u,"
+			    " root page %u", bt, revision, txn->root);
+			free(p);
+			return txn;
+		}
+	}
+
+	DPRINTF("revision %u not found", revision);
+	free(p);
+	btree_txn_abort(txn);
+	errno = ENOENT;
+	return NULL;
+}
+
+/* Begins a read-only transaction on the same tree as the given one, which
+ * may belong to another handle on the same file.  Pages are never
+ * overwritten, so this only takes the root page of the other transaction,
+ * without looking for its meta page.  Fails with ENOENT if the handles are
+ * on different files, as after a compaction replaced the file.
+ */
+struct btree_txn *
+btree_txn_begin_like(struct btree *bt, struct btree_txn *other)
+{
+	struct btree_txn	*txn;
+	struct stat		 sb, other_sb;
+
+	if (fstat(bt->fd, &sb) != 0 || fstat(other->bt->fd, &other_sb) != 0)
+		return NULL;
+	if (sb.st_dev != other_sb.st_dev || sb.st_ino != other_sb.st_ino) {
+		DPRINTF("btree %p is not on the file of txn %p", bt, other);
+		errno = ENOENT;
+		return NULL;
+	}
+
+	if ((txn = btree_txn_begin(bt, 1)) == NULL)
+		return NULL;
+	txn->root = other->root;
+	DPRINTF("begin transaction on btree %p, root page %u", bt, txn->root);
+
+	return txn;
+}
+
 void
 btree_txn_abort(struct btree_txn *txn)
 {
 	struct mpage	*mp;
 	struct btree	*bt;
+	int		 saved_errno;
 
 	if (txn == NULL)
 		return;
@@ -721,12 +878,47 @@ btree_txn_abort(struct btree_txn *txn)
 			    txn->bt->fd, strerror(errno));
 		}
 		free(txn->dirty_queue);
+
+		/* Read the meta page back, undoing any changes to the
+		 * in-memory copy.  Keep errno for callers failing with it.
+		 */
+		saved_errno = errno;
+		bt->size = 0;
+		btree_read_meta(bt, NULL);
+		errno = saved_errno;
 	}
 
 	btree_close(txn->bt);
 	free(txn);
 }
 
+/* Empties the tree by dropping its root page. None of the pages are
+ * rewritten; the next commit only writes a meta page without a root.
+ */
+int
+btree_txn_clear(struct btree *bt, struct btree_txn *txn)
+{
+	if (bt == NULL || txn == NULL || bt != txn->bt || txn != bt->txn) {
+		errno = EINVAL;
+		return BT_FAIL;
+	}
+
+	if (F_ISSET(txn->flags, BT_TXN_RDONLY | BT_TXN_ERROR)) {
+		errno = EINVAL;
+		return BT_FAIL;
+	}
+
+	DPRINTF("clearing btree %p, root page %u", bt, txn->root);
+	txn->root = P_INVALID;
+	bt->meta.depth = 0;
+	bt->meta.entries = 0;
+	bt->meta.branch_pages = 0;
+	bt->meta.leaf_pages = 0;
+	bt->meta.overflow_pages = 0;
+
+	return BT_SUCCESS;
+}
+
 int
 btree_txn_commit(struct btree_txn *txn)
 {
@@ -763,7 +955,7 @@ btree_txn_commit(struct btree_txn *txn)
 		return BT_FAIL;
 	}
 
-	if (SIMPLEQ_EMPTY(txn->dirty_queue))
+	if (SIMPLEQ_EMPTY(txn->dirty_queue) && txn->root == bt->meta.root)
 		goto done;
 
 	if (F_ISSET(bt->flags, BT_FIXPADDING)) {
@@ -846,7 +1038,7 @@ btree_write_header(struct btree *bt, int fd)
 	DPRINTF("writing header page");
 	assert(bt != NULL);
 
-	psize = PAGESIZE;
+	psize = bt->head.psize ? bt->head.psize : PAGESIZE;
 
 	if ((p = calloc(1, psize)) == NULL)
 		return -1;
@@ -856,6 +1048,8 @@ btree_write_header(struct btree *bt, int fd)
 	h->magic = BT_MAGIC;
 	h->version = BT_VERSION;
 	h->psize = psize;
+	/* Compaction keeps the creation time, including an unknown one. */
+	h->created_at = bt->head.created_at;
 	bcopy(h, &bt->head, sizeof(*h));
 
 	rc = write(fd, p, bt->head.psize);
@@ -869,10 +1063,17 @@ btree_write_header(struct btree *bt, int fd)
 	return BT_SUCCESS;
 }
 
+static int
+valid_page_size(unsigned int psize)
+{
+	return psize >= MINPAGESIZE && psize <= MAXPAGESIZE &&
+	    (psize & (psize - 1)) == 0;
+}
+
 static int
 btree_read_header(struct btree *bt)
 {
-	char		 page[PAGESIZE];
+	char		 page[MINPAGESIZE];
 	struct page	*p;
 	struct bt_head	*h;
 	int		 rc;
@@ -882,10 +1083,10 @@ btree_read_header(struct btree *bt)
 	/* We don't know the page size yet, so use a minimum value.
 	 */
 
-	if ((rc = pread(bt->fd, page, PAGESIZE, 0)) == 0) {
+	if ((rc = pread(bt->fd, page, MINPAGESIZE, 0)) == 0) {
 		errno = ENOENT;
 		return -1;
-	} else if (rc != PAGESIZE) {
+	} else if (rc != MINPAGESIZE) {
 		if (rc > 0)
 			errno = EINVAL;
 		DPRINTF("read: %s", strerror(errno));
@@ -914,6 +1115,12 @@ btree_read_header(struct btree *bt)
 		return -1;
 	}
 
+	if (!valid_page_size(h->psize)) {
+		DPRINTF("header has invalid page size %u", h->psize);
+		errno = EINVAL;
+		return -1;
+	}
+
 	bcopy(h, &bt->head, sizeof(*h));
 	return 0;
 }
@@ -1076,10 +1283,21 @@ fail:
 
 struct btree *
 btree_open_fd(int fd, unsigned int flags)
+{
+	return btree_open_fd_psize(fd, flags, 0);
+}
+
+struct btree *
+btree_open_fd_psize(int fd, unsigned int flags, unsigned int psize)
 {
 	struct btree	*bt;
 	int		 fl;
 
+	if (psize != 0 && !valid_page_size(psize)) {
+		errno = EINVAL;
+		return NULL;
+	}
+
 	fl = fcntl(fd, F_GETFL);
 	if (fcntl(fd, F_SETFL, fl | O_APPEND) == -1)
 		return NULL;
@@ -1091,6 +1309,7 @@ btree_open_fd(int fd, unsigned int flags)
 	bt->flags &= ~BT_FIXPADDING;
 	bt->ref = 1;
 	bt->meta.root = P_INVALID;
+	bt->head.psize = psize;		/* used only for a new database */
 
 	if ((bt->page_cache = calloc(1, sizeof(*bt->page_cache))) == NULL)
 		goto fail;
@@ -1105,6 +1324,7 @@ btree_open_fd(int fd, unsigned int flags)
 		if (errno != ENOENT)
 			goto fail;
 		DPRINTF("new database");
+		bt->head.created_at = time(0);
 		btree_write_header(bt, bt->fd);
 	}
 
@@ -1134,19 +1354,33 @@ fail:
 
 struct btree *
 btree_open(const char *path, unsigned int flags, mode_t mode)
+{
+	return btree_open_psize(path, flags, mode, 0);
+}
+
+struct btree *
+btree_open_psize(const char *path, unsigned int flags, mode_t mode,
+    unsigned int psize)
 {
 	int		 fd, oflags;
 	struct btree	*bt;
 
-	if (F_ISSET(flags, BT_RDONLY))
+	if (F_ISSET(flags, BT_RDONLY)) {
+		if (F_ISSET(flags, BT_EXCL)) {
+			errno = EINVAL;
+			return NULL;
+		}
 		oflags = O_RDONLY;
-	else
+	} else
 		oflags = O_RDWR | O_CREAT | O_APPEND;
+	if (F_ISSET(flags, BT_EXCL))
+		oflags |= O_EXCL;
+	flags &= ~BT_EXCL;
 
 	if ((fd = open(path, oflags, mode)) == -1)
 		return NULL;
 
-	if ((bt = btree_open_fd(fd, flags)) == NULL)
+	if ((bt = btree_open_fd_psize(fd, flags, psize)) == NULL)
 		close(fd);
 	else {
 		bt->path = strdup(path);
@@ -1217,7 +1451,7 @@ btree_search_node(struct btree *bt, struct mpage *mp, struct btval *key,
 		nodekey.data = NODEKEY(node);
 
 		if (bt->cmp)
-			rc = bt->cmp(key, &nodekey);
+			rc = bt_user_cmp(bt, key, &nodekey);
 		else
 			rc = bt_cmp(bt, key, &nodekey, &mp->prefix);
 
@@ -1635,6 +1869,10 @@ btree_sibling(struct cursor *cursor, int move_right)
 	cursor_push_page(cursor, mp);
 	find_common_prefix(cursor->bt, mp);
 
+	/* Moving left, enter the sibling at its last key. */
+	if (!move_right)
+		CURSOR_TOP(cursor)->ki = NUMKEYS(mp) - 1;
+
 	return BT_SUCCESS;
 }
 
@@ -1712,6 +1950,47 @@ btree_cursor_next(struct cursor *cursor, struct btval *key, struct btval *data)
 	return BT_SUCCESS;
 }
 
+static int
+btree_cursor_prev(struct cursor *cursor, struct btval *key, struct btval *data)
+{
+	struct ppage	*top;
+	struct mpage	*mp;
+	struct node	*leaf;
+
+	if (!cursor->initialized || cursor->eof) {
+		errno = ENOENT;
+		return BT_FAIL;
+	}
+
+	top = CURSOR_TOP(cursor);
+	mp = top->mpage;
+
+	DPRINTF("cursor_prev: top page is %u in cursor %p", mp->pgno, cursor);
+
+	if (top->ki == 0) {
+		DPRINTF("=====> move to previous sibling page");
+		if (btree_sibling(cursor, 0) != BT_SUCCESS) {
+			cursor->eof = 1;
+			return BT_FAIL;
+		}
+		top = CURSOR_TOP(cursor);
+		mp = top->mpage;
+		DPRINTF("previous page is %u, key index %u", mp->pgno, top->ki);
+	} else
+		top->ki--;
+
+	assert(IS_LEAF(mp));
+	leaf = NODEPTR(mp, top->ki);
+
+	if (data && btree_read_data(cursor->bt, mp, leaf, data) != BT_SUCCESS)
+		return BT_FAIL;
+
+	if (bt_set_key(cursor->bt, mp, leaf, key) != 0)
+		return BT_FAIL;
+
+	return BT_SUCCESS;
+}
+
 static int
 btree_cursor_set(struct cursor *cursor, struct btval *key, struct btval *data,
     int *exactp)
@@ -1788,6 +2067,97 @@ btree_cursor_first(struct cursor *cursor, struct btval *key, struct btval *data)
 	return BT_SUCCESS;
 }
 
+static int
+btree_cursor_last(struct cursor *cursor, struct btval *key, struct btval *data)
+{
+	struct btree	*bt = cursor->bt;
+	struct btree_txn *txn = cursor->txn;
+	struct mpage	*mp, *parent;
+	struct node	*leaf;
+	pgno_t		 root;
+	unsigned int	 i;
+
+	if (txn == NULL) {
+		if (btree_read_meta(bt, NULL) != BT_SUCCESS)
+			return BT_FAIL;
+		root = bt->meta.root;
+	} else if (F_ISSET(txn->flags, BT_TXN_ERROR)) {
+		errno = EINVAL;
+		return BT_FAIL;
+	} else
+		root = txn->root;
+
+	if (root == P_INVALID) {		/* Tree is empty. */
+		errno = ENOENT;
+		return BT_FAIL;
+	}
+
+	if ((mp = btree_get_mpage(bt, root)) == NULL)
+		return BT_FAIL;
+	if (cursor_push_page(cursor, mp) == NULL)
+		return BT_FAIL;
+
+	/* Follow the rightmost child down to the last leaf page. */
+	while (IS_BRANCH(mp)) {
+		i = NUMKEYS(mp) - 1;
+		CURSOR_TOP(cursor)->ki = i;
+		parent = mp;
+		if ((mp = btree_get_mpage(bt, NODEPGNO(NODEPTR(mp, i)))) == NULL)
+			return BT_FAIL;
+		mp->parent = parent;
+		mp->parent_index = i;
+		find_common_prefix(bt, mp);
+		if (cursor_push_page(cursor, mp) == NULL)
+			return BT_FAIL;
+	}
+	assert(IS_LEAF(mp));
+
+	i = NUMKEYS(mp) - 1;
+	CURSOR_TOP(cursor)->ki = i;
+	leaf = NODEPTR(mp, i);
+	cursor->initialized = 1;
+	cursor->eof = 0;
+
+	if (data && btree_read_data(bt, mp, leaf, data) != BT_SUCCESS)
+		return BT_FAIL;
+
+	if (bt_set_key(bt, mp, leaf, key) != 0)
+		return BT_FAIL;
+
+	return BT_SUCCESS;
+}
+
+static int
+btree_cursor_current(struct cursor *cursor, struct btval *key,
+    struct btval *data)
+{
+	struct ppage	*top;
+	struct mpage	*mp;
+	struct node	*leaf;
+
+	if (!cursor->initialized || cursor->eof || CURSOR_EMPTY(cursor)) {
+		errno = ENOENT;
+		return BT_FAIL;
+	}
+
+	top = CURSOR_TOP(cursor);
+	mp = top->mpage;
+	if (!IS_LEAF(mp) || top->ki >= NUMKEYS(mp)) {
+		errno = ENOENT;
+		return BT_FAIL;
+	}
+
+	leaf = NODEPTR(mp, top->ki);
+
+	if (data && btree_read_data(cursor->bt, mp, leaf, data) != BT_SUCCESS)
+		return BT_FAIL;
+
+	if (bt_set_key(cursor->bt, mp, leaf, key) != 0)
+		return BT_FAIL;
+
+	return BT_SUCCESS;
+}
+
 int
 btree_cursor_get(struct cursor *cursor, struct btval *key, struct btval *data,
     enum cursor_op op)
@@ -1797,6 +2167,9 @@ btree_cursor_get(struct cursor *cursor, struct btval *key, struct btval *data,
 
 	assert(cursor);
 
+	if (op != BT_NEXT && op != BT_GET_CURRENT)
+		cursor->deleted = 0;
+
 	switch (op) {
 	case BT_CURSOR:
 	case BT_CURSOR_EXACT:
@@ -1809,9 +2182,16 @@ btree_cursor_get(struct cursor *cursor, struct btval *key, struct btval *data,
 			rc = btree_cursor_set(cursor, key, data, &exact);
 		else
 			rc = btree_cursor_set(cursor, key, data, NULL);
+		/* A failed seek leaves the cursor unpositioned. */
+		if (rc != BT_SUCCESS)
+			cursor->eof = 1;
 		break;
 	case BT_NEXT:
-		if (!cursor->initialized)
+		if (cursor->deleted) {
+			/* Already placed on the successor of a deleted item. */
+			cursor->deleted = 0;
+			rc = btree_cursor_current(cursor, key, data);
+		} else if (!cursor->initialized)
 			rc = btree_cursor_first(cursor, key, data);
 		else
 			rc = btree_cursor_next(cursor, key, data);
@@ -1821,6 +2201,17 @@ btree_cursor_get(struct cursor *cursor, struct btval *key, struct btval *data,
 			cursor_pop_page(cursor);
 		rc = btree_cursor_first(cursor, key, data);
 		break;
+	case BT_LAST:
+		while (CURSOR_TOP(cursor) != NULL)
+			cursor_pop_page(cursor);
+		rc = btree_cursor_last(cursor, key, data);
+		break;
+	case BT_PREV:
+		rc = btree_cursor_prev(cursor, key, data);
+		break;
+	case BT_GET_CURRENT:
+		rc = btree_cursor_current(cursor, key, data);
+		break;
 	default:
 		DPRINTF("unhandled/unimplemented cursor operation %u", op);
 		rc = BT_FAIL;
@@ -1832,6 +2223,66 @@ btree_cursor_get(struct cursor *cursor, struct btval *key, struct btval *data,
 	return rc;
 }
 
+/* Delete the item the cursor is placed on. The cursor is left on the
+ * following item, which the next BT_NEXT returns.
+ */
+int
+btree_cursor_del(struct cursor *cursor)
+{
+	int		 rc;
+	struct btval	 key, found;
+
+	assert(cursor);
+
+	if (cursor->txn == NULL) {
+		errno = EINVAL;
+		return BT_FAIL;
+	}
+
+	if (cursor->deleted) {
+		errno = ENOENT;
+		return BT_FAIL;
+	}
+
+	memset(&found, 0, sizeof(found));
+	if (btree_cursor_current(cursor, &found, NULL) != BT_SUCCESS)
+		return BT_FAIL;
+
+	/* The key may point into a page that the delete modifies. */
+	memset(&key, 0, sizeof(key));
+	if ((key.data = malloc(found.size)) == NULL) {
+		btval_reset(&found);
+		return BT_FAIL;
+	}
+	bcopy(found.data, key.data, found.size);
+	key.size = found.size;
+	key.free_data = 1;
+	btval_reset(&found);
+
+	/* Release the pages so the delete does not have to copy them. */
+	while (CURSOR_TOP(cursor) != NULL)
+		cursor_pop_page(cursor);
+
+	rc = btree_txn_del(cursor->bt, cursor->txn, &key, NULL);
+
+	/* Place the cursor on the successor, or back on the item if the
+	 * delete failed.
+	 */
+	memset(&found, 0, sizeof(found));
+	found.data = key.data;
+	found.size = key.size;
+	if (btree_cursor_set(cursor, &found, NULL, NULL) == BT_SUCCESS) {
+		cursor->deleted = (rc == BT_SUCCESS);
+		btval_reset(&found);
+	} else
+		cursor->eof = 1;
+
+	btval_reset(&key);
+	mpage_prune(cursor->bt);
+
+	return rc;
+}
+
 static struct mpage *
 btree_new_page(struct btree *bt, uint32_t flags)
 {
@@ -1872,7 +2323,7 @@ bt_leaf_size(struct btree *bt, struct btval *key, struct btval *data)
 	size_t		 sz;
 
 	sz = LEAFSIZE(key, data);
-	if (data->size >= bt->head.psize / BT_MINKEYS) {
+	if (sz + sizeof(indx_t) > NODEMAX(bt)) {
 		/* put on overflow page */
 		sz -= data->size - sizeof(pgno_t);
 	}
@@ -1959,7 +2410,7 @@ btree_add_node(struct btree *bt, struct mpage *mp, indx_t indx,
 		if (F_ISSET(flags, F_BIGDATA)) {
 			/* Data already on overflow page. */
 			node_size -= data->size - sizeof(pgno_t);
-		} else if (data->size >= bt->head.psize / BT_MINKEYS) {
+		} else if (node_size + sizeof(indx_t) > NODEMAX(bt)) {
 			/* Put data on overflow page. */
 			DPRINTF("data size is %zu, put on overflow page",
 			    data->size);
@@ -2875,6 +3326,18 @@ btree_split(struct btree *bt, struct mpage **mpp, unsigned int *newindxp,
 	return rc;
 }
 
+/* Returns whether the page is the rightmost one on its level, as found
+ * by the last search.
+ */
+static int
+btree_is_rightmost(struct mpage *mp)
+{
+	for (; mp->parent != NULL; mp = mp->parent)
+		if (mp->parent_index != NUMKEYS(mp->parent) - 1)
+			return 0;
+	return 1;
+}
+
 int
 btree_txn_put(struct btree *bt, struct btree_txn *txn,
     struct btval *key, struct btval *data, unsigned int flags)
@@ -2906,7 +3369,7 @@ btree_txn_put(struct btree *bt, struct btree_txn *txn,
 		bt = txn->bt;
 	}
 
-	if (key->size == 0 || key->size > MAXKEYSIZE) {
+	if (key->size == 0 || key->size > MAXKEY(bt)) {
 		errno = EINVAL;
 		return BT_FAIL;
 	}
@@ -2923,6 +3386,14 @@ btree_txn_put(struct btree *bt, struct btree_txn *txn,
 	rc = btree_search_page(bt, txn, key, NULL, 1, &mp);
 	if (rc == BT_SUCCESS) {
 		leaf = btree_search_node(bt, mp, key, &exact, &ki);
+		if (F_ISSET(flags, BT_APPEND) &&
+		    (leaf != NULL || !btree_is_rightmost(mp))) {
+			DPRINTF("key %.*s doesn't sort after all keys",
+			    (int)key->size, (char *)key->data);
+			errno = EINVAL;
+			rc = BT_FAIL;
+			goto done;
+		}
 		if (leaf && exact) {
 			if (F_ISSET(flags, BT_NOOVERWRITE)) {
 				DPRINTF("duplicate key %.*s",
@@ -2932,6 +3403,7 @@ btree_txn_put(struct btree *bt, struct btree_txn *txn,
 				goto done;
 			}
 			btree_del_node(bt, mp, ki);
+			bt->meta.entries--;	/* counted again below */
 		}
 		if (leaf == NULL) {		/* append if not found */
 			ki = NUMKEYS(mp);
@@ -3053,7 +3525,7 @@ int
 btree_compact(struct btree *bt)
 {
 	char			*compact_path = NULL;
-	struct btree		*btc;
+	struct btree		*btc = NULL;
 	struct btree_txn	*txn, *txnc = NULL;
 	int			 fd;
 	pgno_t			 root;
@@ -3081,7 +3553,11 @@ btree_compact(struct btree *bt)
 		return BT_FAIL;
 	}
 
-	if ((btc = btree_open_fd(fd, 0)) == NULL)
+	if (btree_write_header(bt, fd) != BT_SUCCESS) {
+		close(fd);
+		goto failed;
+	}
+	if ((btc = btree_open_fd_psize(fd, 0, bt->head.psize)) == NULL)
 		goto failed;
 	bcopy(&bt->meta, &btc->meta, sizeof(bt->meta));
 	btc->meta.revisions = 0;
@@ -3138,12 +3614,324 @@ btree_revert(struct btree *bt)
 	return ftruncate(bt->fd, bt->head.psize * bt->meta.root);
 }
 
+static void
+btree_verify_corrupt(struct btree_verify *v, pgno_t pgno)
+{
+	DPRINTF("page %u is corrupt", pgno);
+	if (v->corrupt != NULL)
+		v->corrupt(pgno, v->arg);
+}
+
+/* Reads a page for verification. Returns 1 if the page was read and has
+ * one of the given flags, 0 if it is corrupt and -1 on failure.
+ */
+static int
+btree_verify_read(struct btree *bt, struct btree_verify *v, pgno_t pgno,
+    struct page *p, uint32_t flags)
+{
+	if (btree_read_page(bt, pgno, p) != BT_SUCCESS) {
+		if (errno != EINVAL && errno != ENOENT)
+			return -1;
+		btree_verify_corrupt(v, pgno);
+		return 0;
+	}
+	if ((p->flags & flags) == 0) {
+		DPRINTF("page %u has unexpected flags 0x%X", pgno, p->flags);
+		btree_verify_corrupt(v, pgno);
+		return 0;
+	}
+	return 1;
+}
+
+/* Returns true if all nodes of a branch or leaf page lie within the page.
+ */
+static int
+btree_verify_layout(struct btree *bt, struct page *p)
+{
+	indx_t		 i;
+	size_t		 end;
+	struct node	*node;
+
+	if (p->lower < PAGEHDRSZ || p->lower > p->upper ||
+	    p->upper > bt->head.psize)
+		return 0;
+
+	for (i = 0; i < NUMKEYSP(p); i++) {
+		if (p->ptrs[i] < p->upper ||
+		    p->ptrs[i] + NODESIZE > bt->head.psize)
+			return 0;
+		node = NODEPTRP(p, i);
+		end = p->ptrs[i] + NODESIZE + node->ksize;
+		if (F_ISSET(p->flags, P_LEAF)) {
+			if (F_ISSET(node->flags, F_BIGDATA))
+				end += sizeof(pgno_t);
+			else
+				end += node->n_dsize;
+		}
+		if (end > bt->head.psize)
+			return 0;
+	}
+
+	return 1;
+}
+
+/* Expands the key of node i on page p, stripped of prefix pfx. Returns
+ * false if the expanded key is too long.
+ */
+static int
+btree_verify_key(struct btree *bt, struct page *p, struct btkey *pfx,
+    indx_t i, struct btkey *key)
+{
+	struct node	*node;
+
+	node = NODEPTRP(p, i);
+	if (pfx->len + node->ksize > sizeof(key->str))
+		return 0;
+	key->len = sizeof(key->str);
+	concat_prefix(bt, pfx->str, pfx->len, NODEKEY(node), node->ksize,
+	    key->str, &key->len);
+	return 1;
+}
+
+static int
+btree_verify_cmp(struct btree *bt, struct btkey *a, struct btkey *b)
+{
+	struct btval	 va, vb;
+
+	memset(&va, 0, sizeof(va));
+	memset(&vb, 0, sizeof(vb));
+	va.data = a->str;
+	va.size = a->len;
+	vb.data = b->str;
+	vb.size = b->len;
+	return btree_cmp(bt, &va, &vb);
+}
+
+/* Verifies the overflow pages holding the data of a leaf node on page
+ * pgno.
+ */
+static int
+btree_verify_overflow(struct btree *bt, struct btree_verify *v,
+    pgno_t pgno, struct node *leaf, struct page *p)
+{
+	size_t		 left, max;
+	pgno_t		 next;
+	int		 rc;
+
+	max = bt->head.psize - PAGEHDRSZ;
+	bcopy(NODEDATA(leaf), &next, sizeof(next));
+	for (left = leaf->n_dsize; left > 0; left -= MINIMUM(left, max)) {
+		if (next == 0 || next >= v->pages) {
+			DPRINTF("page %u links to invalid overflow page %u",
+			    pgno, next);
+			btree_verify_corrupt(v, pgno);
+			return BT_SUCCESS;
+		}
+		pgno = next;
+		if ((rc = btree_verify_read(bt, v, pgno, p, P_OVERFLOW)) != 1)
+			return rc == 0 ? BT_SUCCESS : BT_FAIL;
+		v->overflow_pages++;
+		next = p->p_next_pgno;
+	}
+	if (next != 0) {
+		DPRINTF("overflow page %u links past the end of the data", pgno);
+		btree_verify_corrupt(v, pgno);
+	}
+
+	return BT_SUCCESS;
+}
+
+/* Verifies the subtree rooted at page pgno, whose keys must sort within
+ * [lbound, ubound). Missing bounds are NULL.
+ */
+static int
+btree_verify_tree(struct btree *bt, struct btree_verify *v, pgno_t pgno,
+    struct btkey *lbound, struct btkey *ubound, unsigned int depth)
+{
+	indx_t		 i, n, first;
+	int		 rc = BT_SUCCESS;
+	struct btkey	 pfx, key, prev, next;
+	struct btkey	*clower, *cupper;
+	struct node	*node;
+	struct page	*p, *op = NULL;
+
+	if ((p = malloc(bt->head.psize)) == NULL)
+		return BT_FAIL;
+
+	switch (btree_verify_read(bt, v, pgno, p, P_BRANCH | P_LEAF)) {
+	case -1:
+		free(p);
+		return BT_FAIL;
+	case 0:
+		free(p);
+		return BT_SUCCESS;
+	}
+
+	/* Only a root leaf page may be empty, and branch pages have at
+	 * least two children.
+	 */
+	n = NUMKEYSP(p);
+	if (!btree_verify_layout(bt, p) || (n == 0 && depth > 0) ||
+	    (F_ISSET(p->flags, P_BRANCH) && (n < 2 || depth >= BT_MAXDEPTH))) {
+		DPRINTF("page %u has an invalid layout", pgno);
+		goto corrupt;
+	}
+
+	/* Mirror find_common_prefix: a page only has a prefix if it is
+	 * bounded on both sides.
+	 */
+	pfx.len = 0;
+	if (bt->cmp == NULL && lbound != NULL && ubound != NULL)
+		common_prefix(bt, lbound, ubound, &pfx);
+
+	/* The first key of a branch page is unused. */
+	first = F_ISSET(p->flags, P_BRANCH) ? 1 : 0;
+	for (i = first; i < n; i++) {
+		if (!btree_verify_key(bt, p, &pfx, i, &key))
+			goto corrupt;
+		if (i == first) {
+			if (lbound != NULL &&
+			    btree_verify_cmp(bt, lbound, &key) > 0) {
+				DPRINTF("page %u has keys below its bounds", pgno);
+				goto corrupt;
+			}
+		} else if (btree_verify_cmp(bt, &prev, &key) >= 0) {
+			DPRINTF("page %u has unsorted keys", pgno);
+			goto corrupt;
+		}
+		bcopy(&key, &prev, sizeof(key));
+	}
+	if (n > 0 && ubound != NULL &&
+	    btree_verify_cmp(bt, &prev, ubound) >= 0) {
+		DPRINTF("page %u has keys above its bounds", pgno);
+		goto corrupt;
+	}
+
+	if (F_ISSET(p->flags, P_LEAF)) {
+		v->leaf_pages++;
+		for (i = 0; i < n && rc == BT_SUCCESS; i++) {
+			node = NODEPTRP(p, i);
+			if (!F_ISSET(node->flags, F_BIGDATA))
+				continue;
+			if (op == NULL &&
+			    (op = malloc(bt->head.psize)) == NULL) {
+				rc = BT_FAIL;
+				break;
+			}
+			rc = btree_verify_overflow(bt, v, pgno, node, op);
+		}
+		free(op);
+		free(p);
+		return rc;
+	}
+
+	v->branch_pages++;
+	for (i = 0; i < n; i++) {
+		node = NODEPTRP(p, i);
+		if (NODEPGNO(node) == 0 || NODEPGNO(node) >= v->pages) {
+			DPRINTF("page %u links to invalid page %u", pgno,
+			    NODEPGNO(node));
+			btree_verify_corrupt(v, pgno);
+			continue;
+		}
+		clower = lbound;
+		if (i > 0) {
+			btree_verify_key(bt, p, &pfx, i, &key);
+			clower = &key;
+		}
+		cupper = ubound;
+		if (i + 1 < n) {
+			btree_verify_key(bt, p, &pfx, i + 1, &next);
+			cupper = &next;
+		}
+		rc = btree_verify_tree(bt, v, NODEPGNO(node), clower, cupper,
+		    depth + 1);
+		if (rc != BT_SUCCESS)
+			break;
+	}
+	free(p);
+	return rc;
+
+corrupt:
+	btree_verify_corrupt(v, pgno);
+	free(p);
+	return BT_SUCCESS;
+}
+
+/* Verifies the integrity of the database as seen by a new read-only
+ * transaction. Every page in the file is read to check its page number,
+ * and the digests of meta pages are recomputed. The tree of the last
+ * revision is walked to check the ordering of its keys and the links
+ * between its pages. Corrupt pages are reported to v->corrupt and don't
+ * fail the verification, only errors reading the file do.
+ */
+int
+btree_verify(struct btree *bt, struct btree_verify *v)
+{
+	struct btree_txn	*txn;
+	struct page		*p;
+	pgno_t			 pgno;
+	int			 rc;
+
+	if ((txn = btree_txn_begin(bt, 1)) == NULL)
+		return BT_FAIL;
+
+	if ((p = malloc(bt->head.psize)) == NULL) {
+		btree_txn_abort(txn);
+		return BT_FAIL;
+	}
+
+	v->pages = bt->size / bt->head.psize;
+	v->meta_pages = 0;
+	v->branch_pages = 0;
+	v->leaf_pages = 0;
+	v->overflow_pages = 0;
+
+	rc = BT_SUCCESS;
+	for (pgno = 1; pgno < v->pages; pgno++) {
+		switch (btree_verify_read(bt, v, pgno, p,
+		    P_BRANCH | P_LEAF | P_OVERFLOW | P_META)) {
+		case -1:
+			rc = BT_FAIL;
+			break;
+		case 1:
+			if (!F_ISSET(p->flags, P_META))
+				break;
+			v->meta_pages++;
+			if (!btree_is_meta_page(p))
+				btree_verify_corrupt(v, pgno);
+			break;
+		}
+		if (rc != BT_SUCCESS)
+			break;
+	}
+	free(p);
+
+	if (rc == BT_SUCCESS && txn->root != P_INVALID)
+		rc = btree_verify_tree(bt, v, txn->root, NULL, NULL, 0);
+
+	btree_txn_abort(txn);
+	return rc;
+}
+
 void
 btree_set_cache_size(struct btree *bt, unsigned int cache_size)
 {
 	bt->stat.max_cache = cache_size;
 }
 
+/* Sets the function ordering keys, replacing the default byte-wise (or
+ * reverse byte-wise) comparison. It must be set before the tree is
+ * accessed, and every time it is opened. Prefix compression is disabled
+ * with a custom comparison.
+ */
+void
+btree_set_cmp(struct btree *bt, bt_cmp_func cmp, void *arg)
+{
+	bt->cmp = cmp;
+	bt->cmp_arg = arg;
+}
+
 unsigned int
 btree_get_flags(struct btree *bt)
 {
@@ -3156,6 +3944,30 @@ btree_get_path(struct btree *bt)
 	return bt->path;
 }
 
+int
+btree_get_fd(struct btree *bt)
+{
+	return bt->fd;
+}
+
+int
+btree_get_ref(struct btree *bt)
+{
+	return bt->ref;
+}
+
+struct btree *
+btree_txn_get_bt(struct btree_txn *txn)
+{
+	return txn->bt;
+}
+
+struct btree *
+btree_cursor_get_bt(struct cursor *cursor)
+{
+	return cursor->bt;
+}
+
 const struct btree_stat *
 btree_stat(struct btree *bt)
 {
@@ -3169,7 +3981,7 @@ btree_stat(struct btree *bt)
 	bt->stat.depth = bt->meta.depth;
 	bt->stat.entries = bt->meta.entries;
 	bt->stat.psize = bt->head.psize;
-	bt->stat.created_at = bt->meta.created_at;
+	bt->stat.created_at = bt->head.created_at;
 
 	return &bt->stat;
 }
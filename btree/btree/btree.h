@@ -31,30 +31,36 @@ struct btval {
 };
 
 typedef int		 (*bt_cmp_func)(const struct btval *a,
-					const struct btval *b);
+					const struct btval *b, void *arg);
 typedef void		 (*bt_prefix_func)(const struct btval *a,
 					   const struct btval *b,
 					   struct btval *sep);
 
 #define BT_NOOVERWRITE	 1
+#define BT_APPEND	 2
 
 enum cursor_op {				/* cursor operations */
 	BT_CURSOR,				/* position at given key */
 	BT_CURSOR_EXACT,			/* position at key, or fail */
 	BT_FIRST,
 	BT_NEXT,
-	BT_LAST,				/* not implemented */
-	BT_PREV					/* not implemented */
+	BT_LAST,
+	BT_PREV,
+	BT_GET_CURRENT				/* item at cursor position */
 };
 
 /* return codes */
 #define BT_FAIL		-1
 #define BT_SUCCESS	 0
 
+/* returned by a compare function set with btree_set_cmp that failed */
+#define BT_CMP_FAIL	(-0x7fffffff - 1)
+
 /* btree flags */
 #define BT_NOSYNC		 0x02		/* don't fsync after commit */
 #define BT_RDONLY		 0x04		/* read only */
 #define BT_REVERSEKEY		 0x08		/* use reverse string keys */
+#define BT_EXCL			 0x10		/* fail if the file exists */
 
 struct btree_stat {
 	unsigned long long int	 hits;		/* cache hits */
@@ -71,13 +77,31 @@ struct btree_stat {
 	time_t			 created_at;
 };
 
+struct btree_verify {
+	unsigned int		 pages;		/* pages in the file */
+	unsigned int		 meta_pages;
+	unsigned int		 branch_pages;	/* pages of the last revision */
+	unsigned int		 leaf_pages;
+	unsigned int		 overflow_pages;
+	void			(*corrupt)(unsigned int pgno, void *arg);
+	void			*arg;		/* argument passed to corrupt */
+};
+
 struct btree		*btree_open_fd(int fd, unsigned int flags);
+struct btree		*btree_open_fd_psize(int fd, unsigned int flags,
+			    unsigned int psize);
 struct btree		*btree_open(const char *path, unsigned int flags,
 			    mode_t mode);
+struct btree		*btree_open_psize(const char *path, unsigned int flags,
+			    mode_t mode, unsigned int psize);
 void			 btree_close(struct btree *bt);
 const struct btree_stat	*btree_stat(struct btree *bt);
 
 struct btree_txn	*btree_txn_begin(struct btree *bt, int rdonly);
+struct btree_txn	*btree_txn_begin_at(struct btree *bt,
+			    unsigned int revision);
+struct btree_txn	*btree_txn_begin_like(struct btree *bt,
+			    struct btree_txn *other);
 int			 btree_txn_commit(struct btree_txn *txn);
 void			 btree_txn_abort(struct btree_txn *txn);
 
@@ -88,11 +112,18 @@ int			 btree_txn_put(struct btree *bt, struct btree_txn *txn,
 			    unsigned int flags);
 int			 btree_txn_del(struct btree *bt, struct btree_txn *txn,
 			    struct btval *key, struct btval *data);
+int			 btree_txn_clear(struct btree *bt,
+			    struct btree_txn *txn);
 
 void			 btree_set_cache_size(struct btree *bt,
 			    unsigned int cache_size);
+void			 btree_set_cmp(struct btree *bt, bt_cmp_func cmp,
+			    void *arg);
 unsigned int		 btree_get_flags(struct btree *bt);
 const char		*btree_get_path(struct btree *bt);
+int			 btree_get_fd(struct btree *bt);
+int			 btree_get_ref(struct btree *bt);
+struct btree		*btree_txn_get_bt(struct btree_txn *txn);
 
 struct cursor		*btree_txn_cursor_open(struct btree *bt,
 			    struct btree_txn *txn);
@@ -100,9 +131,12 @@ void			 btree_cursor_close(struct cursor *cursor);
 int			 btree_cursor_get(struct cursor *cursor,
 			    struct btval *key, struct btval *data,
 			    enum cursor_op op);
+int			 btree_cursor_del(struct cursor *cursor);
+struct btree		*btree_cursor_get_bt(struct cursor *cursor);
 
 int			 btree_sync(struct btree *bt);
 int			 btree_compact(struct btree *bt);
+int			 btree_verify(struct btree *bt, struct btree_verify *v);
 int			 btree_revert(struct btree *bt);
 
 int			 btree_cmp(struct btree *bt, const struct btval *a,
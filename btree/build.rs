@@ -1,5 +1,4 @@
 extern crate cc;
-extern crate pkg_config;
 
 use std::env;
 use std::path::PathBuf;
@@ -8,14 +7,15 @@ fn main() {
     let mut btree: PathBuf =
         PathBuf::from(&env::var("CARGO_MANIFEST_DIR").unwrap());
     btree.push("btree");
+    println!("cargo:rerun-if-changed={}", btree.display());
 
-    if !pkg_config::find_library("libbtree").is_ok() {
-        println!("cargo:rustc-link-lib=crypto");
-        let mut build = cc::Build::new();
-        build
-            .file(btree.join("btree.c"))
-            .opt_level(2)
-            .flag("-Wno-unused-parameter")
-            .compile("libbtree.a")
-    }
+    // The vendored source is always built, as the Rust side relies on
+    // functions and flags that a system libbtree lacks.
+    println!("cargo:rustc-link-lib=crypto");
+    let mut build = cc::Build::new();
+    build
+        .file(btree.join("btree.c"))
+        .opt_level(2)
+        .flag("-Wno-unused-parameter")
+        .compile("libbtree.a")
 }
@@ -4,11 +4,14 @@
 
 pub const BT_FAIL: i32 = -1;
 pub const BT_SUCCESS: i32 = 0;
+pub const BT_CMP_FAIL: i32 = i32::MIN;
 
 pub const BT_NOOVERWRITE: u32 = 1;
+pub const BT_APPEND: u32 = 2;
 pub const BT_NOSYNC: u32 = 2;
 pub const BT_RDONLY: u32 = 4;
 pub const BT_REVERSEKEY: u32 = 8;
+pub const BT_EXCL: u32 = 16;
 
 #[repr(C)] pub struct btree { _private: [u8; 0] }
 #[repr(C)] pub struct btree_txn { _private: [u8; 0] }
@@ -23,8 +26,8 @@ pub struct btval {
     pub mp: *mut mpage,
 }
 
-pub type bt_cmp_func = extern "C" fn(a: *const btval, b: *const btval)
-    -> ::libc::c_int;
+pub type bt_cmp_func = extern "C" fn(a: *const btval, b: *const btval,
+    arg: *mut ::libc::c_void) -> ::libc::c_int;
 
 pub type bt_prefix_func = extern "C" fn(a: *const btval, b: *const btval,
     sep: *mut btval);
@@ -34,6 +37,9 @@ pub const BT_CURSOR: cursor_op = 0;
 pub const BT_CURSOR_EXACT: cursor_op = 1;
 pub const BT_FIRST: cursor_op = 2;
 pub const BT_NEXT: cursor_op = 3;
+pub const BT_LAST: cursor_op = 4;
+pub const BT_PREV: cursor_op = 5;
+pub const BT_GET_CURRENT: cursor_op = 6;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -52,15 +58,38 @@ pub struct btree_stat {
     pub created_at: ::libc::time_t,
 }
 
+pub type verify_corrupt_func = extern "C" fn(pgno: ::libc::c_uint,
+    arg: *mut ::libc::c_void);
+
+#[repr(C)]
+pub struct btree_verify {
+    pub pages: ::libc::c_uint,
+    pub meta_pages: ::libc::c_uint,
+    pub branch_pages: ::libc::c_uint,
+    pub leaf_pages: ::libc::c_uint,
+    pub overflow_pages: ::libc::c_uint,
+    pub corrupt: Option<verify_corrupt_func>,
+    pub arg: *mut ::libc::c_void,
+}
+
 extern "C" {
     pub fn btree_open_fd(fd: ::libc::c_int, flags: ::libc::c_uint)
         -> *mut btree;
+    pub fn btree_open_fd_psize(fd: ::libc::c_int, flags: ::libc::c_uint,
+        psize: ::libc::c_uint) -> *mut btree;
     pub fn btree_open(path: *const ::libc::c_char, flags: ::libc::c_uint,
         mode: ::libc::mode_t) -> *mut btree;
+    pub fn btree_open_psize(path: *const ::libc::c_char,
+        flags: ::libc::c_uint, mode: ::libc::mode_t, psize: ::libc::c_uint)
+        -> *mut btree;
     pub fn btree_close(bt: *mut btree);
     pub fn btree_stat(bt: *mut btree) -> *const btree_stat;
     pub fn btree_txn_begin(bt: *mut btree, rdonly: ::libc::c_int)
         -> *mut btree_txn;
+    pub fn btree_txn_begin_at(bt: *mut btree, revision: ::libc::c_uint)
+        -> *mut btree_txn;
+    pub fn btree_txn_begin_like(bt: *mut btree, other: *mut btree_txn)
+        -> *mut btree_txn;
     pub fn btree_txn_commit(txn: *mut btree_txn) -> ::libc::c_int;
     pub fn btree_txn_abort(txn: *mut btree_txn);
     pub fn btree_txn_get(bt: *mut btree, txn: *mut btree_txn,
@@ -70,16 +99,27 @@ extern "C" {
         -> ::libc::c_int;
     pub fn btree_txn_del(bt: *mut btree, txn: *mut btree_txn,
         key: *mut btval, data: *mut btval) -> ::libc::c_int;
+    pub fn btree_txn_clear(bt: *mut btree, txn: *mut btree_txn)
+        -> ::libc::c_int;
     pub fn btree_set_cache_size(bt: *mut btree, cache_size: ::libc::c_uint);
+    pub fn btree_set_cmp(bt: *mut btree, cmp: bt_cmp_func,
+        arg: *mut ::libc::c_void);
     pub fn btree_get_flags(bt: *mut btree) -> ::libc::c_uint;
     pub fn btree_get_path(bt: *mut btree) -> *const ::libc::c_char;
+    pub fn btree_get_fd(bt: *mut btree) -> ::libc::c_int;
+    pub fn btree_get_ref(bt: *mut btree) -> ::libc::c_int;
+    pub fn btree_txn_get_bt(txn: *mut btree_txn) -> *mut btree;
     pub fn btree_txn_cursor_open(bt: *mut btree, txn: *mut btree_txn)
         -> *mut cursor;
     pub fn btree_cursor_close(cursor: *mut cursor);
     pub fn btree_cursor_get(cursor: *mut cursor, key: *mut btval,
         data: *mut btval, op: cursor_op) -> ::libc::c_int;
+    pub fn btree_cursor_del(cursor: *mut cursor) -> ::libc::c_int;
+    pub fn btree_cursor_get_bt(cursor: *mut cursor) -> *mut btree;
     pub fn btree_sync(bt: *mut btree) -> ::libc::c_int;
     pub fn btree_compact(bt: *mut btree) -> ::libc::c_int;
+    pub fn btree_verify(bt: *mut btree, v: *mut btree_verify)
+        -> ::libc::c_int;
     pub fn btree_revert(bt: *mut btree) -> ::libc::c_int;
     pub fn btree_cmp(bt: *mut btree, a: *const btval, b: *const btval)
         -> ::libc::c_int;
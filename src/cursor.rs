@@ -1,19 +1,31 @@
+use std::cmp::Ordering;
 use std::marker::PhantomData;
-use std::{fmt, result};
+use std::ops::{Bound, RangeBounds};
+use std::{fmt, panic, ptr, result};
 
-use database::Database;
+use database::{is_reserved, Database};
 use entry::Entry;
+use error::take_comparator_panic;
 use error::{clear_error, result_from_int, result_from_ptr};
-use error::{ErrorKind, Op, Result};
+use error::{Error, ErrorKind, Op, Result};
 use ffi;
+use libc;
 use transaction::Transaction;
+use transaction::{check_write_limit, RoTransaction, RwTransaction};
 
+/// Where `Cursor::get` moves the cursor to.
+///
+/// More positions may be added as btree gains cursor operations, so
+/// matches on a `Position` outside this crate need a wildcard arm.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum Position {
     Current,
     Exact,
     First,
     Next,
+    Last,
+    GetCurrent,
 }
 
 impl From<Position> for ffi::cursor_op {
@@ -23,10 +35,92 @@ impl From<Position> for ffi::cursor_op {
             Position::Exact => ffi::BT_CURSOR_EXACT,
             Position::First => ffi::BT_FIRST,
             Position::Next => ffi::BT_NEXT,
+            Position::Last => ffi::BT_LAST,
+            Position::GetCurrent => ffi::BT_GET_CURRENT,
         }
     }
 }
 
+/// Moves the cursor to `pos` and returns the item there, translating
+/// `ErrorKind::NotFound` into `None`.
+fn get_item<'txn, C>(
+    cursor: &C,
+    pos: Position,
+) -> Result<Option<(Vec<u8>, Vec<u8>)>>
+where
+    C: Cursor<'txn> + ?Sized,
+{
+    match cursor.get(None, None, pos) {
+        Ok((key, data)) => Ok(Some((key.unwrap_or_default(), data))),
+        Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Moves the cursor back to the item before the current one and returns
+/// it, translating `ErrorKind::NotFound` into `None`.
+fn prev_item<'txn, C>(cursor: &C) -> Result<Option<(Vec<u8>, Vec<u8>)>>
+where
+    C: Cursor<'txn> + ?Sized,
+{
+    let mut keyent = Entry::new();
+    let mut dataent = Entry::new();
+    let res = unsafe {
+        clear_error();
+        result_from_int(
+            ffi::btree_cursor_get(
+                cursor.cursor(),
+                keyent.inner_mut(),
+                dataent.inner_mut(),
+                ffi::BT_PREV,
+            ),
+            Op::CurPrev,
+        )
+    };
+    match res {
+        Ok(()) => Ok(Some((keyent.get_value(), dataent.get_value()))),
+        Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns the smallest key greater than every key starting with `prefix`,
+/// or `None` if there is no such key, i.e. the prefix is empty or made of
+/// 0xFF bytes only.
+pub(crate) fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let len = prefix.iter().rposition(|&b| b != 0xff)? + 1;
+    let mut succ = prefix[..len].to_vec();
+    succ[len - 1] += 1;
+    Some(succ)
+}
+
+/// Iterate over the items with keys starting with `prefix` like
+/// `Cursor::iter_prefix` does, including the reserved keys regardless of
+/// the database configuration.
+pub(crate) fn iter_prefix_raw<'txn, C>(
+    cursor: &mut C,
+    prefix: &[u8],
+) -> Iter<'txn>
+where
+    C: Cursor<'txn>,
+{
+    Iter::prefixed(cursor.cursor(), cursor.dbi(), false, prefix)
+}
+
+/// Compares two keys the way the database orders them.
+///
+/// A panic of the key comparator is resumed here, as there is no btree
+/// call to report it.
+fn cmp_keys(dbi: *mut ffi::btree, a: &[u8], b: &[u8]) -> Ordering {
+    let mut a = Entry::from_slice(&a);
+    let mut b = Entry::from_slice(&b);
+    let res = unsafe { ffi::btree_cmp(dbi, a.inner_mut(), b.inner_mut()) };
+    if let Some(payload) = take_comparator_panic() {
+        panic::resume_unwind(payload);
+    }
+    res.cmp(&0)
+}
+
 /// A database cursor.
 pub trait Cursor<'txn> {
     /// Returns a raw pointer to the underlying btree cursor.
@@ -35,6 +129,21 @@ pub trait Cursor<'txn> {
     /// lifetime of the cursor.
     fn cursor(&self) -> *mut ffi::cursor;
 
+    /// Returns the underlying btree database handle the cursor was opened
+    /// on.
+    fn dbi(&self) -> *mut ffi::btree {
+        unsafe { ffi::btree_cursor_get_bt(self.cursor()) }
+    }
+
+    /// Returns whether iterators created from this cursor skip reserved
+    /// keys, see `DatabaseBuilder::hide_reserved`.
+    ///
+    /// Defaults to `false`, so cursors implemented outside this crate see
+    /// every key.
+    fn hide_reserved(&self) -> bool {
+        false
+    }
+
     /// Retrieves a key/data pair from the cursor. Depending on the cursor
     /// position, the current key may be returned.
     fn get(
@@ -73,13 +182,244 @@ pub trait Cursor<'txn> {
     /// the database. For new cursors, the iterator will begin with
     /// the first item in the database.
     fn iter(&mut self) -> Iter<'txn> {
-        Iter::new(self.cursor(), Position::Next, Position::Next, None)
+        Iter::new(
+            self.cursor(),
+            Position::Next,
+            Position::Next,
+            None,
+            self.hide_reserved(),
+        )
+    }
+
+    /// Iterate over database items like `Cursor::iter` does, returning
+    /// errors as `Err` items rather than panicking on them.
+    ///
+    /// An error, such as a failure to read a page, ends the iteration.
+    fn iter_results(&mut self) -> IterResults<'txn> {
+        IterResults { iter: self.iter() }
+    }
+
+    /// Iterate over database items like `Cursor::iter` does, including
+    /// the reserved keys regardless of the database configuration.
+    fn iter_raw(&mut self) -> Iter<'txn> {
+        Iter::new(self.cursor(), Position::Next, Position::Next, None, false)
+    }
+
+    /// Returns the key at the current cursor position without reading its
+    /// value.
+    ///
+    /// Fails with `ErrorKind::NotFound` if the cursor isn't positioned on
+    /// an item, e.g. before the first move or past the last item.
+    fn current_key(&self) -> Result<Vec<u8>> {
+        let mut keyent = Entry::new();
+        unsafe {
+            clear_error();
+            result_from_int(
+                ffi::btree_cursor_get(
+                    self.cursor(),
+                    keyent.inner_mut(),
+                    ptr::null_mut(),
+                    ffi::BT_GET_CURRENT,
+                ),
+                Op::CurGet(Position::GetCurrent),
+            )?;
+        }
+        Ok(keyent.get_value())
+    }
+
+    /// Positions the cursor at the first item and returns it, or `None` if
+    /// the database is empty.
+    ///
+    /// Reserved keys are skipped if the database hides them.
+    fn first(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let mut item = get_item(self, Position::First)?;
+        while self.hide_reserved() {
+            match item {
+                Some((ref key, _)) if is_reserved(key) => (),
+                _ => break,
+            }
+            item = get_item(self, Position::Next)?;
+        }
+        Ok(item)
+    }
+
+    /// Positions the cursor at the last item and returns it, or `None` if
+    /// the database is empty.
+    ///
+    /// Reserved keys are stepped back over if the database hides them,
+    /// which matters when they don't sort first, e.g. with a custom
+    /// comparator or `REVERSE_KEY`.
+    fn last(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let mut item = get_item(self, Position::Last)?;
+        while self.hide_reserved() {
+            match item {
+                Some((ref key, _)) if is_reserved(key) => (),
+                _ => break,
+            }
+            item = prev_item(self)?;
+        }
+        Ok(item)
+    }
+
+    /// Returns the item at the current cursor position, or `None` if the
+    /// cursor isn't positioned on an item or is on a hidden reserved key.
+    fn current(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let item = get_item(self, Position::GetCurrent)?;
+        Ok(item.filter(|(key, _)| !self.hide_reserved() || !is_reserved(key)))
+    }
+
+    /// Positions the cursor at `key`, or at the first item after it if it
+    /// isn't present, and returns whether `key` itself was found.
+    ///
+    /// Past the last item, the cursor is left unpositioned and `false` is
+    /// returned.
+    fn seek<K>(&mut self, key: K) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        match self.get(Some(key), None, Position::Current) {
+            Ok((Some(found), _)) => Ok(found == key),
+            Ok((None, _)) => Ok(true),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Positions the cursor at the first item past all keys starting with
+    /// `prefix` and returns it, or `None` if there is no such item.
+    ///
+    /// Keys are assumed to be ordered bytewise, which doesn't hold for
+    /// databases opened with `REVERSE_KEY`, and may not with a custom
+    /// comparator, see `DatabaseBuilder::set_comparator`.
+    fn seek_past_prefix<K>(
+        &self,
+        prefix: K,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let succ = match prefix_successor(prefix.as_ref()) {
+            Some(succ) => succ,
+            None => return Ok(None),
+        };
+        match self.get(Some(&succ), None, Position::Current) {
+            Ok((key, data)) => Ok(Some((key.unwrap_or(succ), data))),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Iterate over database items with keys from `start` up to, but not
+    /// including, `end`.
+    ///
+    /// Keys are compared the way the database orders them, so nothing is
+    /// returned unless `start` sorts before `end`.
+    fn iter_range<K>(&mut self, start: K, end: K) -> Iter<'txn>
+    where
+        K: AsRef<[u8]>,
+    {
+        Iter::bounded(
+            self.cursor(),
+            self.dbi(),
+            self.hide_reserved(),
+            Bound::Included(start.as_ref()),
+            Bound::Excluded(end.as_ref()),
+        )
+    }
+
+    /// Iterate over database items with keys within `range`, e.g.
+    /// `cursor.range(&b"key1"[..]..=&b"key3"[..])`.
+    ///
+    /// Any of the standard ranges of byte slices may be used, as well as
+    /// a pair of `Bound`s.  Like with `Cursor::iter_range`, keys are
+    /// compared the way the database orders them.
+    fn range<'k, R>(&mut self, range: R) -> Iter<'txn>
+    where
+        R: RangeBounds<&'k [u8]>,
+    {
+        Iter::bounded(
+            self.cursor(),
+            self.dbi(),
+            self.hide_reserved(),
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+        )
+    }
+
+    /// Iterate over database items with keys starting with `prefix`.
+    ///
+    /// Keys are assumed to be ordered bytewise, which doesn't hold for
+    /// databases opened with `REVERSE_KEY`.  Custom comparators have to
+    /// keep keys sharing a prefix together, see
+    /// `DatabaseBuilder::set_comparator`.
+    fn iter_prefix<P>(&mut self, prefix: P) -> Iter<'txn>
+    where
+        P: AsRef<[u8]>,
+    {
+        Iter::prefixed(
+            self.cursor(),
+            self.dbi(),
+            self.hide_reserved(),
+            prefix.as_ref(),
+        )
+    }
+
+    /// Iterate over database keys like `Cursor::iter` does, without
+    /// reading the values.
+    fn iter_keys(&mut self) -> IterKeys<'txn> {
+        IterKeys { iter: self.iter() }
+    }
+
+    /// Iterate over database items like `Cursor::iter` does, borrowing
+    /// the keys and values instead of copying them.
+    ///
+    /// See `IterRef` for how the borrowed items may be used.  The iterator
+    /// borrows the cursor, so the cursor can't be moved while a slice is
+    /// held:
+    ///
+    /// ```compile_fail
+    /// # extern crate btreedb;
+    /// # use btreedb::{Cursor, Database, Transaction};
+    /// # fn main() {
+    /// let db = Database::temporary().unwrap();
+    /// let txn = db.begin_ro_txn().unwrap();
+    /// let mut cursor = txn.open_ro_cursor(&db).unwrap();
+    /// let mut iter = cursor.iter_ref();
+    /// let (key, _) = iter.next().unwrap();
+    /// cursor.seek("key").unwrap();
+    /// println!("{:?}", key);
+    /// # }
+    /// ```
+    fn iter_ref(&mut self) -> IterRef<'_> {
+        IterRef {
+            iter: Iter::new(
+                self.cursor(),
+                Position::Next,
+                Position::Next,
+                None,
+                self.hide_reserved(),
+            ),
+            item: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Iterate over database values like `Cursor::iter` does.
+    fn iter_values(&mut self) -> IterValues<'txn> {
+        IterValues { iter: self.iter() }
     }
 
     /// Iterate over database items starting from the beginning of
     /// the database.
     fn iter_start(&mut self) -> Iter<'txn> {
-        Iter::new(self.cursor(), Position::First, Position::Next, None)
+        Iter::new(
+            self.cursor(),
+            Position::First,
+            Position::Next,
+            None,
+            self.hide_reserved(),
+        )
     }
 
     /// Iterate over database items starting from the given key.
@@ -97,6 +437,7 @@ pub trait Cursor<'txn> {
             Position::Current,
             Position::Next,
             Some(key.as_ref().to_vec()),
+            self.hide_reserved(),
         )
     }
 }
@@ -104,6 +445,7 @@ pub trait Cursor<'txn> {
 /// A read-only cursor for navigating the items within a database.
 pub struct RoCursor<'txn> {
     cursor: *mut ffi::cursor,
+    hide_reserved: bool,
     _marker: PhantomData<fn() -> &'txn ()>,
 }
 
@@ -111,6 +453,10 @@ impl<'txn> Cursor<'txn> for RoCursor<'txn> {
     fn cursor(&self) -> *mut ffi::cursor {
         self.cursor
     }
+
+    fn hide_reserved(&self) -> bool {
+        self.hide_reserved
+    }
 }
 
 impl<'txn> fmt::Debug for RoCursor<'txn> {
@@ -135,23 +481,115 @@ impl<'txn> RoCursor<'txn> {
         let cursor = unsafe {
             clear_error();
             result_from_ptr::<ffi::cursor>(
-                ffi::btree_txn_cursor_open(db.dbi(), txn.txn()),
+                ffi::btree_txn_cursor_open(db.txn_handle(txn.txn()), txn.txn()),
                 Op::CurOpen,
             )?
         };
         Ok(RoCursor {
-            cursor: cursor,
+            cursor,
+            hide_reserved: db.hides_reserved(),
             _marker: PhantomData,
         })
     }
 }
 
+/// A read-write cursor for navigating and deleting the items within a
+/// database.
+pub struct RwCursor<'txn> {
+    cursor: *mut ffi::cursor,
+    hide_reserved: bool,
+    records_changes: bool,
+    // The transaction's count of bytes written and its limit.
+    written: &'txn mut usize,
+    max_bytes: Option<usize>,
+}
+
+impl<'txn> Cursor<'txn> for RwCursor<'txn> {
+    fn cursor(&self) -> *mut ffi::cursor {
+        self.cursor
+    }
+
+    fn hide_reserved(&self) -> bool {
+        self.hide_reserved
+    }
+}
+
+impl<'txn> fmt::Debug for RwCursor<'txn> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("RwCursor").finish()
+    }
+}
+
+impl<'txn> Drop for RwCursor<'txn> {
+    fn drop(&mut self) {
+        unsafe { ffi::btree_cursor_close(self.cursor) }
+    }
+}
+
+impl<'txn> RwCursor<'txn> {
+    /// Creates a new read-write cursor in the given database and
+    /// transaction. Prefer using `RwTransaction::open_rw_cursor`.
+    pub(crate) fn new<'db>(
+        txn: &'txn mut RwTransaction<'db>,
+        db: &Database,
+    ) -> Result<RwCursor<'txn>> {
+        let cursor = unsafe {
+            clear_error();
+            result_from_ptr::<ffi::cursor>(
+                ffi::btree_txn_cursor_open(db.txn_handle(txn.txn()), txn.txn()),
+                Op::CurOpen,
+            )?
+        };
+        let (written, max_bytes) = txn.write_budget();
+        Ok(RwCursor {
+            cursor,
+            hide_reserved: db.hides_reserved(),
+            records_changes: db.records_changes(),
+            written,
+            max_bytes,
+        })
+    }
+
+    /// Deletes the item at the current cursor position.
+    ///
+    /// The cursor is left on the following item, which is returned by the
+    /// next `Position::Next` move, so deleting while iterating with
+    /// `Cursor::iter` doesn't skip any items.  Deleting twice without
+    /// moving the cursor in between fails with `ErrorKind::NotFound`.
+    ///
+    /// The key length counts towards the limit of a transaction started
+    /// with `Database::begin_rw_txn_bounded`.  Fails with
+    /// `ErrorKind::InvalidArgument` if the database keeps a change feed,
+    /// see `DatabaseBuilder::change_feed`: recording the delete would write
+    /// to the tree under the cursor, so use `RwTransaction::del` instead.
+    pub fn del(&mut self) -> Result<()> {
+        if self.records_changes {
+            return Err(Error::from_errno(libc::EINVAL, Op::CurDel).context(
+                "Cursor deletes can't be recorded in the change feed"
+                    .to_string(),
+            ));
+        }
+        let len = self.current_key()?.len();
+        check_write_limit(*self.written, self.max_bytes, len, Op::CurDel)?;
+        unsafe {
+            clear_error();
+            result_from_int(ffi::btree_cursor_del(self.cursor), Op::CurDel)?;
+        }
+        *self.written += len;
+        Ok(())
+    }
+}
+
 /// An iterator over the values in an btree database.
 pub struct Iter<'txn> {
     cursor: *mut ffi::cursor,
     from: Option<Vec<u8>>,
     curr: Position,
     next: Position,
+    hide_reserved: bool,
+    until: Bound<Vec<u8>>,
+    dbi: *mut ffi::btree,
+    done: bool,
     _marker: PhantomData<fn(&'txn ())>,
 }
 
@@ -162,33 +600,112 @@ impl<'txn> Iter<'txn> {
         curr: Position,
         next: Position,
         from: Option<Vec<u8>>,
+        hide_reserved: bool,
     ) -> Iter<'t> {
         Iter {
             cursor,
             from,
             curr,
             next,
+            hide_reserved,
+            until: Bound::Unbounded,
+            dbi: ptr::null_mut(),
+            done: false,
             _marker: PhantomData,
         }
     }
-}
 
-impl<'txn> fmt::Debug for Iter<'txn> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
-        f.debug_struct("Iter").finish()
+    /// Creates a new iterator over the items with keys starting with
+    /// `prefix`.
+    fn prefixed<'t>(
+        cursor: *mut ffi::cursor,
+        dbi: *mut ffi::btree,
+        hide_reserved: bool,
+        prefix: &[u8],
+    ) -> Iter<'t> {
+        let succ = prefix_successor(prefix);
+        Iter::bounded(
+            cursor,
+            dbi,
+            hide_reserved,
+            Bound::Included(prefix),
+            succ.as_ref()
+                .map_or(Bound::Unbounded, |succ| Bound::Excluded(succ)),
+        )
     }
-}
 
-impl<'txn> Iterator for Iter<'txn> {
-    type Item = (Vec<u8>, Vec<u8>);
+    /// Creates a new iterator over the items with keys within the bounds.
+    ///
+    /// An empty key sorts before all others, so it's treated as no bound
+    /// at all when used as the start.
+    fn bounded<'t>(
+        cursor: *mut ffi::cursor,
+        dbi: *mut ffi::btree,
+        hide_reserved: bool,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Iter<'t> {
+        let mut done = false;
+        let (curr, from) = match start {
+            Bound::Included(key) | Bound::Excluded(key) if key.is_empty() => {
+                (Position::First, None)
+            }
+            Bound::Unbounded => (Position::First, None),
+            Bound::Included(key) => (Position::Current, Some(key.to_vec())),
+            Bound::Excluded(key) => {
+                // Seek right away to find out whether the cursor has to
+                // step over the start key.
+                let mut keyent = Entry::from_slice(&key);
+                let res = unsafe {
+                    clear_error();
+                    ffi::btree_cursor_get(
+                        cursor,
+                        keyent.inner_mut(),
+                        ptr::null_mut(),
+                        ffi::BT_CURSOR,
+                    )
+                };
+                if let Some(payload) = take_comparator_panic() {
+                    panic::resume_unwind(payload);
+                }
+                if res != ffi::BT_SUCCESS {
+                    done = true;
+                    (Position::Next, None)
+                } else if cmp_keys(dbi, keyent.as_slice(), key)
+                    == Ordering::Equal
+                {
+                    (Position::Next, None)
+                } else {
+                    (Position::GetCurrent, None)
+                }
+            }
+        };
+        let mut iter =
+            Iter::new(cursor, curr, Position::Next, from, hide_reserved);
+        iter.until = match end {
+            Bound::Included(key) => Bound::Included(key.to_vec()),
+            Bound::Excluded(key) => Bound::Excluded(key.to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        iter.dbi = dbi;
+        iter.done = done;
+        iter
+    }
 
-    fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+    /// Moves the cursor to the next item and returns it, or `None` at the
+    /// end of the database.  The value is only read if `data` is set.
+    fn step(&mut self, data: bool) -> Result<Option<(Entry, Entry)>> {
         let from = self.from.take();
         let mut keyent = match from {
             Some(ref key) => Entry::from_slice(key),
             None => Entry::new(),
         };
         let mut dataent = Entry::new();
+        let dataptr = if data {
+            dataent.inner_mut() as *mut ffi::btval
+        } else {
+            ptr::null_mut()
+        };
         let curr = self.curr.clone();
         self.curr = self.next.clone();
         unsafe {
@@ -197,7 +714,7 @@ impl<'txn> Iterator for Iter<'txn> {
                 ffi::btree_cursor_get(
                     self.cursor,
                     keyent.inner_mut(),
-                    dataent.inner_mut(),
+                    dataptr,
                     curr.clone().into(),
                 ),
                 Op::CurGet(curr),
@@ -209,82 +726,401 @@ impl<'txn> Iterator for Iter<'txn> {
                     // greater than all values in the
                     // database.
                     ErrorKind::InvalidArgument | ErrorKind::NotFound => {
-                        return None
+                        return Ok(None)
                     }
-                    _ => panic!(
-                        "btree_cursor_get returned an unexpected error: {}",
-                        err
-                    ),
+                    _ => return Err(err),
                 }
             }
-            Some((keyent.get_value(), dataent.get_value()))
         }
+        Ok(Some((keyent, dataent)))
     }
-}
 
-#[cfg(test)]
-mod test {
-    use tempdir::TempDir;
+    /// Returns the next key without reading its value.
+    pub(crate) fn try_next_key(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(self.try_next_entry(false)?.map(|(key, _)| key.get_value()))
+    }
 
-    use cursor::Position;
-    use database::Database;
-    use transaction::WriteFlags;
+    /// Counts the remaining items without copying them.
+    pub(crate) fn try_count(mut self) -> Result<u64> {
+        let mut count = 0;
+        while self.try_next_entry(false)?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
 
-    use super::*;
+    /// Returns the next item, panicking on unexpected errors.
+    fn next_entry(&mut self, data: bool) -> Option<(Entry, Entry)> {
+        match self.try_next_entry(data) {
+            Ok(entry) => entry,
+            Err(err) => {
+                panic!("btree_cursor_get returned an unexpected error: {}", err)
+            }
+        }
+    }
 
-    #[test]
-    fn test_get() {
-        let dir = TempDir::new("test").unwrap();
-        let dbpath = dir.path().join("test");
-        let db = Database::new().open(dbpath.as_path()).unwrap();
+    /// Returns the next item, skipping reserved keys if requested and
+    /// stopping at the end of the range.
+    fn try_next_entry(&mut self, data: bool) -> Result<Option<(Entry, Entry)>> {
+        while !self.done {
+            let (key, data) = match self.step(data)? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+            self.done = match self.until {
+                Bound::Included(ref until) => {
+                    cmp_keys(self.dbi, key.as_slice(), until)
+                        == Ordering::Greater
+                }
+                Bound::Excluded(ref until) => {
+                    cmp_keys(self.dbi, key.as_slice(), until) != Ordering::Less
+                }
+                Bound::Unbounded => false,
+            };
+            if self.done {
+                break;
+            }
+            if !self.hide_reserved || !is_reserved(key.as_slice()) {
+                return Ok(Some((key, data)));
+            }
+        }
+        Ok(None)
+    }
+}
 
-        let mut txn = db.begin_rw_txn().unwrap();
-        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
-        txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
-        txn.put(&db, b"key3", b"val3", WriteFlags::empty()).unwrap();
+impl<'txn> fmt::Debug for Iter<'txn> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("Iter").finish()
+    }
+}
 
-        let cursor = txn.open_ro_cursor(&db).unwrap();
-        assert_eq!(
-            (Some(b"key1".to_vec()), b"val1".to_vec()),
-            cursor.get(None, None, Position::First).unwrap()
-        );
-        assert_eq!(
-            (Some(b"key1".to_vec()), b"val1".to_vec()),
-            cursor
-                .get(Some(&b"key1"[..]), None, Position::Current)
-                .unwrap()
-        );
-        assert_eq!(
-            (Some(b"key1".to_vec()), b"val1".to_vec()),
-            cursor
-                .get(Some(&b"key1"[..]), None, Position::Exact)
-                .unwrap()
-        );
-        assert_eq!(
-            (Some(b"key2".to_vec()), b"val2".to_vec()),
-            cursor.get(None, None, Position::Next).unwrap()
-        );
+impl<'txn> Iterator for Iter<'txn> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.next_entry(true)
+            .map(|(key, data)| (key.get_value(), data.get_value()))
     }
+}
 
-    #[test]
-    fn test_iter() {
-        let dir = TempDir::new("test").unwrap();
-        let dbpath = dir.path().join("test");
-        let db = Database::new().open(dbpath.as_path()).unwrap();
+/// An iterator over the values in an btree database that reports errors
+/// instead of panicking.
+///
+/// An error ends the iteration: it is returned once, and `None` after it.
+pub struct IterResults<'txn> {
+    iter: Iter<'txn>,
+}
 
-        let items: Vec<(Vec<u8>, Vec<u8>)> = vec![
-            (b"key1".to_vec(), b"val1".to_vec()),
-            (b"key2".to_vec(), b"val2".to_vec()),
-            (b"key3".to_vec(), b"val3".to_vec()),
-            (b"key5".to_vec(), b"val5".to_vec()),
-        ];
+impl<'txn> fmt::Debug for IterResults<'txn> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("IterResults").finish()
+    }
+}
 
-        {
-            let mut txn = db.begin_rw_txn().unwrap();
-            for &(ref key, ref data) in &items {
-                txn.put(&db, key, data, WriteFlags::empty()).unwrap();
+impl<'txn> Iterator for IterResults<'txn> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>)>> {
+        match self.iter.try_next_entry(true) {
+            Ok(entry) => {
+                entry.map(|(key, data)| Ok((key.get_value(), data.get_value())))
+            }
+            Err(err) => {
+                self.iter.done = true;
+                Some(Err(err))
             }
-            txn.commit().unwrap();
+        }
+    }
+}
+
+/// An iterator over the values in an btree database that borrows the items
+/// from the database pages.
+///
+/// `Iter` copies every key and value into a pair of `Vec`s.  `IterRef`
+/// instead hands out slices pointing into the page cache where it can,
+/// sparing both copies.  btree still assembles some items into buffers of
+/// its own: values spilling into overflow pages, and keys on pages whose
+/// keys share a common prefix, which is stripped from the page and put
+/// back on every read.  Without a custom comparator that's most pages, so
+/// a scan usually allocates once per item rather than not at all.  See
+/// `benches/cursor.rs` for a comparison with `Iter`.  The slices are only
+/// valid until the following call to `next`, which is why this isn't an
+/// `Iterator`: use a `while let` loop or `for_each`.
+pub struct IterRef<'cursor> {
+    iter: Iter<'cursor>,
+    item: Option<(Entry, Entry)>,
+    // `Iter` alone would let the borrow of the cursor end early.
+    _marker: PhantomData<&'cursor mut ()>,
+}
+
+impl<'cursor> IterRef<'cursor> {
+    /// Returns the next item, or `None` at the end of the database.
+    ///
+    /// Panics on unexpected errors, like `Iter` does.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(&[u8], &[u8])> {
+        match self.try_next() {
+            Ok(item) => item,
+            Err(err) => {
+                panic!("btree_cursor_get returned an unexpected error: {}", err)
+            }
+        }
+    }
+
+    /// Returns the next item like `IterRef::next` does, returning errors
+    /// rather than panicking on them.
+    pub(crate) fn try_next(&mut self) -> Result<Option<(&[u8], &[u8])>> {
+        // Release the pages of the previous item first.
+        self.item = None;
+        self.item = self.iter.try_next_entry(true)?;
+        Ok(self
+            .item
+            .as_ref()
+            .map(|(key, data)| (key.as_slice(), data.as_slice())))
+    }
+
+    /// Calls the closure on each of the remaining items.
+    pub fn for_each<F>(mut self, mut f: F)
+    where
+        F: FnMut(&[u8], &[u8]),
+    {
+        while let Some((key, data)) = self.next() {
+            f(key, data);
+        }
+    }
+}
+
+impl<'cursor> fmt::Debug for IterRef<'cursor> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("IterRef").finish()
+    }
+}
+
+/// An iterator over the values in an btree database that owns its
+/// read-only transaction and cursor.
+pub struct OwnedIter<'db> {
+    iter: Iter<'db>,
+    cursor: *mut ffi::cursor,
+    _txn: RoTransaction<'db>,
+}
+
+impl<'db> OwnedIter<'db> {
+    /// Creates a new iterator over the items with keys within the bounds,
+    /// as seen by a new read-only transaction.
+    pub(crate) fn new(
+        db: &'db Database,
+        hide_reserved: bool,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Result<OwnedIter<'db>> {
+        let txn = db.begin_ro_txn()?;
+        let cursor = unsafe {
+            clear_error();
+            result_from_ptr::<ffi::cursor>(
+                ffi::btree_txn_cursor_open(db.txn_handle(txn.txn()), txn.txn()),
+                Op::CurOpen,
+            )?
+        };
+        let iter = Iter::bounded(
+            cursor,
+            db.txn_handle(txn.txn()),
+            hide_reserved,
+            start,
+            end,
+        );
+        Ok(OwnedIter {
+            iter,
+            cursor,
+            _txn: txn,
+        })
+    }
+}
+
+impl<'db> fmt::Debug for OwnedIter<'db> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("OwnedIter").finish()
+    }
+}
+
+impl<'db> Drop for OwnedIter<'db> {
+    fn drop(&mut self) {
+        // The cursor must go before the transaction does.
+        unsafe { ffi::btree_cursor_close(self.cursor) }
+    }
+}
+
+impl<'db> Iterator for OwnedIter<'db> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.iter.next()
+    }
+}
+
+/// An iterator over the keys in an btree database.
+///
+/// The values aren't read from the database at all.
+pub struct IterKeys<'txn> {
+    iter: Iter<'txn>,
+}
+
+impl<'txn> fmt::Debug for IterKeys<'txn> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("IterKeys").finish()
+    }
+}
+
+impl<'txn> Iterator for IterKeys<'txn> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.iter.next_entry(false).map(|(key, _)| key.get_value())
+    }
+}
+
+/// An iterator over the values in an btree database.
+///
+/// The keys aren't copied out of the database pages.
+pub struct IterValues<'txn> {
+    iter: Iter<'txn>,
+}
+
+impl<'txn> fmt::Debug for IterValues<'txn> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        f.debug_struct("IterValues").finish()
+    }
+}
+
+impl<'txn> Iterator for IterValues<'txn> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.iter.next_entry(true).map(|(_, data)| data.get_value())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    use tempdir::TempDir;
+
+    use cursor::Position;
+    use database::{Database, DatabaseFlags};
+    use transaction::WriteFlags;
+
+    use super::*;
+
+    #[test]
+    fn test_get() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key3", b"val3", WriteFlags::empty()).unwrap();
+
+        let cursor = txn.open_ro_cursor(&db).unwrap();
+        assert_eq!(
+            (Some(b"key1".to_vec()), b"val1".to_vec()),
+            cursor.get(None, None, Position::First).unwrap()
+        );
+        assert_eq!(
+            (Some(b"key1".to_vec()), b"val1".to_vec()),
+            cursor
+                .get(Some(&b"key1"[..]), None, Position::Current)
+                .unwrap()
+        );
+        assert_eq!(
+            (Some(b"key1".to_vec()), b"val1".to_vec()),
+            cursor
+                .get(Some(&b"key1"[..]), None, Position::Exact)
+                .unwrap()
+        );
+        assert_eq!(
+            (Some(b"key2".to_vec()), b"val2".to_vec()),
+            cursor.get(None, None, Position::Next).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_current_key() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key2", &vec![2; 10000], WriteFlags::empty())
+            .unwrap();
+        txn.put(&db, b"key3", b"val3", WriteFlags::empty()).unwrap();
+
+        let cursor = txn.open_ro_cursor(&db).unwrap();
+        assert!(cursor
+            .current_key()
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+        cursor.get(Some(b"key2"), None, Position::Exact).unwrap();
+        assert_eq!(b"key2".to_vec(), cursor.current_key().unwrap());
+        assert_eq!(
+            (Some(b"key3".to_vec()), b"val3".to_vec()),
+            cursor.get(None, None, Position::Next).unwrap()
+        );
+        assert_eq!(b"key3".to_vec(), cursor.current_key().unwrap());
+        assert!(cursor.get(None, None, Position::Next).is_err());
+        assert!(cursor
+            .current_key()
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_seek() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key3", b"val3", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key5", b"val5", WriteFlags::empty()).unwrap();
+
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        assert!(cursor.seek(b"key3").unwrap());
+        assert_eq!(b"key3".to_vec(), cursor.current_key().unwrap());
+        assert!(!cursor.seek(b"key4").unwrap());
+        assert_eq!(b"key5".to_vec(), cursor.current_key().unwrap());
+        assert!(!cursor.seek(b"key0").unwrap());
+        assert_eq!(b"key1".to_vec(), cursor.current_key().unwrap());
+        assert!(!cursor.seek(b"key6").unwrap());
+        assert!(cursor
+            .current_key()
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_iter() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let items: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (b"key1".to_vec(), b"val1".to_vec()),
+            (b"key2".to_vec(), b"val2".to_vec()),
+            (b"key3".to_vec(), b"val3".to_vec()),
+            (b"key5".to_vec(), b"val5".to_vec()),
+        ];
+
+        {
+            let mut txn = db.begin_rw_txn().unwrap();
+            for (key, data) in &items {
+                txn.put(&db, key, data, WriteFlags::empty()).unwrap();
+            }
+            txn.commit().unwrap();
         }
 
         let txn = db.begin_ro_txn().unwrap();
@@ -315,6 +1151,364 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_iter_range() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let items: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (b"key1".to_vec(), b"val1".to_vec()),
+            (b"key2".to_vec(), b"val2".to_vec()),
+            (b"key3".to_vec(), b"val3".to_vec()),
+            (b"key5".to_vec(), b"val5".to_vec()),
+        ];
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for (key, data) in &items {
+            txn.put(&db, key, data, WriteFlags::empty()).unwrap();
+        }
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        assert_eq!(
+            items[1..3].to_vec(),
+            cursor.iter_range(b"key2", b"key5").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            items[1..3].to_vec(),
+            cursor
+                .iter_range(&b"key11"[..], b"key4")
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            items.clone(),
+            cursor.iter_range(b"key0", b"key6").collect::<Vec<_>>()
+        );
+        assert_eq!(0, cursor.iter_range(b"key5", b"key2").count());
+        assert_eq!(0, cursor.iter_range(b"key2", b"key2").count());
+        assert_eq!(0, cursor.iter_range(&b"key31"[..], b"key4").count());
+        assert_eq!(0, cursor.iter_range(b"key6", b"key9").count());
+    }
+
+    #[test]
+    fn test_range() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let items: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (b"key1".to_vec(), b"val1".to_vec()),
+            (b"key2".to_vec(), b"val2".to_vec()),
+            (b"key3".to_vec(), b"val3".to_vec()),
+            (b"key5".to_vec(), b"val5".to_vec()),
+        ];
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for (key, data) in &items {
+            txn.put(&db, key, data, WriteFlags::empty()).unwrap();
+        }
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        let (key2, key3, key4, key5) =
+            (&b"key2"[..], &b"key3"[..], &b"key4"[..], &b"key5"[..]);
+        let mut range = |range| cursor.range(range).collect::<Vec<_>>();
+        assert_eq!(items, range((Bound::Unbounded, Bound::Unbounded)));
+        assert_eq!(
+            items[1..3],
+            range((Bound::Included(key2), Bound::Excluded(key5)))[..]
+        );
+        assert_eq!(
+            items[2..3],
+            range((Bound::Excluded(key2), Bound::Excluded(key5)))[..]
+        );
+        assert_eq!(
+            items[3..],
+            range((Bound::Excluded(key3), Bound::Unbounded))[..]
+        );
+        assert_eq!(
+            items[2..],
+            range((Bound::Excluded(&b"key21"[..]), Bound::Included(key5)))[..]
+        );
+
+        assert_eq!(items, cursor.range(..).collect::<Vec<_>>());
+        assert_eq!(items[1..], cursor.range(key2..).collect::<Vec<_>>()[..]);
+        assert_eq!(items[3..], cursor.range(key4..).collect::<Vec<_>>()[..]);
+        assert_eq!(
+            items[1..3],
+            cursor.range(key2..key5).collect::<Vec<_>>()[..]
+        );
+        assert_eq!(
+            items[1..],
+            cursor.range(key2..=key5).collect::<Vec<_>>()[..]
+        );
+        assert_eq!(
+            items[1..3],
+            cursor.range(key2..=key4).collect::<Vec<_>>()[..]
+        );
+        assert_eq!(items[..2], cursor.range(..key3).collect::<Vec<_>>()[..]);
+        assert_eq!(items[..3], cursor.range(..=key3).collect::<Vec<_>>()[..]);
+        assert_eq!(0, cursor.range(key3..key3).count());
+        assert_eq!(1, cursor.range(key3..=key3).count());
+        assert_eq!(0, cursor.range(key5..key2).count());
+        assert_eq!(0, cursor.range(&b"key6"[..]..).count());
+    }
+
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!(Some(b"/r0".to_vec()), prefix_successor(b"/r/"));
+        assert_eq!(Some(b"/s".to_vec()), prefix_successor(b"/r\xff\xff"));
+        assert_eq!(None, prefix_successor(b"\xff\xff"));
+        assert_eq!(None, prefix_successor(b""));
+    }
+
+    #[test]
+    fn test_seek_past_prefix() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for key in &[
+            &b"/a/1"[..],
+            b"/a/2",
+            b"/a\xff",
+            b"/a\xff\x01",
+            b"/b/1",
+            b"\xff\xff",
+        ] {
+            txn.put(&db, key, key, WriteFlags::empty()).unwrap();
+        }
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let cursor = txn.open_ro_cursor(&db).unwrap();
+        assert_eq!(
+            Some((b"/a\xff".to_vec(), b"/a\xff".to_vec())),
+            cursor.seek_past_prefix(b"/a/").unwrap()
+        );
+        assert_eq!(
+            Some((b"/b/1".to_vec(), b"/b/1".to_vec())),
+            cursor.seek_past_prefix(b"/a\xff").unwrap()
+        );
+        assert_eq!(
+            (Some(b"\xff\xff".to_vec()), b"\xff\xff".to_vec()),
+            cursor.get(None, None, Position::Next).unwrap()
+        );
+        assert_eq!(
+            Some((b"\xff\xff".to_vec(), b"\xff\xff".to_vec())),
+            cursor.seek_past_prefix(b"/b").unwrap()
+        );
+        assert_eq!(None, cursor.seek_past_prefix(b"\xff").unwrap());
+        assert_eq!(None, cursor.seek_past_prefix(b"\xff\xff").unwrap());
+    }
+
+    #[test]
+    fn test_iter_ref() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let items: Vec<(Vec<u8>, Vec<u8>)> = (0..1000u32)
+            .map(|i| {
+                let key = format!("key{:04}", i).into_bytes();
+                let val = if i % 100 == 0 {
+                    vec![i as u8; 10000]
+                } else {
+                    format!("val{}", i).into_bytes()
+                };
+                (key, val)
+            })
+            .collect();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for (key, val) in &items {
+            txn.put(&db, key, val, WriteFlags::empty()).unwrap();
+        }
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        let mut iter = cursor.iter_ref();
+        let mut expected = items.iter();
+        while let Some((key, val)) = iter.next() {
+            let (ekey, eval) = expected.next().unwrap();
+            assert_eq!(&ekey[..], key);
+            assert_eq!(&eval[..], val);
+        }
+        assert!(expected.next().is_none());
+        assert!(iter.next().is_none());
+
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        let mut count = 0;
+        cursor.iter_ref().for_each(|key, val| {
+            assert_eq!(&items[count].0[..], key);
+            assert_eq!(&items[count].1[..], val);
+            count += 1;
+        });
+        assert_eq!(items.len(), count);
+    }
+
+    #[test]
+    fn test_iter_keys_values() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let items: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (b"key1".to_vec(), b"val1".to_vec()),
+            (b"key2".to_vec(), vec![2; 10000]),
+            (b"key3".to_vec(), b"val3".to_vec()),
+        ];
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for (key, data) in items.iter().rev() {
+            txn.put(&db, key, data, WriteFlags::empty()).unwrap();
+        }
+        txn.commit().unwrap();
+        db.set_meta(b"meta").unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        assert_eq!(
+            items.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>(),
+            cursor.iter_keys().collect::<Vec<_>>()
+        );
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        assert_eq!(
+            items
+                .iter()
+                .map(|(_, data)| data.clone())
+                .collect::<Vec<_>>(),
+            cursor.iter_values().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_first_last_current() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+        db.set_meta(b"meta").unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..1000 {
+            let key = format!("key{:04}", i);
+            txn.put(&db, &key, &key, WriteFlags::empty()).unwrap();
+        }
+
+        let cursor = txn.open_ro_cursor(&db).unwrap();
+        assert_eq!(None, cursor.current().unwrap());
+        let item = (b"key0999".to_vec(), b"key0999".to_vec());
+        assert_eq!(Some(item.clone()), cursor.last().unwrap());
+        assert_eq!(Some(item), cursor.current().unwrap());
+        assert!(cursor.get(None, None, Position::Next).is_err());
+        assert_eq!(None, cursor.current().unwrap());
+
+        let item = (b"key0000".to_vec(), b"key0000".to_vec());
+        assert_eq!(Some(item.clone()), cursor.first().unwrap());
+        assert_eq!(Some(item), cursor.current().unwrap());
+    }
+
+    #[test]
+    fn test_last_reserved_sorting_last() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new()
+            .set_flags(DatabaseFlags::REVERSE_KEY)
+            .open(dbpath.as_path())
+            .unwrap();
+
+        // Keys are compared from their last byte, so the reserved keys sort
+        // after any other key, and span several pages at the end of the
+        // tree.
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..3000 {
+            let key = format!("{:04}", i);
+            txn.put(&db, &key, &key, WriteFlags::empty()).unwrap();
+            let mut key = format!("\0reserved{:04}", i).into_bytes();
+            key.push(0xff);
+            txn.put(&db, &key, b"", WriteFlags::empty()).unwrap();
+        }
+        txn.commit().unwrap();
+        db.set_meta(b"meta").unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let cursor = txn.open_ro_cursor(&db).unwrap();
+        let item = (b"2999".to_vec(), b"2999".to_vec());
+        assert_eq!(Some(item.clone()), cursor.last().unwrap());
+        assert_eq!(Some(item), cursor.current().unwrap());
+    }
+
+    #[test]
+    fn test_first_last_current_empty_database() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        let cursor = txn.open_ro_cursor(&db).unwrap();
+        assert_eq!(None, cursor.first().unwrap());
+        assert_eq!(None, cursor.last().unwrap());
+        assert_eq!(None, cursor.current().unwrap());
+        drop(cursor);
+        drop(txn);
+
+        // Only reserved keys, which are hidden by default.
+        db.set_meta(b"meta").unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        let cursor = txn.open_ro_cursor(&db).unwrap();
+        assert_eq!(None, cursor.first().unwrap());
+        assert_eq!(None, cursor.last().unwrap());
+        assert_eq!(None, cursor.current().unwrap());
+    }
+
+    #[test]
+    fn test_iter_results() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..1000 {
+            let key = format!("key{:04}", i);
+            txn.put(&db, &key, &[0; 100], WriteFlags::empty()).unwrap();
+        }
+        txn.commit().unwrap();
+        db.close();
+
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        let mut iter = cursor.iter_results();
+        assert_eq!(b"key0000".to_vec(), iter.next().unwrap().unwrap().0);
+
+        // Make further page reads fail by swapping the database file
+        // descriptor for the read end of a pipe, which can't be read at an
+        // offset.
+        let meta = fs::metadata(&dbpath).unwrap();
+        let fd = (0..1024)
+            .find(|&fd| unsafe {
+                let mut st: libc::stat = ::std::mem::zeroed();
+                libc::fstat(fd, &mut st) == 0
+                    && st.st_dev == meta.dev()
+                    && st.st_ino == meta.ino()
+            })
+            .unwrap();
+        let mut fds = [0; 2];
+        assert_eq!(0, unsafe { libc::pipe(fds.as_mut_ptr()) });
+        assert_eq!(fd, unsafe { libc::dup2(fds[0], fd) });
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+
+        let err = iter.find(|item| item.is_err()).unwrap().unwrap_err();
+        assert_eq!(ErrorKind::Other, err.kind());
+        assert!(iter.next().is_none());
+    }
+
     #[test]
     fn test_iter_empty_database() {
         let dir = TempDir::new("test").unwrap();
@@ -328,6 +1522,50 @@ mod test {
         assert_eq!(0, cursor.iter_from(b"foo").count());
     }
 
+    #[test]
+    fn test_iter_hide_reserved() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+
+        {
+            let db = Database::new().open(dbpath.as_path()).unwrap();
+            db.set_meta(b"meta").unwrap();
+            let mut txn = db.begin_rw_txn().unwrap();
+            txn.put(&db, b"\0int", b"val0", WriteFlags::empty())
+                .unwrap();
+            txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+            txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
+            txn.commit().unwrap();
+
+            let txn = db.begin_ro_txn().unwrap();
+            let mut cursor = txn.open_ro_cursor(&db).unwrap();
+            let keys =
+                |iter: Iter| iter.map(|(key, _)| key).collect::<Vec<_>>();
+            assert_eq!(
+                vec![
+                    b"\0int".to_vec(),
+                    b"\0meta\0".to_vec(),
+                    b"key1".to_vec(),
+                    b"key2".to_vec(),
+                ],
+                keys(cursor.iter_raw())
+            );
+            assert_eq!(
+                vec![b"key1".to_vec(), b"key2".to_vec()],
+                keys(cursor.iter_start())
+            );
+            assert_eq!(vec![b"key2".to_vec()], keys(cursor.iter_from(b"key2")));
+        }
+
+        let db = Database::new()
+            .hide_reserved(false)
+            .open(dbpath.as_path())
+            .unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        assert_eq!(4, cursor.iter_start().count());
+    }
+
     fn match_below<P>(pat: P) -> impl Fn(&[u8]) -> bool
     where
         P: AsRef<[u8]>,
@@ -403,4 +1641,126 @@ mod test {
 
         assert_eq!(nkeys.len(), entries);
     }
+
+    #[test]
+    fn test_rw_cursor_del() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+        let entries = 1000;
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..entries {
+            let val = format!("{}", i);
+            txn.put(&db, &format!("/a/{}", i), &val, WriteFlags::empty())
+                .unwrap();
+            txn.put(&db, &format!("/b/{}", i), &val, WriteFlags::empty())
+                .unwrap();
+        }
+        txn.commit().unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        {
+            let mut cursor = txn.open_rw_cursor(&db).unwrap();
+            assert!(cursor.del().unwrap_err().same_kind(ErrorKind::NotFound));
+            let mut seen = 0;
+            for (key, _) in cursor.iter_start() {
+                seen += 1;
+                if key.starts_with(b"/a/") {
+                    cursor.del().unwrap();
+                }
+            }
+            assert_eq!(seen, 2 * entries);
+            assert!(cursor.del().unwrap_err().same_kind(ErrorKind::NotFound));
+        }
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        let keys: Vec<_> = cursor.iter_start().map(|(key, _)| key).collect();
+        assert_eq!(keys.len(), entries);
+        assert!(keys.iter().all(|key| key.starts_with(b"/b/")));
+    }
+
+    #[test]
+    fn test_rw_cursor_del_bounded() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+        db.update(|txn| {
+            txn.put(&db, b"key1", b"val1", WriteFlags::empty())?;
+            txn.put(&db, b"key2", b"val2", WriteFlags::empty())
+        })
+        .unwrap();
+
+        let mut txn = db.begin_rw_txn_bounded(6).unwrap();
+        {
+            let mut cursor = txn.open_rw_cursor(&db).unwrap();
+            cursor.get(None, None, Position::First).unwrap();
+            cursor.del().unwrap();
+            assert!(cursor
+                .del()
+                .unwrap_err()
+                .same_kind(ErrorKind::FileTooLarge));
+        }
+        assert_eq!(4, txn.bytes_written());
+        txn.commit().unwrap();
+        assert_eq!(1, db.len().unwrap());
+    }
+
+    #[test]
+    fn test_rw_cursor_del_change_feed() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().change_feed(true).open(&dbpath).unwrap();
+        db.update(|txn| txn.put(&db, b"key1", b"val1", WriteFlags::empty()))
+            .unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        {
+            let mut cursor = txn.open_rw_cursor(&db).unwrap();
+            cursor.get(None, None, Position::First).unwrap();
+            assert!(cursor
+                .del()
+                .unwrap_err()
+                .same_kind(ErrorKind::InvalidArgument));
+        }
+        txn.commit().unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert_eq!(1, db.poll_changes(0, 10).unwrap().0.len());
+    }
+
+    #[test]
+    fn test_iter_prefix() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+        let entries = 1000;
+
+        {
+            let mut txn = db.begin_rw_txn().unwrap();
+            for i in 0..entries {
+                for prefix in &["/q/", "/r/", "/s/"] {
+                    let key = format!("{}{}", prefix, i);
+                    let val = format!("{}", i);
+                    txn.put(&db, &key, &val, WriteFlags::empty()).unwrap();
+                }
+            }
+            txn.put(&db, b"\xff", b"", WriteFlags::empty()).unwrap();
+            txn.put(&db, b"\xff\xff", b"", WriteFlags::empty()).unwrap();
+            txn.commit().unwrap();
+        }
+
+        let txn = db.begin_ro_txn().unwrap();
+        let mut cur = txn.open_ro_cursor(&db).unwrap();
+        let keys: Vec<_> = cur.iter_prefix("/r/").map(|(key, _)| key).collect();
+        assert_eq!(keys.len(), entries);
+        assert!(keys.iter().all(|key| key.starts_with(b"/r/")));
+
+        assert_eq!(0, cur.iter_prefix("/t/").count());
+        assert_eq!(2, cur.iter_prefix(b"\xff").count());
+        assert_eq!(1, cur.iter_prefix(b"\xff\xff").count());
+        assert_eq!(3 * entries + 2, cur.iter_prefix(b"").count());
+    }
 }
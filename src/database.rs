@@ -1,31 +1,307 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::env;
 use std::ffi::CString;
 #[cfg(windows)]
 use std::ffi::OsStr;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::mem::ManuallyDrop;
+use std::ops::Bound;
 #[cfg(unix)]
 use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use errno;
 use libc;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
+use cursor::{iter_prefix_raw, prefix_successor, Cursor, OwnedIter};
+use error::set_comparator_panic;
 use error::{clear_error, result_from_int, result_from_ptr};
-use error::{Error, Op, Result};
+use error::{Error, ErrorKind, Op, Result};
 use ffi;
-use transaction::{RoTransaction, RwTransaction};
+use keys;
+use stat::{
+    CompactOutcome, CompactionEstimate, LengthDistribution, Stat, VerifyReport,
+};
+use transaction::{RoTransaction, RwTransaction, Snapshot};
+use transaction::{Transaction, WriteFlags};
+use wal;
+
+/// Key under which `Database::set_meta` stores the metadata blob.
+///
+/// Like every key starting with a NUL byte it is reserved, and hidden from
+/// cursor iteration unless `DatabaseBuilder::hide_reserved` is turned off.
+pub const META_KEY: &[u8] = b"\0meta\0";
+
+/// Prefix of the keys under which `RwTransaction::append` stores values,
+/// followed by their big-endian 64-bit sequence number.
+pub(crate) const LOG_PREFIX: &[u8] = b"\0log\0";
+
+/// Key holding the last sequence number allocated by
+/// `RwTransaction::append`.
+pub(crate) const LOG_SEQ_KEY: &[u8] = b"\0seq\0log\0";
+
+/// Prefix of the keys under which the change feed read by
+/// `Database::poll_changes` is kept, followed by their big-endian 64-bit
+/// sequence number.
+pub(crate) const FEED_PREFIX: &[u8] = b"\0feed\0";
+
+/// Key holding the last sequence number allocated to the change feed.
+pub(crate) const FEED_SEQ_KEY: &[u8] = b"\0seq\0feed\0";
+
+/// Number of items `Database::bulk_load_unique` stores per transaction.
+const BULK_LOAD_BATCH: usize = 1000;
+
+/// Number of items `Database::scan_budgeted` reads between checks of the
+/// clock.
+const SCAN_CLOCK_INTERVAL: usize = 64;
+
+/// Size of a page header, mirroring `PAGEHDRSZ` in btree.
+const PAGE_HEADER_SIZE: usize = 12;
+
+/// Size of a leaf node header, mirroring `NODESIZE` in btree.
+const NODE_HEADER_SIZE: usize = 7;
+
+/// Size of a node offset in the page index, mirroring `indx_t` in btree.
+const NODE_INDEX_SIZE: usize = 2;
+
+/// Size of a page number, mirroring `pgno_t` in btree.
+const PGNO_SIZE: usize = 4;
+
+/// Smallest page size btree supports, mirroring `MINPAGESIZE` in btree.
+const MIN_PAGE_SIZE: u32 = 512;
+
+/// Largest page size btree supports, mirroring `MAXPAGESIZE` in btree.
+const MAX_PAGE_SIZE: u32 = 32768;
+
+/// Minimum number of nodes per page, mirroring `BT_MINKEYS` in btree.
+/// Leaf nodes that would take up more than the usable part of a page
+/// divided by this put their value on overflow pages.
+const MIN_KEYS: usize = 4;
+
+/// Longest key btree accepts on any page size, mirroring `MAXKEYSIZE` in
+/// btree.
+const MAX_KEY_SIZE: usize = 2047;
+
+/// A key ordering set with `DatabaseBuilder::set_comparator`.  The closure
+/// is boxed twice, so that btree can be handed a thin pointer to it.
+type Comparator = Arc<Box<dyn Fn(&[u8], &[u8]) -> Ordering + Send + Sync>>;
+
+/// Collects the page numbers reported by `btree_verify` into the vector
+/// passed as `arg`.
+extern "C" fn push_corrupt_page(pgno: libc::c_uint, arg: *mut libc::c_void) {
+    let pages = unsafe { &mut *(arg as *mut Vec<u32>) };
+    pages.push(pgno);
+}
+
+/// Calls the comparator passed to `btree_set_cmp` as `arg`.
+extern "C" fn compare_keys(
+    a: *const ffi::btval,
+    b: *const ffi::btval,
+    arg: *mut libc::c_void,
+) -> libc::c_int {
+    unsafe fn as_slice<'a>(val: *const ffi::btval) -> &'a [u8] {
+        if (*val).size == 0 {
+            return &[];
+        }
+        slice::from_raw_parts((*val).data as *const u8, (*val).size)
+    }
+
+    let cmp = unsafe {
+        &*(arg as *const Box<dyn Fn(&[u8], &[u8]) -> Ordering + Send + Sync>)
+    };
+    // Unwinding into btree is undefined behavior, so the panic is kept for
+    // the btree call to report, and btree fails the write transaction.
+    let res = panic::catch_unwind(AssertUnwindSafe(|| unsafe {
+        cmp(as_slice(a), as_slice(b))
+    }));
+    match res {
+        Ok(Ordering::Less) => -1,
+        Ok(Ordering::Equal) => 0,
+        Ok(Ordering::Greater) => 1,
+        Err(payload) => {
+            set_comparator_panic(payload);
+            ffi::BT_CMP_FAIL
+        }
+    }
+}
+
+/// Returns the operation reported by errors of `Database::backup`.
+fn backup_op() -> Op {
+    Op::Other("Failed to back up the database".to_string())
+}
+
+/// Converts an I/O error met while backing up the database.
+fn backup_error(err: io::Error) -> Error {
+    Error::from_io(err, backup_op())
+}
+
+/// Converts an I/O error met while dumping the database.
+fn dump_error(err: io::Error) -> Error {
+    Error::from_io(err, Op::Other("Failed to dump the database".to_string()))
+}
+
+fn load_op() -> Op {
+    Op::Other("Failed to load the dump".to_string())
+}
+
+fn truncated_dump() -> Error {
+    Error::from_errno(libc::EINVAL, load_op())
+        .context("Dump ends in the middle of an item".to_string())
+}
+
+/// Reads a field of a dump written by `Database::dump`.  Returns `None` if
+/// the input ends right before the field, which is only valid for a key.
+fn read_dump_field<R: Read>(input: &mut R) -> Result<Option<Vec<u8>>> {
+    let load_error = |err: io::Error| Error::from_io(err, load_op());
+    let mut len = Vec::with_capacity(4);
+    input.take(4).read_to_end(&mut len).map_err(load_error)?;
+    if len.is_empty() {
+        return Ok(None);
+    }
+    let mut field = Vec::new();
+    if len.len() == 4 {
+        let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]);
+        input
+            .take(u64::from(len))
+            .read_to_end(&mut field)
+            .map_err(load_error)?;
+        if field.len() == len as usize {
+            return Ok(Some(field));
+        }
+    }
+    Err(truncated_dump())
+}
+
+/// Returns whether the key belongs to the reserved keyspace used for
+/// internal bookkeeping, i.e. whether it starts with a NUL byte.
+pub(crate) fn is_reserved(key: &[u8]) -> bool {
+    key.first() == Some(&0)
+}
+
+/// Kind of change recorded in the change feed, see
+/// `DatabaseBuilder::change_feed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    /// An item was stored.
+    Put,
+    /// An item was deleted.
+    Del,
+}
+
+/// Encodes a change feed record: the operation, the big-endian 32-bit
+/// length of the key, the key and the stored value.
+pub(crate) fn encode_change(op: ChangeOp, key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(1 + 4 + key.len() + data.len());
+    record.push(match op {
+        ChangeOp::Put => wal::OP_PUT,
+        ChangeOp::Del => wal::OP_DEL,
+    });
+    record.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    record.extend_from_slice(key);
+    record.extend_from_slice(data);
+    record
+}
+
+/// Decodes a record encoded with `encode_change`.
+fn decode_change(record: &[u8]) -> Option<(ChangeOp, Vec<u8>, Vec<u8>)> {
+    let op = match record.first() {
+        Some(&wal::OP_PUT) => ChangeOp::Put,
+        Some(&wal::OP_DEL) => ChangeOp::Del,
+        _ => return None,
+    };
+    if record.len() < 5 {
+        return None;
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&record[1..5]);
+    let len = u32::from_be_bytes(len) as usize;
+    if record.len() - 5 < len {
+        return None;
+    }
+    let (key, data) = record[5..].split_at(len);
+    Some((op, key.to_vec(), data.to_vec()))
+}
 
 /// An append-only database.
 pub struct Database {
-    handle: *mut ffi::btree,
+    handle: AtomicPtr<ffi::btree>,
+    // Handles replaced by `DatabaseBuilder::auto_reopen_on_stale`, closed
+    // once no transaction can still refer to them.
+    retired: Mutex<Vec<*mut ffi::btree>>,
+    // Number of calls between loading `handle` and being done with it, see
+    // `Database::with_handle`.  Retired handles are left open while any
+    // are in progress, as they may have loaded one just before it retired.
+    pins: AtomicUsize,
     builder: DatabaseBuilder,
+    // Removed when the database is dropped, see `Database::temporary`.
+    temp_path: Option<PathBuf>,
+    // Holds the lock taken by `DatabaseBuilder::lock_exclusive` until the
+    // database is dropped.
+    lock: Option<fs::File>,
 }
 
 impl Database {
     /// Creates a new builder for specifying options for opening a database.
+    #[allow(clippy::new_ret_no_self)]
     pub fn new() -> DatabaseBuilder {
         DatabaseBuilder {
             flags: DatabaseFlags::empty(),
             cache_size: 0,
             path: PathBuf::new(),
             mode: 0o644,
+            hide_reserved: true,
+            page_size: 0,
+            exclusive: false,
+            comparator: None,
+            key_len: None,
+            warm_keys: Vec::new(),
+            change_feed: false,
+            auto_reopen: false,
+            lock: false,
+        }
+    }
+
+    /// Creates a new database in the temporary directory of the system,
+    /// whose file is removed when it is dropped.
+    ///
+    /// Handles opened with `Database::try_clone` keep working after the
+    /// file is removed, but don't remove it themselves.
+    pub fn temporary() -> Result<Database> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let path = env::temp_dir().join(format!(
+                "btreedb-{}-{}",
+                process::id(),
+                COUNTER.fetch_add(1, AtomicOrdering::Relaxed)
+            ));
+            // A file may be left behind by an earlier process with the
+            // same id.
+            match Database::new().create_exclusive().open(&path) {
+                Ok(mut db) => {
+                    db.temp_path = Some(path);
+                    return Ok(db);
+                }
+                Err(ref err) if err.kind() == ErrorKind::AlreadyExists => (),
+                Err(err) => return Err(err),
+            }
         }
     }
 
@@ -33,273 +309,2502 @@ impl Database {
     ///
     /// The caller **must** ensure that the handle is not used after the
     /// lifetime of the database, or after the database has been closed.
+    /// With `DatabaseBuilder::auto_reopen_on_stale`, a handle replaced by a
+    /// reopen is closed once its last transaction ends, after which it
+    /// must not be used either.
     pub fn dbi(&self) -> *mut ffi::btree {
-        self.handle
+        self.handle()
     }
 
-    /// Create a read-only transaction for use with the database.
-    pub fn begin_ro_txn<'db>(&'db self) -> Result<RoTransaction<'db>> {
-        RoTransaction::new(self)
+    fn handle(&self) -> *mut ffi::btree {
+        self.handle.load(AtomicOrdering::Acquire)
     }
 
-    /// Create a read-write transaction for use with the database.
-    /// This method will error out while there are any other read-write
-    /// transactions open on the database.
-    pub fn begin_rw_txn<'db>(&'db self) -> Result<RwTransaction<'db>> {
-        RwTransaction::new(self)
+    /// Calls `f` with the current handle, which is kept open until `f`
+    /// returns even if another thread replaces it meanwhile.
+    pub(crate) fn with_handle<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(*mut ffi::btree) -> T,
+    {
+        self.pins.fetch_add(1, AtomicOrdering::SeqCst);
+        let res = f(self.handle());
+        self.pins.fetch_sub(1, AtomicOrdering::SeqCst);
+        res
     }
 
-    pub fn reopen(&mut self) -> Result<()> {
-        clear_error();
-        unsafe {
-            ffi::btree_close(self.handle);
+    /// Returns the handle the transaction was started on, if it belongs
+    /// to this database, or the current handle otherwise so that btree
+    /// rejects the mismatch.
+    pub(crate) fn txn_handle(
+        &self,
+        txn: *mut ffi::btree_txn,
+    ) -> *mut ffi::btree {
+        let bt = unsafe { ffi::btree_txn_get_bt(txn) };
+        if bt == self.handle() || self.retired.lock().unwrap().contains(&bt) {
+            bt
+        } else {
+            self.handle()
         }
-        let mut builder = self.builder.clone();
-        let mut newdb = builder.reopen()?;
-        self.handle = newdb.handle;
-        newdb.handle = ::std::ptr::null_mut();
-        Ok(())
     }
 
-    /// Revert last transaction.
-    pub fn revert(&self) -> Result<()> {
-        clear_error();
-        unsafe { result_from_int(ffi::btree_revert(self.handle), Op::Revert) }
+    /// Closes the retired handles that no transaction or cursor refers to
+    /// any more.  Called whenever a transaction ends, on whichever handle,
+    /// as the last transaction on a retired handle may have ended while
+    /// another thread was loading the handle.
+    pub(crate) fn release_retired(&self) {
+        let mut retired = self.retired.lock().unwrap();
+        if retired.is_empty() {
+            return;
+        }
+        if self.pins.load(AtomicOrdering::SeqCst) != 0 {
+            return;
+        }
+        // The database holds one reference, and every transaction and
+        // cursor another.
+        retired.retain(|&handle| unsafe {
+            if ffi::btree_get_ref(handle) > 1 {
+                return true;
+            }
+            ffi::btree_close(handle);
+            false
+        });
     }
 
-    /// Compact the database.
-    ///
-    /// When compaction of a database file is complete, a special marker
-    /// is appended to the database file that requires the calling program
-    /// to reopen the file and perform new requests against the compacted
-    /// database.
-    pub fn compact(&self) -> Result<()> {
-        clear_error();
-        unsafe { result_from_int(ffi::btree_compact(self.handle), Op::Compact) }
+    /// Returns the path the database was opened with.
+    pub fn path(&self) -> &Path {
+        &self.builder.path
     }
 
-    /// Flush data buffers to disk.
-    ///
-    /// Data is always written to disk when `Transaction::commit` is called,
-    /// but the operating system may keep it buffered. btree always flushes
-    /// the OS buffers upon commit as well, unless the database was opened
-    /// with `NO_SYNC`.
-    pub fn sync(&self) -> Result<()> {
-        clear_error();
-        unsafe { result_from_int(ffi::btree_sync(self.handle), Op::Sync) }
+    /// Returns the options the database handle is operating with.
+    pub fn flags(&self) -> DatabaseFlags {
+        let flags = self.with_handle(|bt| unsafe { ffi::btree_get_flags(bt) });
+        DatabaseFlags::from_bits_truncate(flags)
     }
 
-    /// Closes the database handle. Normally unnecessary.
-    ///
-    /// Databases should only be closed by a single thread, and only if no
-    /// other threads are going to reference the database handle or one of
-    /// its cursors any further. Do not close a handle if an existing
-    /// transaction has modified its database. Doing so can cause database
-    /// corruption or other errors.
-    pub fn close(self) {
-        clear_error();
-        unsafe {
-            ffi::btree_close(self.handle);
+    /// Returns whether cursor iterators should skip reserved keys.
+    pub(crate) fn hides_reserved(&self) -> bool {
+        self.builder.hide_reserved
+    }
+
+    /// Returns whether writes are recorded in the change feed.
+    pub(crate) fn records_changes(&self) -> bool {
+        self.builder.change_feed
+    }
+
+    /// Checks the key against the length set with
+    /// `DatabaseBuilder::fixed_key_len`, and keys to put against the
+    /// longest key the page size allows.  Lookups aren't limited, so that
+    /// longer keys written before the limit existed stay reachable.
+    pub(crate) fn check_key_len(&self, key: &[u8], op: Op) -> Result<()> {
+        if op == Op::TxnPut && key.len() > self.max_key_len() {
+            return Err(Error::from_errno(libc::EINVAL, op).context(format!(
+                "Key length {} exceeds the maximum of {} for {} byte pages",
+                key.len(),
+                self.max_key_len(),
+                self.page_size()
+            )));
+        }
+        match self.builder.key_len {
+            Some(len) if key.len() != len && !is_reserved(key) => {
+                Err(Error::from_errno(libc::EINVAL, op).context(format!(
+                    "Key length {} differs from the fixed length {}",
+                    key.len(),
+                    len
+                )))
+            }
+            _ => Ok(()),
         }
     }
-}
 
-impl Drop for Database {
-    fn drop(&mut self) {
-        clear_error();
-        unsafe {
-            ffi::btree_close(self.handle);
+    /// Read the keys set with `DatabaseBuilder::warm_keys` to bring their
+    /// pages into the cache.  Failures are ignored, as warming is only an
+    /// optimization.
+    fn warm(&self) {
+        if self.builder.warm_keys.is_empty() {
+            return;
+        }
+        if let Ok(txn) = self.begin_ro_txn() {
+            for key in &self.builder.warm_keys {
+                let _ = txn.get_ref(self, key);
+            }
         }
     }
-}
 
-unsafe impl Sync for Database {}
-unsafe impl Send for Database {}
+    /// Returns the number of pages read from disk by this handle so far.
+    pub(crate) fn page_reads(&self) -> u64 {
+        self.with_handle(|bt| unsafe { (*ffi::btree_stat(bt)).reads })
+    }
 
-bitflags! {
-    #[doc="Database options."]
-    #[derive(Default)]
-    pub struct DatabaseFlags: libc::c_uint {
-        #[doc="Don't flush system buffers to disk when committing"]
-        #[doc="a transaction."]
-        #[doc="\n\n"]
-        #[doc="This optimization means a system crash can corrupt"]
-        #[doc="the database or lose the last transactions if buffers"]
-        #[doc="are not yet flushed to disk. The risk is governed by"]
-        #[doc="how often the system flushes dirty buffers to disk"]
-        #[doc="and how often `Database::sync` is called."]
-        const NO_SYNC = ffi::BT_NOSYNC;
+    /// Returns the page size of the database.
+    fn page_size(&self) -> usize {
+        self.with_handle(|bt| unsafe { (*ffi::btree_stat(bt)).psize as usize })
+    }
 
-        #[doc="Open the database in read-only mode. No write"]
-        #[doc="operations will be allowed."]
-        const READ_ONLY = ffi::BT_RDONLY;
+    /// Returns the largest size of a node, including its index slot, that
+    /// still fits `MIN_KEYS` nodes to a page.
+    fn node_max(&self) -> usize {
+        (self.page_size() - PAGE_HEADER_SIZE) / MIN_KEYS
+    }
 
-        #[doc="Keys are strings to be compared in reverse order,"]
-        #[doc="from the end of the strings to the beginning."]
-        #[doc="\n\n"]
-        #[doc="By default, keys are treated as strings and compared"]
-        #[doc="from the beginning to the end."]
-        const REVERSE_KEY = ffi::BT_REVERSEKEY;
+    /// Returns the length of the longest key the database accepts.
+    ///
+    /// Every page has to hold a few keys, so this depends on the page
+    /// size, see `DatabaseBuilder::set_page_size`.  It's 1008 bytes for
+    /// the default 4096 byte pages.
+    pub fn max_key_len(&self) -> usize {
+        let max =
+            self.node_max() - NODE_HEADER_SIZE - PGNO_SIZE - NODE_INDEX_SIZE;
+        max.min(MAX_KEY_SIZE)
     }
-}
 
-#[derive(Clone)]
-pub struct DatabaseBuilder {
-    flags: DatabaseFlags,
-    cache_size: u32,
-    path: PathBuf,
-    mode: u32,
-}
+    /// Returns the approximate number of bytes an item would take up on
+    /// disk once written.
+    ///
+    /// This accounts for the node header and its index slot in the leaf
+    /// page, and for values large enough to go on overflow pages, for the
+    /// whole pages they fill.  Branch pages and the copies of pages made
+    /// by every commit are not included.
+    pub fn estimate_stored_size(&self, key: &[u8], value: &[u8]) -> usize {
+        let psize = self.page_size();
+        let size = NODE_HEADER_SIZE + NODE_INDEX_SIZE + key.len();
+        if size + value.len() <= self.node_max() {
+            return size + value.len();
+        }
+        let usable = psize - PAGE_HEADER_SIZE;
+        let pages = value.len().div_ceil(usable);
+        size + PGNO_SIZE + pages * psize
+    }
 
-impl DatabaseBuilder {
-    /// Open an existing database or create a new one.
+    /// Retrieve database statistics.
+    pub fn stat(&self) -> Result<Stat> {
+        clear_error();
+        self.with_handle(|bt| unsafe {
+            let stat = result_from_ptr::<ffi::btree_stat>(
+                ffi::btree_stat(bt) as *mut ffi::btree_stat,
+                Op::Stat,
+            )?;
+            Ok(Stat::from(&*stat))
+        })
+    }
+
+    /// Tally the lengths of all keys and values in a single pass.
     ///
-    /// On UNIX, the database files will be opened with 644 permissions.
-    pub fn open(&mut self, path: &Path) -> Result<Database> {
-        self.open_with_permissions(path, 0o644)
+    /// Reserved keys are left out unless `DatabaseBuilder::hide_reserved`
+    /// is turned off.
+    pub fn length_distribution(&self) -> Result<LengthDistribution> {
+        let txn = self.begin_ro_txn()?;
+        let mut cursor = txn.open_ro_cursor(self)?;
+        let mut dist = LengthDistribution::default();
+        for (key, data) in cursor.iter_start() {
+            dist.keys.add(key.len());
+            dist.values.add(data.len());
+        }
+        Ok(dist)
     }
 
-    /// Open an existing database or create a new one with the provided
-    /// UNIX permissions.
-    pub fn open_with_permissions(
-        &mut self,
-        path: &Path,
-        mode: u32,
-    ) -> Result<Database> {
-        self.path = path.to_path_buf();
-        self.mode = mode;
+    /// Returns the size of the database file in bytes.
+    ///
+    /// The file is looked up by its path, so this fails for databases
+    /// opened with `DatabaseBuilder::open_fd`.
+    pub fn file_size(&self) -> Result<u64> {
+        fs::metadata(self.path())
+            .map(|meta| meta.len())
+            .map_err(|err| {
+                let errno = err.raw_os_error().unwrap_or(libc::EINVAL);
+                Error::from_errno(errno, Op::Stat)
+            })
+    }
 
-        let path = match CString::new(path.as_os_str().as_bytes()) {
-            Ok(path) => path,
-            Err(..) => {
-                let mut errstr = String::from("Invalid path");
-                if let Some(path) = path.to_str() {
-                    errstr += &format!(": {}", path);
-                }
-                return Err(Error::other(errstr));
-            }
-        };
+    /// Writes a copy of the database to a new file at `dest`.
+    ///
+    /// The database stays usable while the copy is made.  Committed pages
+    /// are never overwritten, so copying the file up to its current size
+    /// yields the state of the last commit, as of the call, that had been
+    /// fully written.  Fails with `ErrorKind::AlreadyExists` if `dest`
+    /// exists, see `Database::backup_overwrite`.  The file is looked up by
+    /// its path, so this fails for databases opened with
+    /// `DatabaseBuilder::open_fd`.
+    pub fn backup(&self, dest: &Path) -> Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dest)
+            .map_err(backup_error)?;
+        let res = self.copy_to(&mut file);
+        if res.is_err() {
+            drop(file);
+            let _ = fs::remove_file(dest);
+        }
+        res.map(|_| ())
+    }
 
-        clear_error();
-        let dbi = unsafe {
-            result_from_ptr::<ffi::btree>(
-                ffi::btree_open(
-                    path.as_ptr(),
-                    self.flags.bits(),
-                    mode as libc::mode_t,
+    /// Writes a copy of the database to `dest` like `Database::backup`,
+    /// replacing the file if it exists.
+    pub fn backup_overwrite(&self, dest: &Path) -> Result<()> {
+        let mut file = fs::File::create(dest).map_err(backup_error)?;
+        self.copy_to(&mut file).map(|_| ())
+    }
+
+    /// Writes a copy of the database to an open file descriptor, such as
+    /// a pipe or a socket, and returns the number of bytes written.
+    ///
+    /// The copy is made like `Database::backup` does it, and can be opened
+    /// as a database of its own.  The descriptor is left open.
+    #[cfg(unix)]
+    pub fn copy_to_fd(&self, fd: RawFd) -> Result<u64> {
+        let mut out = ManuallyDrop::new(unsafe { fs::File::from_raw_fd(fd) });
+        self.copy_to(&mut *out)
+    }
+
+    /// Writes the database file up to its last whole page to `out`, and
+    /// returns the number of bytes written.
+    fn copy_to<W: Write>(&self, out: &mut W) -> Result<u64> {
+        // Beginning a transaction fails if the file was replaced by
+        // compaction, rather than copying a dead file.
+        let _txn = self.begin_ro_txn()?;
+        let mut file = fs::File::open(self.path()).map_err(backup_error)?;
+        let psize = self.page_size() as u64;
+        let len = file.metadata().map_err(backup_error)?.len() / psize * psize;
+        let copied =
+            io::copy(&mut (&mut file).take(len), out).map_err(backup_error)?;
+        out.flush().map_err(backup_error)?;
+        if copied != len {
+            return Err(Error::from_errno(libc::EIO, backup_op()).context(
+                format!(
+                    "Database file shrank to {} bytes while copying",
+                    copied
                 ),
-                Op::Open,
-            )?
-        };
+            ));
+        }
+        Ok(copied)
+    }
 
-        if self.cache_size > 0 {
-            unsafe {
-                ffi::btree_set_cache_size(dbi, self.cache_size);
+    /// Writes every item of the database to `out`, and returns the number
+    /// of items written.
+    ///
+    /// Each item is written as the big-endian 32-bit length of its key,
+    /// the key, the big-endian 32-bit length of its value and the value.
+    /// The items are read with a cursor under a single read-only
+    /// transaction, so the dump holds the state of the last commit while
+    /// only one item at a time is kept in memory.  Reserved keys are
+    /// included regardless of the database configuration.
+    pub fn dump<W: Write>(&self, mut out: W) -> Result<u64> {
+        let txn = self.begin_ro_txn()?;
+        let mut cursor = txn.open_ro_cursor(self)?;
+        let mut count = 0;
+        for (key, val) in cursor.iter_raw() {
+            for field in [&key, &val].iter() {
+                out.write_all(&(field.len() as u32).to_be_bytes())
+                    .and_then(|_| out.write_all(field))
+                    .map_err(dump_error)?;
             }
+            count += 1;
         }
+        out.flush().map_err(dump_error)?;
+        Ok(count)
+    }
 
-        Ok(Database {
-            handle: dbi,
-            builder: self.clone(),
+    /// Stores the items of a dump written by `Database::dump`, and returns
+    /// the number of items stored.
+    ///
+    /// All items are stored in a single transaction, replacing the values
+    /// of keys already present.  If the dump is cut short or can't be read,
+    /// the transaction is rolled back and none of its items are stored.
+    pub fn load<R: Read>(&self, mut input: R) -> Result<u64> {
+        self.update(|txn| {
+            let mut count = 0;
+            while let Some(key) = read_dump_field(&mut input)? {
+                let val =
+                    read_dump_field(&mut input)?.ok_or_else(truncated_dump)?;
+                txn.put(self, &key, &val, WriteFlags::empty())?;
+                count += 1;
+            }
+            Ok(count)
         })
     }
 
-    pub(crate) fn reopen(&mut self) -> Result<Database> {
-        let pathbuf = self.path.clone();
-        let mode = self.mode;
-        self.open_with_permissions(pathbuf.as_path(), mode)
+    /// Estimate the space `Database::compact` would reclaim, without
+    /// compacting.
+    ///
+    /// The compacted file is expected to hold a header page, the pages of
+    /// the latest revision of the tree and a meta page.
+    pub fn compaction_estimate(&self) -> Result<CompactionEstimate> {
+        let stat = self.stat()?;
+        let file_size = self.file_size()?;
+        let pages = 2
+            + u64::from(stat.branch_pages)
+            + u64::from(stat.leaf_pages)
+            + u64::from(stat.overflow_pages);
+        let compacted_size = pages * u64::from(stat.psize);
+        Ok(CompactionEstimate {
+            file_size,
+            compacted_size,
+            reclaimable: file_size.saturating_sub(compacted_size),
+        })
     }
 
-    /// Sets the provided options for the database.
-    pub fn set_flags(&mut self, flags: DatabaseFlags) -> &mut Self {
-        self.flags = flags;
-        self
+    /// Returns the number of items in the database.
+    ///
+    /// The count is tracked by btree itself, so this doesn't scan the
+    /// database.  It reflects the last committed revision seen by this
+    /// handle, with one exception: while a write transaction is open on
+    /// this handle, its pending puts and deletes are already counted.
+    ///
+    /// btree counts every key, so the count includes reserved keys such as
+    /// `META_KEY` even when `DatabaseBuilder::hide_reserved` hides them
+    /// from iteration.  Use `Transaction::count_range` to count the items
+    /// iteration returns.
+    pub fn len(&self) -> Result<u64> {
+        Ok(self.stat()?.entries)
     }
 
-    /// Set the cache size for database entries.
+    /// Returns whether the database holds no items, see `Database::len`.
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Create a read-only transaction for use with the database.
     ///
-    /// The size is specified in number of pages.  Note that more than the
-    /// configured number of pages may exist in the cache, as dirty pages
-    /// and pages referenced by cursors are excluded from cache expiration.
-    /// Cached pages are expired in a least recently used (LRU) order.
-    pub fn set_cache_size(&mut self, cache_size: u32) -> &mut Self {
-        self.cache_size = cache_size;
-        self
+    /// btree keeps no table of readers, so the number of read-only
+    /// transactions isn't limited, and a process that crashes while
+    /// reading leaves nothing behind to clean up.
+    pub fn begin_ro_txn<'db>(&'db self) -> Result<RoTransaction<'db>> {
+        self.retry_stale(|| RoTransaction::new(self))
+    }
+
+    /// Takes a snapshot of the database, which all reads through it see
+    /// until it is dropped.
+    pub fn snapshot<'db>(&'db self) -> Result<Snapshot<'db>> {
+        self.begin_ro_txn().map(Snapshot::new)
+    }
+
+    /// Create a read-only transaction that sees the database as it was
+    /// right after the given revision was committed.
+    ///
+    /// Revisions are numbered as returned by
+    /// `RwTransaction::commit_with_revision`.  Committed pages are never
+    /// overwritten, so every revision since the database was created or
+    /// last compacted can be read; older ones, and ones that don't exist
+    /// yet, fail with `ErrorKind::NotFound`.  Finding a revision takes a
+    /// scan of the file backwards from its end, which grows with the
+    /// number of pages written since.
+    pub fn begin_ro_txn_at<'db>(
+        &'db self,
+        revision: u64,
+    ) -> Result<RoTransaction<'db>> {
+        RoTransaction::new_at(self, revision)
+    }
+
+    /// Create a read-write transaction for use with the database.
+    /// This method will error out while there are any other read-write
+    /// transactions open on the database, and with
+    /// `ErrorKind::PermissionDenied` if the database was opened with
+    /// `DatabaseFlags::READ_ONLY`.
+    pub fn begin_rw_txn<'db>(&'db self) -> Result<RwTransaction<'db>> {
+        self.retry_stale(|| RwTransaction::new(self))
+    }
+
+    /// Create a read-write transaction that may write at most `max_bytes`
+    /// bytes, counting key and value lengths.
+    ///
+    /// A put or delete that would exceed the limit fails with
+    /// `ErrorKind::FileTooLarge` without being applied, and the
+    /// transaction remains usable.
+    pub fn begin_rw_txn_bounded<'db>(
+        &'db self,
+        max_bytes: usize,
+    ) -> Result<RwTransaction<'db>> {
+        let mut txn = self.begin_rw_txn()?;
+        txn.set_max_bytes(max_bytes);
+        Ok(txn)
+    }
+
+    /// Run the closure inside a read-write transaction.
+    ///
+    /// The transaction is committed if the closure returns `Ok`, and its
+    /// result is passed on to the caller.  Otherwise the transaction is
+    /// aborted and the error is returned.
+    pub fn update<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut RwTransaction) -> Result<T>,
+    {
+        let mut txn = self.begin_rw_txn()?;
+        let res = f(&mut txn)?;
+        txn.commit()?;
+        Ok(res)
+    }
+
+    /// Run the closure inside a read-only transaction.
+    ///
+    /// The transaction is aborted once the closure returns, regardless of
+    /// its result.  Since `view` only borrows the database immutably, the
+    /// closure may capture the same `&Database` and pass it on to the
+    /// transaction methods, e.g. `db.view(|txn| txn.get(&db, b"key"))`.
+    pub fn view<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&RoTransaction) -> Result<T>,
+    {
+        let txn = self.begin_ro_txn()?;
+        f(&txn)
+    }
+
+    /// Iterate over the items after `start_token` up to and including
+    /// `end_token`.
+    ///
+    /// Tokens are keys, typically the last one seen by a previous
+    /// iteration, and need not be present in the database.  A missing
+    /// token leaves that end of the range open.  The iterator reads from a
+    /// read-only transaction started by this call.
+    pub fn iter_tokens<'db>(
+        &'db self,
+        start_token: Option<Vec<u8>>,
+        end_token: Option<Vec<u8>>,
+    ) -> Result<OwnedIter<'db>> {
+        OwnedIter::new(
+            self,
+            self.hides_reserved(),
+            start_token
+                .as_ref()
+                .map_or(Bound::Unbounded, |key| Bound::Excluded(key)),
+            end_token
+                .as_ref()
+                .map_or(Bound::Unbounded, |key| Bound::Included(key)),
+        )
+    }
+
+    /// Collect items following the `after` key for as long as the time
+    /// budget allows.
+    ///
+    /// Returns the items along with a token to pass as `after` to resume
+    /// the scan, or `None` if the scan reached the end of the database.
+    /// The clock is checked every few dozen items, so the budget may be
+    /// overrun slightly, and each call makes progress even if the budget
+    /// is zero.
+    #[allow(clippy::type_complexity)]
+    pub fn scan_budgeted(
+        &self,
+        after: Option<Vec<u8>>,
+        budget: Duration,
+    ) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, Option<Vec<u8>>)> {
+        let deadline = Instant::now() + budget;
+        let mut items = Vec::new();
+        for item in self.iter_tokens(after, None)? {
+            items.push(item);
+            if items.len() % SCAN_CLOCK_INTERVAL == 0
+                && Instant::now() >= deadline
+            {
+                let token = items.last().map(|item| item.0.clone());
+                return Ok((items, token));
+            }
+        }
+        Ok((items, None))
+    }
+
+    /// Returns an iterator over the values stored with
+    /// `RwTransaction::append`, in the order they were appended, along
+    /// with their sequence numbers.
+    ///
+    /// The iterator sees the database as of the call, through a read-only
+    /// transaction of its own.
+    pub fn iter_log<'db>(
+        &'db self,
+    ) -> Result<impl Iterator<Item = (u64, Vec<u8>)> + 'db> {
+        let end = prefix_successor(LOG_PREFIX);
+        let end = end
+            .as_ref()
+            .map_or(Bound::Unbounded, |key| Bound::Excluded(&key[..]));
+        let iter =
+            OwnedIter::new(self, false, Bound::Included(LOG_PREFIX), end)?;
+        Ok(iter.filter_map(|(key, data)| {
+            let mut seq = [0u8; 8];
+            if key.len() != LOG_PREFIX.len() + seq.len() {
+                return None;
+            }
+            seq.copy_from_slice(&key[LOG_PREFIX.len()..]);
+            Some((u64::from_be_bytes(seq), data))
+        }))
+    }
+
+    /// Returns up to `limit` changes recorded after the sequence number
+    /// `since_seq`, in the order they were made, along with the sequence
+    /// number to poll from next.
+    ///
+    /// Each change is returned with its sequence number, and deletions
+    /// come with an empty value.  Nothing is recorded unless the database
+    /// was opened with `DatabaseBuilder::change_feed`.  Sequence numbers
+    /// start at 1, so poll from 0 to read the feed from its beginning.
+    #[allow(clippy::type_complexity)]
+    pub fn poll_changes(
+        &self,
+        since_seq: u64,
+        limit: usize,
+    ) -> Result<(Vec<(u64, ChangeOp, Vec<u8>, Vec<u8>)>, u64)> {
+        let mut start = FEED_PREFIX.to_vec();
+        match since_seq.checked_add(1) {
+            Some(seq) => start.extend_from_slice(&seq.to_be_bytes()),
+            None => return Ok((Vec::new(), since_seq)),
+        }
+        let end = prefix_successor(FEED_PREFIX);
+        let end = end
+            .as_ref()
+            .map_or(Bound::Unbounded, |key| Bound::Excluded(&key[..]));
+        let iter = OwnedIter::new(self, false, Bound::Included(&start), end)?;
+
+        let mut changes = Vec::new();
+        let mut high = since_seq;
+        for (key, record) in iter.take(limit) {
+            let seq = keys::decode_u64_be(&key[FEED_PREFIX.len()..]);
+            let change = decode_change(&record);
+            match (seq, change) {
+                (Some(seq), Some((op, key, data))) => {
+                    changes.push((seq, op, key, data));
+                    high = seq;
+                }
+                _ => {
+                    return Err(Error::other(
+                        "Invalid change feed record".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok((changes, high))
+    }
+
+    /// Store an application-defined metadata blob in the database.
+    ///
+    /// The blob is kept under the reserved `META_KEY` and replaces any
+    /// previously stored one.  It is written in its own transaction.
+    pub fn set_meta(&self, blob: &[u8]) -> Result<()> {
+        self.update(|txn| txn.put(self, &META_KEY, &blob, WriteFlags::empty()))
+    }
+
+    /// Retrieve the metadata blob stored with `Database::set_meta`.
+    pub fn get_meta(&self) -> Result<Option<Vec<u8>>> {
+        self.view(|txn| txn.get_opt(self, &META_KEY))
+    }
+
+    /// Store items with keys that are expected not to be in the database
+    /// yet, and return the number of items stored.
+    ///
+    /// Items are stored in batches, each in its own transaction.  Loading
+    /// stops at the first key that is already present, either in the
+    /// database or earlier in `items`, with an `ErrorKind::AlreadyExists`
+    /// error naming the key.  The batch containing the duplicate is rolled
+    /// back, while the batches before it stay committed.
+    pub fn bulk_load_unique<I, K, D>(&self, items: I) -> Result<u64>
+    where
+        I: IntoIterator<Item = (K, D)>,
+        K: AsRef<[u8]>,
+        D: AsRef<[u8]>,
+    {
+        let mut loaded = 0;
+        let mut items = items.into_iter().peekable();
+        while items.peek().is_some() {
+            let mut txn = self.begin_rw_txn()?;
+            let mut batch = 0;
+            for (key, data) in items.by_ref().take(BULK_LOAD_BATCH) {
+                let flags = WriteFlags::NO_OVERWRITE;
+                if let Err(err) = txn.put(self, &key, &data, flags) {
+                    if err.kind() == ErrorKind::AlreadyExists {
+                        return Err(err.context(format!(
+                            "Duplicate key in bulk load: {}",
+                            key.as_ref().escape_ascii()
+                        )));
+                    }
+                    return Err(err);
+                }
+                batch += 1;
+            }
+            txn.commit()?;
+            loaded += batch;
+        }
+        Ok(loaded)
+    }
+
+    /// Find the first index missing from a sequence of keys made of the
+    /// prefix and a big-endian 64-bit index, see `keys::encode_u64_be`.
+    ///
+    /// The sequence starts at its lowest index.  Returns `None` if the
+    /// indices following it are contiguous, or if there are none at all.
+    ///
+    /// Keys are read regardless of `DatabaseBuilder::hide_reserved`, so
+    /// indices below 2^56 are found under an empty prefix too.  The
+    /// reserved keys the database keeps for itself are skipped.
+    pub fn first_gap(&self, prefix: &[u8]) -> Result<Option<u64>> {
+        let txn = self.begin_ro_txn()?;
+        let mut cursor = txn.open_ro_cursor(self)?;
+        let mut prev: Option<u64> = None;
+        for (key, _) in iter_prefix_raw(&mut cursor, prefix) {
+            let index = match keys::decode_u64_be(&key[prefix.len()..]) {
+                Some(index) => index,
+                // None of the reserved keys of the database is as long as
+                // an index.
+                None if is_reserved(&key) => continue,
+                None => {
+                    return Err(Error::other(format!(
+                        "Key {} doesn't end with a 64-bit index",
+                        key.escape_ascii()
+                    )))
+                }
+            };
+            match prev {
+                Some(prev) if index != prev + 1 => return Ok(Some(prev + 1)),
+                _ => prev = Some(index),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Run the closure and report which keys it changed.
+    ///
+    /// The database is scanned before and after running the closure, and
+    /// a hash of every value is recorded.  Keys that were added, removed,
+    /// or whose value hashes differ are returned in key order.  Both scans
+    /// read the whole database, and changes made by other writers while
+    /// the closure runs are reported as well.
+    pub fn keys_changed_between<F>(&self, f: F) -> Result<Vec<Vec<u8>>>
+    where
+        F: FnOnce(&Database) -> Result<()>,
+    {
+        let before = self.content_hashes()?;
+        f(self)?;
+        let after = self.content_hashes()?;
+
+        let mut changed: Vec<Vec<u8>> = before
+            .iter()
+            .filter(|&(key, hash)| after.get(key) != Some(hash))
+            .map(|(key, _)| key.clone())
+            .collect();
+        changed.extend(
+            after
+                .into_iter()
+                .filter(|(key, _)| !before.contains_key(key))
+                .map(|(key, _)| key),
+        );
+        changed.sort();
+        Ok(changed)
+    }
+
+    /// Returns a hash of every value in the database, keyed by its key.
+    fn content_hashes(&self) -> Result<BTreeMap<Vec<u8>, u64>> {
+        let txn = self.begin_ro_txn()?;
+        let mut cursor = txn.open_ro_cursor(self)?;
+        Ok(cursor
+            .iter_start()
+            .map(|(key, data)| {
+                let mut hasher = DefaultHasher::new();
+                data.hash(&mut hasher);
+                (key, hasher.finish())
+            })
+            .collect())
+    }
+
+    /// Run the closure over every item, splitting the keyspace into
+    /// `shards` ranges that are processed in parallel.
+    ///
+    /// The split points are picked from a single keys-only scan, using
+    /// the item count kept in the database header, so that shards hold
+    /// roughly the same number of items.  Every shard is processed in its
+    /// own read-only transaction on a separate database handle, since
+    /// handles don't synchronize access to their page cache, and all of
+    /// them start from the root page the split points were picked from
+    /// rather than looking up a revision.
+    /// Items within a shard are visited in key order, while shards run in
+    /// no particular order.
+    ///
+    /// Fails with `ErrorKind::InvalidArgument` for databases opened with
+    /// `DatabaseBuilder::open_fd`, which can't be opened again.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<F>(&self, shards: usize, f: F) -> Result<()>
+    where
+        F: Fn(&[u8], &[u8]) + Sync,
+    {
+        if self.builder.check_reopen().is_err() {
+            return Err(Error::from_errno(libc::EINVAL, Op::Open).context(
+                "Cannot scan a database opened from a file descriptor in \
+                 parallel"
+                    .to_string(),
+            ));
+        }
+
+        let db = self.builder.clone().reopen()?;
+        let txn = db.begin_ro_txn()?;
+        let per_shard = db.stat()?.entries as usize / shards.max(1) + 1;
+        let mut cursor = txn.open_ro_cursor(&db)?;
+        let bounds: Vec<Vec<u8>> =
+            cursor.iter_keys().step_by(per_shard).collect();
+        drop(cursor);
+
+        // The shards only read the root page of the transaction.
+        struct Shared(*mut ffi::btree_txn);
+        unsafe impl Sync for Shared {}
+        let shared = Shared(txn.txn());
+
+        (0..bounds.len()).into_par_iter().try_for_each(|i| {
+            let db = self.builder.clone().reopen()?;
+            let txn = RoTransaction::new_like(&db, shared.0)?;
+            let mut cursor = txn.open_ro_cursor(&db)?;
+            let iter = match bounds.get(i + 1) {
+                Some(end) => cursor.iter_range(&bounds[i], end),
+                None => cursor.iter_from(&bounds[i]),
+            };
+            for (key, data) in iter {
+                f(&key, &data);
+            }
+            Ok(())
+        })
+    }
+
+    pub fn reopen(&mut self) -> Result<()> {
+        self.builder.check_reopen()?;
+        clear_error();
+        self.close_retired();
+        unsafe {
+            ffi::btree_close(*self.handle.get_mut());
+        }
+        let mut builder = self.builder.clone();
+        let newdb = builder.reopen()?;
+        *self.handle.get_mut() = newdb.take_handle();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            op = "reopen",
+            path = %self.path().display(),
+            "Reopened the database"
+        );
+        Ok(())
+    }
+
+    /// Takes the btree handle out of the database, leaving nothing for it
+    /// to close when it is dropped.
+    fn take_handle(&self) -> *mut ffi::btree {
+        self.handle.swap(ptr::null_mut(), AtomicOrdering::AcqRel)
+    }
+
+    /// Replaces the stale handle `old` with a new one on the same file.
+    ///
+    /// Transactions may still be using `old`, so it is only closed once
+    /// they have ended, see `Database::release_retired`.  If another
+    /// thread got to replace it first, its handle is kept.
+    fn reopen_stale(&self, old: *mut ffi::btree) -> Result<()> {
+        let new = self.builder.clone().reopen()?.take_handle();
+        match self.handle.compare_exchange(
+            old,
+            new,
+            AtomicOrdering::AcqRel,
+            AtomicOrdering::Acquire,
+        ) {
+            Ok(_) => {
+                self.retired.lock().unwrap().push(old);
+                self.release_retired();
+            }
+            Err(_) => unsafe { ffi::btree_close(new) },
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            op = "reopen",
+            path = %self.path().display(),
+            "Reopened the database after it went stale"
+        );
+        Ok(())
+    }
+
+    /// Runs `begin`, and if it fails because the handle is stale and
+    /// `DatabaseBuilder::auto_reopen_on_stale` is set, reopens the
+    /// database and runs it once more.
+    fn retry_stale<T, F>(&self, begin: F) -> Result<T>
+    where
+        F: Fn() -> Result<T>,
+    {
+        let old = self.handle();
+        match begin() {
+            Err(ref err)
+                if err.kind() == ErrorKind::StaleHandle
+                    && self.builder.auto_reopen
+                    && self.builder.check_reopen().is_ok() =>
+            {
+                self.reopen_stale(old)?;
+                begin()
+            }
+            res => res,
+        }
+    }
+
+    fn close_retired(&mut self) {
+        for handle in self.retired.get_mut().unwrap().drain(..) {
+            unsafe {
+                ffi::btree_close(handle);
+            }
+        }
+    }
+
+    /// Opens a second handle on the same database file, with the options
+    /// this one was opened with.
+    ///
+    /// Both handles see the same committed data, but each has its own page
+    /// cache, and closing one leaves the other usable.  Fails for databases
+    /// opened with `DatabaseBuilder::open_fd`.
+    pub fn try_clone(&self) -> Result<Database> {
+        self.builder.clone().reopen()
+    }
+
+    /// Run the closure and reopen the database if it left the handle
+    /// stale, e.g. by calling `Database::compact`.
+    ///
+    /// The closure's result is returned either way, unless reopening the
+    /// database fails.
+    pub fn maintain<F, T>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Database) -> Result<T>,
+    {
+        let res = f(self);
+        if self.is_stale() {
+            self.reopen()?;
+        }
+        res
+    }
+
+    /// Returns whether the handle has to be reopened before further use.
+    fn is_stale(&self) -> bool {
+        match RoTransaction::new(self) {
+            Err(ref err) => err.kind() == ErrorKind::StaleHandle,
+            Ok(_) => false,
+        }
+    }
+
+    /// Revert last transaction.
+    pub fn revert(&self) -> Result<()> {
+        clear_error();
+        self.with_handle(|bt| unsafe {
+            result_from_int(ffi::btree_revert(bt), Op::Revert)
+        })
+    }
+
+    /// Compact the database.
+    ///
+    /// When compaction of a database file is complete, a special marker
+    /// is appended to the database file that requires the calling program
+    /// to reopen the file and perform new requests against the compacted
+    /// database.  The returned outcome tells whether this handle has to be
+    /// reopened with `Database::reopen`.
+    pub fn compact(&self) -> Result<CompactOutcome> {
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::info_span!("compact", path = %self.path().display())
+                .entered();
+        clear_error();
+        let res = self.with_handle(|bt| unsafe {
+            result_from_int(ffi::btree_compact(bt), Op::Compact)
+        });
+        #[cfg(feature = "tracing")]
+        if let Err(ref err) = res {
+            tracing::warn!(op = "compact", error = %err, "Failed to compact");
+        }
+        res?;
+        let outcome = CompactOutcome {
+            reopen_required: self.is_stale(),
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            op = "compact",
+            reopen_required = outcome.reopen_required,
+            "Compacted the database"
+        );
+        Ok(outcome)
+    }
+
+    /// Compact the database and reopen the handle if compaction left it
+    /// stale.
+    ///
+    /// Fails for databases opened with `DatabaseBuilder::open_fd`, which
+    /// can't be reopened.
+    pub fn compact_and_reopen(&mut self) -> Result<()> {
+        self.builder.check_reopen()?;
+        if self.compact()?.reopen_required {
+            self.reopen()?;
+        }
+        Ok(())
+    }
+
+    /// Changes the maximum number of pages kept in the page cache.
+    ///
+    /// Shrinking the cache doesn't evict anything right away: pages beyond
+    /// the new limit are expired in least recently used order as pages
+    /// are released.  `Database::reopen` and `Database::try_clone` use the
+    /// size set with `DatabaseBuilder::set_cache_size` instead.
+    pub fn set_cache_size(&self, pages: u32) {
+        self.with_handle(|bt| unsafe {
+            ffi::btree_set_cache_size(bt, pages);
+        })
+    }
+
+    /// Checks the integrity of the database.
+    ///
+    /// Every page in the file is read to check that it is intact, and
+    /// the checksums of the meta pages written on commit are recomputed.
+    /// The tree of the last committed revision is walked to check the
+    /// order of the keys within and across its pages and the links
+    /// between them.  Corrupt pages are listed in the report rather than
+    /// failing the check, which only fails if the file can't be read.
+    ///
+    /// Only meta pages carry a checksum, so damage to the contents of
+    /// other pages goes unnoticed unless it breaks the structure of the
+    /// tree.
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let mut corrupt_pages: Vec<u32> = Vec::new();
+        let mut verify = ffi::btree_verify {
+            pages: 0,
+            meta_pages: 0,
+            branch_pages: 0,
+            leaf_pages: 0,
+            overflow_pages: 0,
+            corrupt: Some(push_corrupt_page),
+            arg: &mut corrupt_pages as *mut Vec<u32> as *mut libc::c_void,
+        };
+        clear_error();
+        self.with_handle(|bt| unsafe {
+            result_from_int(ffi::btree_verify(bt, &mut verify), Op::Verify)
+        })?;
+        corrupt_pages.sort_unstable();
+        corrupt_pages.dedup();
+        Ok(VerifyReport {
+            pages: verify.pages,
+            meta_pages: verify.meta_pages,
+            branch_pages: verify.branch_pages,
+            leaf_pages: verify.leaf_pages,
+            overflow_pages: verify.overflow_pages,
+            corrupt_pages,
+        })
+    }
+
+    /// Flush data buffers to disk.
+    ///
+    /// Data is always written to disk when `Transaction::commit` is called,
+    /// but the operating system may keep it buffered. btree always flushes
+    /// the OS buffers upon commit as well, unless the database was opened
+    /// with `NO_SYNC`.
+    pub fn sync(&self) -> Result<()> {
+        clear_error();
+        let res = self.with_handle(|bt| unsafe {
+            result_from_int(ffi::btree_sync(bt), Op::Sync)
+        });
+        #[cfg(feature = "tracing")]
+        match res {
+            Ok(()) => tracing::debug!(op = "sync", "Synced the database"),
+            Err(ref err) => {
+                tracing::warn!(op = "sync", error = %err, "Failed to sync")
+            }
+        }
+        res
+    }
+
+    /// Closes the database handle. Normally unnecessary.
+    ///
+    /// Databases should only be closed by a single thread, and only if no
+    /// other threads are going to reference the database handle or one of
+    /// its cursors any further. Do not close a handle if an existing
+    /// transaction has modified its database. Doing so can cause database
+    /// corruption or other errors.
+    pub fn close(self) {
+        clear_error();
+        unsafe {
+            ffi::btree_close(self.handle());
+        }
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        clear_error();
+        self.close_retired();
+        unsafe {
+            ffi::btree_close(*self.handle.get_mut());
+        }
+        if let Some(ref path) = self.temp_path {
+            let _ = fs::remove_file(path);
+            let _ = fs::remove_file(lock_path(path));
+        }
+    }
+}
+
+/// Returns the path of the lock file of the database at `path`, see
+/// `DatabaseBuilder::lock_exclusive`.
+fn lock_path(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+/// Iterates over all items, as seen by a read-only transaction started
+/// when the iterator is created.
+///
+/// Reserved keys are skipped unless `DatabaseBuilder::hide_reserved` is
+/// turned off.
+///
+/// # Panics
+///
+/// Panics if the transaction can't be started, see `Database::iter_tokens`
+/// for a fallible alternative.
+impl<'db> IntoIterator for &'db Database {
+    type Item = (Vec<u8>, Vec<u8>);
+    type IntoIter = OwnedIter<'db>;
+
+    fn into_iter(self) -> OwnedIter<'db> {
+        match self.iter_tokens(None, None) {
+            Ok(iter) => iter,
+            Err(err) => panic!("Failed to iterate over the database: {}", err),
+        }
+    }
+}
+
+/// Returns the descriptor of the database file, e.g. for `flock` or
+/// `fstat`.
+///
+/// The descriptor is owned by the database: it must not be closed, and
+/// nothing but the database may write to it or move its file offset.  A
+/// database reopened with `Database::reopen`, or automatically after it
+/// went stale, has a new descriptor.
+#[cfg(unix)]
+impl AsRawFd for Database {
+    fn as_raw_fd(&self) -> RawFd {
+        self.with_handle(|bt| unsafe { ffi::btree_get_fd(bt) })
+    }
+}
+
+unsafe impl Sync for Database {}
+unsafe impl Send for Database {}
+
+bitflags! {
+    #[doc="Database options."]
+    #[derive(Default)]
+    pub struct DatabaseFlags: libc::c_uint {
+        #[doc="Don't flush system buffers to disk when committing"]
+        #[doc="a transaction."]
+        #[doc="\n\n"]
+        #[doc="This optimization means a system crash can corrupt"]
+        #[doc="the database or lose the last transactions if buffers"]
+        #[doc="are not yet flushed to disk. The risk is governed by"]
+        #[doc="how often the system flushes dirty buffers to disk"]
+        #[doc="and how often `Database::sync` is called."]
+        const NO_SYNC = ffi::BT_NOSYNC;
+
+        #[doc="Open the database in read-only mode. No write"]
+        #[doc="operations will be allowed."]
+        const READ_ONLY = ffi::BT_RDONLY;
+
+        #[doc="Keys are strings to be compared in reverse order,"]
+        #[doc="from the end of the strings to the beginning."]
+        #[doc="\n\n"]
+        #[doc="By default, keys are treated as strings and compared"]
+        #[doc="from the beginning to the end."]
+        const REVERSE_KEY = ffi::BT_REVERSEKEY;
+    }
+}
+
+#[derive(Clone)]
+pub struct DatabaseBuilder {
+    flags: DatabaseFlags,
+    cache_size: u32,
+    path: PathBuf,
+    mode: u32,
+    hide_reserved: bool,
+    page_size: u32,
+    exclusive: bool,
+    comparator: Option<Comparator>,
+    key_len: Option<usize>,
+    warm_keys: Vec<Vec<u8>>,
+    change_feed: bool,
+    auto_reopen: bool,
+    lock: bool,
+}
+
+impl DatabaseBuilder {
+    /// Open an existing database or create a new one.
+    ///
+    /// On UNIX, the database files will be opened with 644 permissions.
+    pub fn open(&mut self, path: &Path) -> Result<Database> {
+        self.open_with_permissions(path, 0o644)
+    }
+
+    /// Open an existing database or create a new one with the provided
+    /// UNIX permissions.
+    pub fn open_with_permissions(
+        &mut self,
+        path: &Path,
+        mode: u32,
+    ) -> Result<Database> {
+        self.path = path.to_path_buf();
+        self.mode = mode;
+        self.check_page_size()?;
+
+        let path = match CString::new(path.as_os_str().as_bytes()) {
+            Ok(path) => path,
+            Err(..) => {
+                let mut errstr = String::from("Invalid path");
+                if let Some(path) = path.to_str() {
+                    errstr += &format!(": {}", path);
+                }
+                return Err(Error::other(errstr));
+            }
+        };
+
+        let mut flags = self.flags.bits();
+        if self.exclusive {
+            flags |= ffi::BT_EXCL;
+        }
+        let lock = if self.lock {
+            Some(self.lock_file(mode)?)
+        } else {
+            None
+        };
+
+        clear_error();
+        let dbi = unsafe {
+            result_from_ptr::<ffi::btree>(
+                ffi::btree_open_psize(
+                    path.as_ptr(),
+                    flags,
+                    mode as libc::mode_t,
+                    self.page_size,
+                ),
+                Op::Open,
+            )?
+        };
+        let mut db = self.wrap(dbi);
+        db.lock = lock;
+        Ok(db)
+    }
+
+    /// Opens the lock file next to the database and locks it, see
+    /// `DatabaseBuilder::lock_exclusive`.
+    fn lock_file(&self, mode: u32) -> Result<fs::File> {
+        let path = lock_path(&self.path);
+        let read_only = self.flags.contains(DatabaseFlags::READ_ONLY);
+        // `flock` doesn't need write access, which read-only openers may
+        // not have, so they only create the lock file if it's missing.
+        let file = match fs::File::open(&path) {
+            Ok(file) if read_only => Ok(file),
+            _ => fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .mode(mode)
+                .open(&path),
+        }
+        .map_err(|err| Error::from_io(err, Op::Open))?;
+        let op = if read_only {
+            libc::LOCK_SH
+        } else {
+            libc::LOCK_EX
+        };
+        if unsafe { libc::flock(file.as_raw_fd(), op | libc::LOCK_NB) } != 0 {
+            return Err(match errno::errno().0 {
+                libc::EWOULDBLOCK => Error::from_errno(libc::EBUSY, Op::Open)
+                    .context(format!(
+                        "Database is locked by another process: {}",
+                        self.path.display()
+                    )),
+                errno => Error::from_errno(errno, Op::Open),
+            });
+        }
+        Ok(file)
+    }
+
+    /// Open a database over an already open file descriptor.
+    ///
+    /// The database takes ownership of the descriptor and closes it when
+    /// dropped, or right away if opening fails.  As there is no path to
+    /// open again, `Database::reopen` fails on such a database and
+    /// `Database::path` returns an empty path.
+    #[cfg(unix)]
+    pub fn open_fd(&mut self, fd: RawFd) -> Result<Database> {
+        self.path = PathBuf::new();
+        if let Err(err) = self.check_page_size() {
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+
+        clear_error();
+        let dbi = unsafe {
+            let dbi =
+                ffi::btree_open_fd_psize(fd, self.flags.bits(), self.page_size);
+            if dbi.is_null() {
+                let err = Error::new(Op::Open);
+                libc::close(fd);
+                return Err(err);
+            }
+            dbi
+        };
+        Ok(self.wrap(dbi))
+    }
+
+    /// Fails unless the page size set with `set_page_size` is a power of
+    /// two btree supports.
+    fn check_page_size(&self) -> Result<()> {
+        let size = self.page_size;
+        if size == 0
+            || (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&size)
+                && size.is_power_of_two()
+        {
+            return Ok(());
+        }
+        Err(Error::from_errno(libc::EINVAL, Op::Open).context(format!(
+            "Page size {} is not a power of two from {} to {}",
+            size, MIN_PAGE_SIZE, MAX_PAGE_SIZE
+        )))
+    }
+
+    /// Applies the remaining options to a freshly opened btree handle.
+    fn wrap(&self, dbi: *mut ffi::btree) -> Database {
+        // The database keeps a clone of the builder, and with it the
+        // comparator, for as long as btree may call it.
+        if let Some(ref cmp) = self.comparator {
+            let arg = &**cmp as *const _ as *mut libc::c_void;
+            unsafe {
+                ffi::btree_set_cmp(dbi, compare_keys, arg);
+            }
+        }
+        if self.cache_size > 0 {
+            unsafe {
+                ffi::btree_set_cache_size(dbi, self.cache_size);
+            }
+        }
+
+        let db = Database {
+            handle: AtomicPtr::new(dbi),
+            retired: Mutex::new(Vec::new()),
+            pins: AtomicUsize::new(0),
+            builder: self.clone(),
+            temp_path: None,
+            lock: None,
+        };
+        db.warm();
+        db
+    }
+
+    /// Fails unless the database was opened by path and can be reopened.
+    fn check_reopen(&self) -> Result<()> {
+        if self.path.as_os_str().is_empty() {
+            return Err(Error::from_errno(libc::EINVAL, Op::Open).context(
+                "Cannot reopen a database opened from a file descriptor"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Open a database and replay a write-ahead log into it.
+    ///
+    /// The log is a sequence of records, each made of a record type byte
+    /// followed by fields prefixed with their length as a big-endian
+    /// 32-bit integer: `P` (`PUT key val`) followed by the key and the
+    /// value, or `D` (`DEL key`) followed by the key.  Deleting a missing
+    /// key is not an error.  All records are applied in a single write
+    /// transaction before the database is returned, so either the whole
+    /// log is replayed or none of it.  An incomplete record at the end of
+    /// the log, as left behind by a crash while appending to it, is
+    /// ignored.
+    pub fn replay_wal<R: Read>(
+        &mut self,
+        path: &Path,
+        wal: R,
+    ) -> Result<Database> {
+        let db = self.open(path)?;
+        wal::replay(&db, wal)?;
+        Ok(db)
+    }
+
+    pub(crate) fn reopen(&mut self) -> Result<Database> {
+        self.check_reopen()?;
+        self.exclusive = false;
+        // The lock stays with the handle that took it.
+        self.lock = false;
+        let pathbuf = self.path.clone();
+        let mode = self.mode;
+        self.open_with_permissions(pathbuf.as_path(), mode)
+    }
+
+    /// Sets the provided options for the database.
+    pub fn set_flags(&mut self, flags: DatabaseFlags) -> &mut Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Set the cache size for database entries.
+    ///
+    /// The size is specified in number of pages.  Note that more than the
+    /// configured number of pages may exist in the cache, as dirty pages
+    /// and pages referenced by cursors are excluded from cache expiration.
+    /// Cached pages are expired in a least recently used (LRU) order.
+    pub fn set_cache_size(&mut self, cache_size: u32) -> &mut Self {
+        self.cache_size = cache_size;
+        self
+    }
+
+    /// Order keys with the given comparison instead of byte-wise, or
+    /// reverse byte-wise with `DatabaseFlags::REVERSE_KEY`.
+    ///
+    /// The order is part of the database format: the same comparison must
+    /// be set every time the database is opened, from before anything is
+    /// written to it.  It is kept for `Database::reopen`.  The comparison
+    /// must be a total order.  Prefix compression of keys is turned off,
+    /// so keys take more space.
+    ///
+    /// A panic in the comparison is caught before it reaches btree.  The
+    /// operation it happened in fails with `ErrorKind::Other`, and the
+    /// write transaction open on the database can no longer be committed.
+    /// Cursor iterators comparing keys against their bounds resume the
+    /// panic instead.
+    ///
+    /// Methods working on keys with a common prefix, such as
+    /// `Cursor::iter_prefix`, visit the keys ordered from the prefix up
+    /// to the first byte string past all keys starting with it.  They
+    /// only find the keys with the prefix if the comparison keeps those
+    /// together, and between these two bounds, the way byte-wise order
+    /// does.
+    pub fn set_comparator<F>(&mut self, cmp: F) -> &mut Self
+    where
+        F: Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static,
+    {
+        self.comparator = Some(Arc::new(Box::new(cmp)));
+        self
+    }
+
+    /// Require `open` to create a new database, failing with
+    /// `ErrorKind::AlreadyExists` if the file exists.
+    ///
+    /// The check and the creation are a single atomic step.  Reopening the
+    /// database later opens the file that was created.  Can't be combined
+    /// with `DatabaseFlags::READ_ONLY`, and doesn't apply to `open_fd`.
+    pub fn create_exclusive(&mut self) -> &mut Self {
+        self.exclusive = true;
+        self
+    }
+
+    /// Lock the database against opening by other processes, failing with
+    /// `ErrorKind::Busy` if one of them holds the lock.
+    ///
+    /// The lock is an `flock` on a `.lock` file next to the database,
+    /// which is created if needed and left in place, unless the database
+    /// is removed by `Database::temporary`.  It is exclusive, unless the
+    /// database is opened with `DatabaseFlags::READ_ONLY`, in which case
+    /// it is shared with other read-only openers, and an existing lock
+    /// file is opened read-only.  The lock is
+    /// held until the database is dropped, and handles made with
+    /// `Database::try_clone` don't take it.  Doesn't apply to `open_fd`.
+    pub fn lock_exclusive(&mut self) -> &mut Self {
+        self.lock = true;
+        self
+    }
+
+    /// Set the size of the pages of a newly created database, in bytes.
+    ///
+    /// The size must be a power of two from 512 to 32768 bytes, or opening
+    /// fails with `ErrorKind::InvalidArgument`.  Larger pages fit more keys
+    /// each, making the tree shallower, and raise the size from which
+    /// values go on overflow pages.  They also allow longer keys, as every
+    /// page has to hold at least four of them, see
+    /// `Database::max_key_len`.  An existing database keeps the page
+    /// size it was created with.  Defaults to 4096 bytes.
+    pub fn set_page_size(&mut self, bytes: u32) -> &mut Self {
+        self.page_size = bytes;
+        self
+    }
+
+    /// Read the given keys right after opening the database, so that the
+    /// pages holding them are in the cache when the database is returned.
+    ///
+    /// Keys that aren't in the database are skipped.  The keys are also
+    /// read whenever the database is reopened.  Warming more pages than
+    /// `set_cache_size` allows evicts the earlier ones.
+    pub fn warm_keys<I, K>(&mut self, keys: I) -> &mut Self
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<[u8]>,
+    {
+        self.warm_keys =
+            keys.into_iter().map(|key| key.as_ref().to_vec()).collect();
+        self
+    }
+
+    /// Hide reserved keys from cursor iterators.
+    ///
+    /// Keys starting with a NUL byte, such as `META_KEY`, are reserved for
+    /// internal bookkeeping and are skipped by `Cursor::iter` and friends
+    /// unless this is turned off.  `Cursor::iter_raw` always sees them.
+    /// Enabled by default.
+    ///
+    /// The check is on the first byte only, so user keys starting with a
+    /// NUL byte, such as big-endian integers below 2^56, are hidden as
+    /// well.  Databases storing such keys should turn this off.
+    pub fn hide_reserved(&mut self, yes: bool) -> &mut Self {
+        self.hide_reserved = yes;
+        self
+    }
+
+    /// Require all keys to be exactly `len` bytes long.
+    ///
+    /// Every transaction method looking up, storing or deleting a single
+    /// key, such as `Transaction::get`, `Transaction::contains_key`,
+    /// `RwTransaction::put` and `RwTransaction::del`, fails with
+    /// `ErrorKind::InvalidArgument` on keys of any other length, before
+    /// reaching btree.  Reserved keys
+    /// are exempt.  Not enforced by default.
+    pub fn fixed_key_len(&mut self, len: usize) -> &mut Self {
+        self.key_len = Some(len);
+        self
+    }
+
+    /// Record every `RwTransaction::put` and `RwTransaction::del` in a
+    /// change feed, to be read with `Database::poll_changes`.
+    ///
+    /// Changes are kept under reserved keys written in the same
+    /// transaction as the change itself, so the feed is as durable as the
+    /// data.  Writes to reserved keys aren't recorded.  `RwCursor::del`
+    /// and `RwTransaction::clear` can't be recorded, and fail with
+    /// `ErrorKind::InvalidArgument` while the feed is on.  Disabled by
+    /// default.
+    pub fn change_feed(&mut self, yes: bool) -> &mut Self {
+        self.change_feed = yes;
+        self
+    }
+
+    /// Reopen the database when starting a transaction finds the handle
+    /// stale, e.g. after it was compacted by another handle or process,
+    /// and start the transaction again.
+    ///
+    /// Applies to `Database::begin_ro_txn`, `Database::begin_rw_txn` and
+    /// the methods built on them.  Transactions started before the reopen
+    /// keep reading the stale handle, along with the cursors opened on
+    /// them, and it is closed when the last of them ends.  Has no effect
+    /// on databases opened with `open_fd`.
+    pub fn auto_reopen_on_stale(&mut self) -> &mut Self {
+        self.auto_reopen = true;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::SystemTime;
+
+    use tempdir::TempDir;
+
+    use error::ErrorKind;
+
+    use super::*;
+
+    #[test]
+    fn test_open() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+
+        assert!(Database::new().open(&dbpath).is_ok());
+    }
+
+    #[test]
+    fn test_open_error_display() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("missing").join("test");
+
+        let err = Database::new().open(&dbpath).err().unwrap();
+        let msg = err.to_string();
+        assert!(msg.starts_with("Failed to open the database: "));
+        assert!(msg.ends_with(&errno::Errno(libc::ENOENT).to_string()));
+    }
+
+    #[test]
+    fn test_path() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let mut db = Database::new().open(&dbpath).unwrap();
+        assert_eq!(dbpath.as_path(), db.path());
+        db.reopen().unwrap();
+        assert_eq!(dbpath.as_path(), db.path());
+    }
+
+    #[test]
+    fn test_flags() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        assert_eq!(DatabaseFlags::empty(), db.flags());
+        db.close();
+
+        let db = Database::new()
+            .set_flags(DatabaseFlags::READ_ONLY | DatabaseFlags::NO_SYNC)
+            .open(&dbpath)
+            .unwrap();
+        assert!(db.flags().contains(DatabaseFlags::READ_ONLY));
+        assert!(db.flags().contains(DatabaseFlags::NO_SYNC));
+        assert!(!db.flags().contains(DatabaseFlags::REVERSE_KEY));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lock_exclusive() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let mut db = Database::new().lock_exclusive().open(&dbpath).unwrap();
+        db.update(|txn| txn.put(&db, b"key1", b"val1", WriteFlags::empty()))
+            .unwrap();
+
+        let err = Database::new().lock_exclusive().open(&dbpath).err();
+        assert_eq!(Some(ErrorKind::Busy), err.map(|err| err.kind()));
+        let err = Database::new()
+            .set_flags(DatabaseFlags::READ_ONLY)
+            .lock_exclusive()
+            .open(&dbpath)
+            .err();
+        assert_eq!(Some(ErrorKind::Busy), err.map(|err| err.kind()));
+        // Opening without the lock is still allowed.
+        Database::new().open(&dbpath).unwrap();
+
+        // The lock stays with the handle across reopening.
+        db.reopen().unwrap();
+        assert!(Database::new().lock_exclusive().open(&dbpath).is_err());
+        let clone = db.try_clone().unwrap();
+        drop(db);
+        Database::new().lock_exclusive().open(&dbpath).unwrap();
+        drop(clone);
+
+        let reader = || {
+            Database::new()
+                .set_flags(DatabaseFlags::READ_ONLY)
+                .lock_exclusive()
+                .open(&dbpath)
+        };
+        let first = reader().unwrap();
+        let second = reader().unwrap();
+        // The lock file is opened read-only, as the reader may not be
+        // allowed to write it.
+        let fd = first.lock.as_ref().unwrap().as_raw_fd();
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        assert_eq!(libc::O_RDONLY, flags & libc::O_ACCMODE);
+        let txn = second.begin_ro_txn().unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&second, b"key1").unwrap());
+        drop(txn);
+        assert!(Database::new().lock_exclusive().open(&dbpath).is_err());
+        drop(first);
+        drop(second);
+        Database::new().lock_exclusive().open(&dbpath).unwrap();
+    }
+
+    #[test]
+    fn test_as_raw_fd() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        db.update(|txn| txn.put(&db, b"key1", b"val1", WriteFlags::empty()))
+            .unwrap();
+
+        let mut st: libc::stat = unsafe { ::std::mem::zeroed() };
+        assert_eq!(0, unsafe { libc::fstat(db.as_raw_fd(), &mut st) });
+        assert_eq!(libc::S_IFREG, st.st_mode & libc::S_IFMT);
+        assert!(st.st_size > 0);
+        assert_eq!(db.file_size().unwrap(), st.st_size as u64);
+    }
+
+    #[test]
+    fn test_open_fd() {
+        use std::fs::OpenOptions;
+        use std::os::unix::io::IntoRawFd;
+
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&dbpath)
+            .unwrap();
+
+        let mut db = Database::new().open_fd(file.into_raw_fd()).unwrap();
+        db.update(|txn| txn.put(&db, b"key1", b"val1", WriteFlags::empty()))
+            .unwrap();
+        assert!(db
+            .reopen()
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+        assert_eq!(Path::new(""), db.path());
+        db.close();
+
+        let db = Database::new().open(&dbpath).unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
+    }
+
+    #[test]
+    fn test_ro_txn() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        let res = txn.get(&db, &"/non-existant".to_string());
+        assert!(res.is_err());
+        if let Err(err) = res {
+            assert_eq!(err.kind(), ErrorKind::NotFound);
+        }
+    }
+
+    #[test]
+    fn test_rw_txn() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        let mut rwtxn = db.begin_rw_txn().unwrap();
+        let res = rwtxn.put(
+            &db,
+            &"/test-key".to_string(),
+            &"Some data".to_string(),
+            WriteFlags::empty(),
+        );
+        assert!(res.is_ok());
+        assert!(rwtxn.commit().is_ok());
+        let rotxn = db.begin_ro_txn().unwrap();
+        let res = rotxn.get(&db, &"/test-key".to_string());
+        assert!(res.is_ok());
+        if let Ok(data) = res {
+            assert_eq!(data, "Some data".as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_multi_rw_txn() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        for i in 0..1000 {
+            let mut rwtxn = db.begin_rw_txn().unwrap();
+            assert!(rwtxn
+                .put(
+                    &db,
+                    &format!("/test-key-{}", i),
+                    &"Some data".to_string(),
+                    WriteFlags::empty(),
+                )
+                .is_ok());
+            assert!(rwtxn.commit().is_ok());
+        }
+        for i in 0..1000 {
+            let rotxn = db.begin_ro_txn().unwrap();
+            assert!(rotxn.get(&db, &format!("/test-key-{}", i)).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        let res = db.update(|txn| {
+            txn.put(&db, b"key1", b"val1", WriteFlags::empty())?;
+            Ok(1)
+        });
+        assert_eq!(res, Ok(1));
+
+        let res = db.update(|txn| {
+            txn.put(&db, b"key2", b"val2", WriteFlags::empty())?;
+            txn.put(&db, b"key1", b"val3", WriteFlags::NO_OVERWRITE)
+        });
+        assert!(res.unwrap_err().same_kind(ErrorKind::AlreadyExists));
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(txn.get(&db, b"key1").unwrap(), b"val1");
+        assert!(txn
+            .get(&db, b"key2")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_view() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        db.update(|txn| {
+            txn.put(&db, b"key1", b"val1", WriteFlags::empty())?;
+            txn.put(&db, b"key2", b"val2", WriteFlags::empty())
+        })
+        .unwrap();
+
+        let vals = db
+            .view(|txn| Ok((txn.get(&db, b"key1")?, txn.get(&db, b"key2")?)))
+            .unwrap();
+        assert_eq!(vals, (b"val1".to_vec(), b"val2".to_vec()));
+        assert!(db
+            .view(|txn| txn.get(&db, b"key3"))
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_iter_tokens() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        db.update(|txn| {
+            for i in 0..100 {
+                let key = format!("/r/{:03}", i);
+                txn.put(&db, &key, &key, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let items: Vec<_> = db.iter_tokens(None, None).unwrap().collect();
+        assert_eq!(100, items.len());
+
+        let first: Vec<_> =
+            db.iter_tokens(None, None).unwrap().take(40).collect();
+        let token = first.last().unwrap().0.clone();
+        let rest: Vec<_> = db.iter_tokens(Some(token), None).unwrap().collect();
+        assert_eq!(items, [first, rest].concat());
+
+        // Tokens don't have to be present in the database.
+        let window: Vec<_> = db
+            .iter_tokens(Some(b"/r/0405".to_vec()), Some(b"/r/050".to_vec()))
+            .unwrap()
+            .collect();
+        assert_eq!(items[41..51].to_vec(), window);
+
+        let tail = db.iter_tokens(Some(b"/r/099".to_vec()), None).unwrap();
+        assert_eq!(0, tail.count());
+    }
+
+    #[test]
+    fn test_scan_budgeted() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        db.update(|txn| {
+            for i in 0..200 {
+                let key = format!("/r/{:03}", i);
+                txn.put(&db, &key, &key, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let (first, token) =
+            db.scan_budgeted(None, Duration::from_secs(0)).unwrap();
+        assert!(!first.is_empty() && first.len() < 200);
+        assert_eq!(token.as_ref(), first.last().map(|item| &item.0));
+
+        let (rest, token) =
+            db.scan_budgeted(token, Duration::from_secs(60)).unwrap();
+        assert_eq!(None, token);
+        assert_eq!(200, first.len() + rest.len());
+        assert_eq!(b"/r/199".to_vec(), rest.last().unwrap().0);
+    }
+
+    #[test]
+    fn test_first_gap() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        let key = |prefix: &[u8], i| {
+            let mut key = prefix.to_vec();
+            key.extend_from_slice(&keys::encode_u64_be(i));
+            key
+        };
+        db.update(|txn| {
+            for i in 5..10 {
+                txn.put(&db, &key(b"/a/", i), b"", WriteFlags::empty())?;
+            }
+            for &i in &[0, 1, 2, 4, 5, 7] {
+                txn.put(&db, &key(b"/b/", i), b"", WriteFlags::empty())?;
+            }
+            txn.put(&db, b"/c/short", b"", WriteFlags::empty())
+        })
+        .unwrap();
+
+        assert_eq!(None, db.first_gap(b"/a/").unwrap());
+        assert_eq!(Some(3), db.first_gap(b"/b/").unwrap());
+        assert_eq!(None, db.first_gap(b"/d/").unwrap());
+        assert!(db.first_gap(b"/c/").is_err());
+
+        // Indices below 2^56 start with a NUL byte, like reserved keys.
+        let db = Database::temporary().unwrap();
+        db.set_meta(b"meta").unwrap();
+        db.update(|txn| {
+            for &i in &[1, 2, 3, 5, 1 << 60] {
+                let key = keys::encode_u64_be(i);
+                txn.put(&db, &key, b"", WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(Some(4), db.first_gap(b"").unwrap());
+    }
+
+    #[test]
+    fn test_meta() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        assert_eq!(db.get_meta(), Ok(None));
+        db.set_meta(b"version=1").unwrap();
+        assert_eq!(db.get_meta(), Ok(Some(b"version=1".to_vec())));
+        db.set_meta(b"version=2").unwrap();
+        assert_eq!(db.get_meta(), Ok(Some(b"version=2".to_vec())));
+    }
+
+    #[test]
+    fn test_bulk_load_unique() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        let items = (0..2500).map(|i| (format!("/r/{}", i), format!("{}", i)));
+        assert_eq!(2500, db.bulk_load_unique(items).unwrap());
+
+        // The duplicate lands in the second batch, which is rolled back.
+        let items = (0..1500)
+            .map(|i| (format!("/s/{}", i), format!("{}", i)))
+            .chain(Some(("/r/7".to_string(), "dup".to_string())));
+        let err = db.bulk_load_unique(items).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert!(err.to_string().contains("/r/7"));
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"7".to_vec(), txn.get(&db, b"/r/7").unwrap());
+        assert!(txn.get_opt(&db, b"/s/999").unwrap().is_some());
+        assert!(txn.get_opt(&db, b"/s/1000").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_keys_changed_between() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        db.update(|txn| {
+            for i in 0..10 {
+                let key = format!("/r/{}", i);
+                txn.put(&db, &key, &key, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let changed = db
+            .keys_changed_between(|db| {
+                db.update(|txn| {
+                    txn.put(db, b"/r/1", b"new", WriteFlags::empty())?;
+                    txn.put(db, b"/r/2", b"/r/2", WriteFlags::empty())?;
+                    txn.put(db, b"/r/a", b"new", WriteFlags::empty())?;
+                    txn.del(db, b"/r/5")
+                })
+            })
+            .unwrap();
+        assert_eq!(
+            vec![b"/r/1".to_vec(), b"/r/5".to_vec(), b"/r/a".to_vec()],
+            changed
+        );
+
+        assert_eq!(
+            Vec::<Vec<u8>>::new(),
+            db.keys_changed_between(|_| Ok(())).unwrap()
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_for_each() {
+        use std::os::unix::io::IntoRawFd;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Mutex, Once};
+
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        db.update(|txn| {
+            for i in 0..1000 {
+                let key = format!("/r/{}", i);
+                let val = "x".repeat(i % 37);
+                txn.put(&db, &key, &val, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        let (count, sum) =
+            cursor.iter_start().fold((0, 0), |(count, sum), (_, data)| {
+                (count + 1, sum + data.len())
+            });
+
+        // Writes committed while the shards run aren't seen by any of them.
+        let writer = Mutex::new(db.try_clone().unwrap());
+        let written = Once::new();
+        let par_count = AtomicUsize::new(0);
+        let par_sum = AtomicUsize::new(0);
+        db.par_for_each(4, |_, data| {
+            written.call_once(|| {
+                let writer = writer.lock().unwrap();
+                writer
+                    .update(|txn| {
+                        for i in 0..1000 {
+                            let key = format!("/r/{}/new", i);
+                            txn.put(&writer, &key, b"x", WriteFlags::empty())?;
+                        }
+                        Ok(())
+                    })
+                    .unwrap();
+            });
+            par_count.fetch_add(1, Ordering::SeqCst);
+            par_sum.fetch_add(data.len(), Ordering::SeqCst);
+        })
+        .unwrap();
+        assert_eq!(count, par_count.load(Ordering::SeqCst));
+        assert_eq!(sum, par_sum.load(Ordering::SeqCst));
+        assert_eq!(2000, writer.lock().unwrap().len().unwrap());
+
+        let file = fs::File::open(&dbpath).unwrap();
+        let fddb = Database::new()
+            .set_flags(DatabaseFlags::READ_ONLY)
+            .open_fd(file.into_raw_fd())
+            .unwrap();
+        assert!(fddb
+            .par_for_each(4, |_, _| ())
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_ro_txn_like() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        db.update(|txn| txn.put(&db, b"key", b"old", WriteFlags::empty()))
+            .unwrap();
+
+        let other = db.try_clone().unwrap();
+        let snapshot = db.begin_ro_txn().unwrap();
+        db.update(|txn| txn.put(&db, b"key", b"new", WriteFlags::empty()))
+            .unwrap();
+        let txn = RoTransaction::new_like(&other, snapshot.txn()).unwrap();
+        assert_eq!(b"old".to_vec(), txn.get(&other, b"key").unwrap());
+        drop(txn);
+
+        db.compact().unwrap();
+        let other = db.try_clone().unwrap();
+        assert!(RoTransaction::new_like(&other, snapshot.txn())
+            .err()
+            .unwrap()
+            .same_kind(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_stat() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        let entries = 1000;
+
+        db.update(|txn| {
+            for i in 0..entries {
+                let key = format!("/r/{}", i);
+                txn.put(&db, &key, &key, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let stat = db.stat().unwrap();
+        assert!(stat.entries >= entries);
+        assert!(stat.depth >= 1);
+        assert!(stat.leaf_pages >= 1);
+        assert_eq!(stat.psize, 4096);
+    }
+
+    #[test]
+    fn test_begin_ro_txn_at() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"old1", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key2", &vec![2; 10000], WriteFlags::empty())
+            .unwrap();
+        let old = txn.commit_with_revision().unwrap();
+        for i in 0..100 {
+            let mut txn = db.begin_rw_txn().unwrap();
+            let val = format!("new{}", i);
+            txn.put(&db, b"key1", &val, WriteFlags::empty()).unwrap();
+            txn.put(&db, b"key3", &vec![3; 10000], WriteFlags::empty())
+                .unwrap();
+            txn.commit().unwrap();
+        }
+
+        let txn = db.begin_ro_txn_at(old).unwrap();
+        assert_eq!(b"old1".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert_eq!(vec![2; 10000], txn.get(&db, b"key2").unwrap());
+        assert!(txn
+            .get(&db, b"key3")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+        drop(txn);
+
+        let txn = db.begin_ro_txn_at(old + 1).unwrap();
+        assert_eq!(b"new0".to_vec(), txn.get(&db, b"key1").unwrap());
+        drop(txn);
+        let txn = db.begin_ro_txn_at(old + 100).unwrap();
+        assert_eq!(b"new99".to_vec(), txn.get(&db, b"key1").unwrap());
+        drop(txn);
+
+        for &revision in &[0, old + 101, u64::MAX] {
+            let err = db.begin_ro_txn_at(revision).err().unwrap();
+            assert_eq!(ErrorKind::NotFound, err.kind());
+        }
+    }
+
+    #[test]
+    fn test_backup() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let backup = dir.path().join("backup");
+        let db = Database::new().open(&dbpath).unwrap();
+        db.update(|txn| {
+            for i in 0..1000 {
+                let key = format!("/r/{:03}", i);
+                txn.put(&db, &key, &key, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        db.backup(&backup).unwrap();
+        db.update(|txn| txn.put(&db, b"late", b"late", WriteFlags::empty()))
+            .unwrap();
+        let err = db.backup(&backup).err().unwrap();
+        assert_eq!(ErrorKind::AlreadyExists, err.kind());
+
+        let copy = Database::new().open(&backup).unwrap();
+        let txn = copy.begin_ro_txn().unwrap();
+        for i in 0..1000 {
+            let key = format!("/r/{:03}", i);
+            assert_eq!(key.as_bytes(), &txn.get(&copy, &key).unwrap()[..]);
+        }
+        assert!(txn
+            .get(&copy, b"late")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+        drop(txn);
+        copy.close();
+
+        db.backup_overwrite(&backup).unwrap();
+        let copy = Database::new().open(&backup).unwrap();
+        let txn = copy.begin_ro_txn().unwrap();
+        assert_eq!(b"late".to_vec(), txn.get(&copy, b"late").unwrap());
+        assert_eq!(1001, copy.stat().unwrap().entries);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_to_fd() {
+        use std::os::unix::io::AsRawFd;
+
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let copypath = dir.path().join("copy");
+        let db = Database::new().open(&dbpath).unwrap();
+        db.update(|txn| {
+            for i in 0..100 {
+                let key = format!("/r/{:02}", i);
+                txn.put(&db, &key, &key, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let file = fs::File::create(&copypath).unwrap();
+        let copied = db.copy_to_fd(file.as_raw_fd()).unwrap();
+        assert_eq!(copied, file.metadata().unwrap().len());
+        assert_eq!(db.file_size().unwrap(), copied);
+        drop(file);
+
+        let copy = Database::new().open(&copypath).unwrap();
+        let txn = copy.begin_ro_txn().unwrap();
+        assert_eq!(b"/r/42".to_vec(), txn.get(&copy, b"/r/42").unwrap());
+        assert_eq!(100, copy.stat().unwrap().entries);
+    }
+
+    #[test]
+    fn test_set_comparator() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let mut db = Database::new()
+            .set_comparator(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)))
+            .open(&dbpath)
+            .unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in (0..1000).rev() {
+            let key = i.to_string();
+            txn.put(&db, &key, &key, WriteFlags::empty()).unwrap();
+        }
+        txn.commit().unwrap();
+
+        let keys = |db: &Database| {
+            db.into_iter()
+                .map(|(key, _)| String::from_utf8(key).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let expected = (0..1000).map(|i| i.to_string()).collect::<Vec<_>>();
+        assert_eq!(expected, keys(&db));
+
+        db.reopen().unwrap();
+        db.update(|txn| txn.put(&db, b"1000", b"1000", WriteFlags::empty()))
+            .unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"999".to_vec(), txn.get(&db, b"999").unwrap());
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        let range = cursor
+            .iter_range(&b"98"[..], &b"101"[..])
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![b"98".to_vec(), b"99".to_vec(), b"100".to_vec()],
+            range
+        );
+        assert_eq!(Some(b"1000".to_vec()), cursor.last().unwrap().map(|i| i.0));
+    }
+
+    #[test]
+    fn test_set_comparator_panic() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new()
+            .set_comparator(|a, b| {
+                assert!(a != b"boom" && b != b"boom");
+                a.cmp(b)
+            })
+            .open(&dbpath)
+            .unwrap();
+        db.update(|txn| {
+            for i in 0..100 {
+                let key = format!("key{:02}", i);
+                txn.put(&db, &key, &key, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        // The write transaction can't be committed after the panic.
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key", b"val", WriteFlags::empty()).unwrap();
+        assert!(txn
+            .put(&db, b"boom", b"val", WriteFlags::empty())
+            .unwrap_err()
+            .same_kind(ErrorKind::Other));
+        assert!(txn.commit().is_err());
+        assert_eq!(100, db.stat().unwrap().entries);
+
+        // Reads fail, and the next one works again.
+        let txn = db.begin_ro_txn().unwrap();
+        let err = txn.get(&db, b"boom").unwrap_err();
+        assert!(err.same_kind(ErrorKind::Other));
+        assert_eq!(None, err.raw_os_error());
+        assert_eq!(b"key42".to_vec(), txn.get(&db, b"key42").unwrap());
+
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            cursor.iter_range(&b"key"[..], &b"boom"[..]).count()
+        }));
+        assert!(res.is_err());
+        assert_eq!(100, cursor.iter_start().count());
+    }
+
+    #[test]
+    fn test_create_exclusive() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+
+        let mut db = Database::new().create_exclusive().open(&dbpath).unwrap();
+        db.update(|txn| txn.put(&db, b"key1", b"val1", WriteFlags::empty()))
+            .unwrap();
+        db.reopen().unwrap();
+        assert_eq!(1, db.len().unwrap());
+        db.close();
+
+        let err = Database::new()
+            .create_exclusive()
+            .open(&dbpath)
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::AlreadyExists, err.kind());
+        assert!(Database::new().open(&dbpath).is_ok());
+    }
+
+    #[test]
+    fn test_page_size() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+
+        {
+            let db = Database::new().set_page_size(1024).open(&dbpath).unwrap();
+            db.update(|txn| {
+                for i in 0..1000 {
+                    let key = format!("/r/{:03}", i);
+                    txn.put(&db, &key, &key, WriteFlags::empty())?;
+                }
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(1024, db.stat().unwrap().psize);
+            db.compact().unwrap();
+        }
+
+        // The page size of an existing database is kept.
+        let db = Database::new().set_page_size(8192).open(&dbpath).unwrap();
+        let stat = db.stat().unwrap();
+        assert_eq!(1024, stat.psize);
+        assert_eq!(1000, stat.entries);
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"/r/999".to_vec(), txn.get(&db, b"/r/999").unwrap());
+        drop(txn);
+        db.close();
+
+        for &size in &[256, 1000, 65536] {
+            let err = Database::new()
+                .set_page_size(size)
+                .open(&dir.path().join("other"))
+                .err()
+                .unwrap();
+            assert_eq!(ErrorKind::InvalidArgument, err.kind());
+        }
+    }
+
+    #[test]
+    fn test_page_size_long_keys() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().set_page_size(512).open(&dbpath).unwrap();
+        assert_eq!(112, db.max_key_len());
+
+        // Keys of the largest length, with values from empty to ones going
+        // on overflow pages.
+        let items: Vec<_> = (0..500)
+            .map(|i| {
+                let mut key = format!("{:03}", i * 7 % 500).into_bytes();
+                key.resize(db.max_key_len(), b'k');
+                (key, vec![b'v'; i % 300])
+            })
+            .collect();
+        db.update(|txn| {
+            for (key, data) in &items {
+                txn.put(&db, key, data, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let mut txn = db.begin_rw_txn().unwrap();
+        let key = vec![b'k'; db.max_key_len() + 1];
+        assert!(txn
+            .put(&db, &key, b"", WriteFlags::empty())
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+        drop(txn);
+        db.close();
+
+        let db = Database::new().open(&dbpath).unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        for (key, data) in &items {
+            assert_eq!(data, &txn.get(&db, key).unwrap());
+        }
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        assert_eq!(items.len(), cursor.iter_start().count());
+        assert_eq!(1008, Database::temporary().unwrap().max_key_len());
+    }
+
+    #[test]
+    fn test_max_key_len_old_limit() {
+        // Keys of up to 2047 bytes used to be accepted on any page size.
+        let key = vec![b'k'; MAX_KEY_SIZE];
+        let db = Database::temporary().unwrap();
+        assert!(db
+            .update(|txn| txn.put(&db, &key, b"", WriteFlags::empty()))
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+
+        let dir = TempDir::new("test").unwrap();
+        let db = Database::new()
+            .set_page_size(32768)
+            .open(&dir.path().join("test"))
+            .unwrap();
+        assert_eq!(MAX_KEY_SIZE, db.max_key_len());
+        db.update(|txn| txn.put(&db, &key, b"value", WriteFlags::empty()))
+            .unwrap();
+        let mut longer = key.clone();
+        longer.push(b'k');
+        assert!(db
+            .update(|txn| txn.put(&db, &longer, b"", WriteFlags::empty()))
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"value".to_vec(), txn.get(&db, &key).unwrap());
     }
-}
 
-#[cfg(test)]
-mod test {
-    use tempdir::TempDir;
+    #[test]
+    fn test_created_at() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        let created_at = db.stat().unwrap().created_at;
+        let age = SystemTime::now()
+            .duration_since(created_at.unwrap())
+            .unwrap();
+        assert!(age < Duration::from_secs(5));
 
-    use super::*;
-    use error::ErrorKind;
-    use transaction::{Transaction, WriteFlags};
+        // Commits, compaction and reopening leave it alone.
+        db.set_meta(b"meta").unwrap();
+        assert_eq!(created_at, db.stat().unwrap().created_at);
+        db.compact().unwrap();
+        drop(db);
+        let db = Database::new().open(&dbpath).unwrap();
+        assert_eq!(created_at, db.stat().unwrap().created_at);
+        assert_eq!(Some(b"meta".to_vec()), db.get_meta().unwrap());
+
+        // A new file gets a new creation time.
+        fs::remove_file(&dbpath).unwrap();
+        thread::sleep(Duration::from_millis(1100));
+        let db = Database::new().open(&dbpath).unwrap();
+        assert!(created_at < db.stat().unwrap().created_at);
+    }
 
     #[test]
-    fn test_open() {
+    fn test_created_at_unrecorded() {
+        use std::fs::OpenOptions;
+        use std::io::{Seek, SeekFrom};
+
         let dir = TempDir::new("test").unwrap();
         let dbpath = dir.path().join("test");
+        Database::new()
+            .open(&dbpath)
+            .unwrap()
+            .set_meta(b"meta")
+            .unwrap();
 
-        assert!(Database::new().open(&dbpath).is_ok());
+        // Files written before the creation time was recorded have zeros
+        // in its place, after the magic, version, flags and page size.
+        let mut file = OpenOptions::new().write(true).open(&dbpath).unwrap();
+        file.seek(SeekFrom::Start(PAGE_HEADER_SIZE as u64 + 16))
+            .unwrap();
+        file.write_all(&[0; 8]).unwrap();
+        drop(file);
+
+        let mut db = Database::new().open(&dbpath).unwrap();
+        assert_eq!(None, db.stat().unwrap().created_at);
+        db.compact_and_reopen().unwrap();
+        assert_eq!(None, db.stat().unwrap().created_at);
+        assert_eq!(Some(b"meta".to_vec()), db.get_meta().unwrap());
     }
 
     #[test]
-    fn test_ro_txn() {
+    fn test_set_cache_size() {
         let dir = TempDir::new("test").unwrap();
         let dbpath = dir.path().join("test");
         let db = Database::new().open(&dbpath).unwrap();
-        let txn = db.begin_ro_txn().unwrap();
-        let res = txn.get(&db, &"/non-existant".to_string());
-        assert!(res.is_err());
-        if let Err(err) = res {
-            assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..2000u32 {
+            txn.put(&db, &i.to_be_bytes(), &[0; 100], WriteFlags::empty())
+                .unwrap();
         }
+        txn.commit().unwrap();
+
+        let read_all = |db: &Database| {
+            let txn = db.begin_ro_txn().unwrap();
+            for i in 0..2000u32 {
+                assert_eq!(
+                    vec![0; 100],
+                    txn.get(db, &i.to_be_bytes()).unwrap()
+                );
+            }
+        };
+        db.set_cache_size(4);
+        assert_eq!(4, db.stat().unwrap().max_cache);
+        read_all(&db);
+        assert!(db.stat().unwrap().cache_size <= 8);
+
+        db.set_cache_size(1000);
+        assert_eq!(1000, db.stat().unwrap().max_cache);
+        read_all(&db);
+        let cached = db.stat().unwrap().cache_size;
+        assert!(cached > 8);
+        let reads = db.stat().unwrap().reads;
+        read_all(&db);
+        assert_eq!(reads, db.stat().unwrap().reads);
+        assert_eq!(cached, db.stat().unwrap().cache_size);
     }
 
     #[test]
-    fn test_rw_txn() {
+    fn test_cache_hit_ratio() {
+        assert_eq!(0.0, Stat::default().cache_hit_ratio());
+
         let dir = TempDir::new("test").unwrap();
         let dbpath = dir.path().join("test");
         let db = Database::new().open(&dbpath).unwrap();
-        let mut rwtxn = db.begin_rw_txn().unwrap();
-        let res = rwtxn.put(
-            &db,
-            &"/test-key".to_string(),
-            &"Some data".to_string(),
-            WriteFlags::empty(),
-        );
-        assert!(res.is_ok());
-        assert!(rwtxn.commit().is_ok());
-        let rotxn = db.begin_ro_txn().unwrap();
-        let res = rotxn.get(&db, &"/test-key".to_string());
-        assert!(res.is_ok());
-        if let Ok(data) = res {
-            assert_eq!(data, "Some data".as_bytes());
+        db.update(|txn| {
+            for i in 0..1000 {
+                let key = format!("/r/{}", i);
+                txn.put(&db, &key, &key, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        for _ in 0..10 {
+            txn.get(&db, b"/r/500").unwrap();
         }
+        let stat = db.stat().unwrap();
+        assert!(stat.hits > 0);
+        let ratio = stat.cache_hit_ratio();
+        assert!(ratio > 0.0 && ratio <= 1.0);
     }
 
     #[test]
-    fn test_multi_rw_txn() {
+    fn test_len() {
         let dir = TempDir::new("test").unwrap();
         let dbpath = dir.path().join("test");
         let db = Database::new().open(&dbpath).unwrap();
-        for i in 0..1000 {
-            let mut rwtxn = db.begin_rw_txn().unwrap();
-            assert!(rwtxn
-                .put(
-                    &db,
-                    &format!("/test-key-{}", i),
-                    &"Some data".to_string(),
-                    WriteFlags::empty(),
-                )
-                .is_ok());
-            assert!(rwtxn.commit().is_ok());
-        }
-        for i in 0..1000 {
-            let rotxn = db.begin_ro_txn().unwrap();
-            assert!(rotxn.get(&db, &format!("/test-key-{}", i)).is_ok());
-        }
+        assert!(db.is_empty().unwrap());
+        assert_eq!(0, db.len().unwrap());
+
+        db.update(|txn| {
+            for i in 0..100 {
+                let key = format!("/r/{}", i);
+                txn.put(&db, &key, &key, WriteFlags::empty())?;
+            }
+            txn.put(&db, b"/r/1", b"again", WriteFlags::empty())?;
+            txn.del(&db, b"/r/2")
+        })
+        .unwrap();
+        assert!(!db.is_empty().unwrap());
+        assert_eq!(99, db.len().unwrap());
+
+        let db = Database::new().open(&dbpath).unwrap();
+        assert_eq!(99, db.len().unwrap());
+
+        db.set_meta(b"meta").unwrap();
+        assert_eq!(100, db.len().unwrap());
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(99, txn.count_range(&db, ..).unwrap());
     }
 
     #[test]
@@ -322,12 +2827,32 @@ mod test {
                 .open(dbpath.as_path())
                 .unwrap();
 
-            // XXX: btree doesn't error out here.
-            //assert!(db.begin_rw_txn().is_err());
+            assert!(db.begin_rw_txn().is_err());
             assert!(db.begin_ro_txn().is_ok());
         }
     }
 
+    #[test]
+    fn test_read_only_rejects_rw_txn() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        Database::new().open(dbpath.as_path()).unwrap();
+
+        let db = Database::new()
+            .set_flags(DatabaseFlags::READ_ONLY)
+            .open(dbpath.as_path())
+            .unwrap();
+        let err = db.begin_rw_txn().err().unwrap();
+        assert_eq!(ErrorKind::PermissionDenied, err.kind());
+        assert_eq!("txn_begin", err.op_name());
+        let err = db.begin_rw_txn_bounded(100).err().unwrap();
+        assert_eq!(ErrorKind::PermissionDenied, err.kind());
+        let err = db
+            .update(|txn| txn.put(&db, b"key", b"val", WriteFlags::empty()))
+            .unwrap_err();
+        assert_eq!(ErrorKind::PermissionDenied, err.kind());
+    }
+
     #[test]
     fn test_close_database() {
         let dir = TempDir::new("test").unwrap();
@@ -355,6 +2880,201 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_estimate_stored_size() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        let small = db.estimate_stored_size(b"key", b"val");
+        assert!(small >= 6);
+        let larger = db.estimate_stored_size(b"key", &[0; 100]);
+        assert!(larger >= small + 97);
+
+        let psize = db.stat().unwrap().psize as usize;
+        let big = db.estimate_stored_size(b"key", &vec![0; psize * 3]);
+        assert!(big >= psize * 3 + 3);
+        assert!(big <= psize * 5);
+    }
+
+    #[test]
+    fn test_length_distribution() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        let dist = db.length_distribution().unwrap();
+        assert_eq!(0, dist.values.count);
+        assert_eq!(0, dist.values.p50());
+
+        db.update(|txn| {
+            for i in 0..100 {
+                let len = match i {
+                    0..=49 => 10,
+                    50..=89 => 100,
+                    _ => 1000,
+                };
+                let key = format!("/r/{:03}", i);
+                txn.put(&db, &key, &vec![0; len], WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        db.set_meta(b"meta").unwrap();
+
+        let dist = db.length_distribution().unwrap();
+        assert_eq!(100, dist.keys.count);
+        assert_eq!(100, dist.keys.buckets[3]);
+        assert_eq!(7, dist.keys.p99());
+        assert_eq!(100, dist.values.count);
+        assert_eq!(14500, dist.values.total);
+        assert_eq!(145.0, dist.values.mean());
+        assert_eq!(15, dist.values.p50());
+        assert_eq!(127, dist.values.p90());
+        assert_eq!(1023, dist.values.p99());
+    }
+
+    #[test]
+    fn test_compaction_estimate() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        let psize = u64::from(db.stat().unwrap().psize);
+
+        db.update(|txn| txn.put(&db, b"key", b"val", WriteFlags::empty()))
+            .unwrap();
+        let fresh = db.compaction_estimate().unwrap();
+        assert_eq!(db.file_size().unwrap(), fresh.file_size);
+        assert!(fresh.reclaimable <= psize);
+
+        for i in 0..10 {
+            db.update(|txn| {
+                for j in 0..100 {
+                    let key = format!("/r/{}/{:03}", i, j);
+                    txn.put(&db, &key, &vec![0; 100], WriteFlags::empty())?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        }
+        db.update(|txn| {
+            for i in 0..10 {
+                for j in 0..95 {
+                    txn.del(&db, &format!("/r/{}/{:03}", i, j))?;
+                }
+            }
+            Ok(())
+        })
+        .unwrap();
+        let est = db.compaction_estimate().unwrap();
+        assert!(est.reclaimable > est.file_size / 2);
+        assert_eq!(est.file_size, est.compacted_size + est.reclaimable);
+
+        db.compact().unwrap();
+        let mut db = db;
+        db.reopen().unwrap();
+        assert!(db.file_size().unwrap() <= est.compacted_size);
+    }
+
+    #[test]
+    fn test_warm_keys() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+
+        let db = Database::new().open(&dbpath).unwrap();
+        db.update(|txn| {
+            for i in 0..1000 {
+                let key = format!("/r/{:03}", i);
+                txn.put(&db, &key, &key, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        db.close();
+
+        let db = Database::new()
+            .warm_keys(vec!["/r/001", "/r/500", "/r/999", "/missing"])
+            .open(&dbpath)
+            .unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert!(txn.is_cached(&db, b"/r/001").unwrap());
+        assert!(txn.is_cached(&db, b"/r/500").unwrap());
+        assert!(txn.is_cached(&db, b"/r/999").unwrap());
+        assert_eq!(b"/r/500".to_vec(), txn.get(&db, b"/r/500").unwrap());
+        assert!(!txn.is_cached(&db, b"/r/250").unwrap());
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        db.set_meta(b"meta").unwrap();
+
+        let items = (0..100)
+            .map(|i| {
+                let key = format!("/r/{:02}", i).into_bytes();
+                (key.clone(), key)
+            })
+            .collect::<Vec<_>>();
+        db.update(|txn| {
+            for (key, val) in items.iter().rev() {
+                txn.put(&db, key, val, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(items, (&db).into_iter().collect::<Vec<_>>());
+        let mut count = 0;
+        for (key, val) in &db {
+            assert_eq!(key, val);
+            count += 1;
+        }
+        assert_eq!(100, count);
+    }
+
+    #[test]
+    fn test_poll_changes() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().change_feed(true).open(&dbpath).unwrap();
+
+        db.update(|txn| {
+            txn.put(&db, b"key1", b"val1", WriteFlags::empty())?;
+            txn.put(&db, b"key2", b"val2", WriteFlags::empty())?;
+            txn.del(&db, b"key1")
+        })
+        .unwrap();
+        db.set_meta(b"meta").unwrap();
+        db.update(|txn| txn.put(&db, b"key2", b"val3", WriteFlags::empty()))
+            .unwrap();
+
+        let (changes, high) = db.poll_changes(0, 10).unwrap();
+        assert_eq!(
+            vec![
+                (1, ChangeOp::Put, b"key1".to_vec(), b"val1".to_vec()),
+                (2, ChangeOp::Put, b"key2".to_vec(), b"val2".to_vec()),
+                (3, ChangeOp::Del, b"key1".to_vec(), Vec::new()),
+                (4, ChangeOp::Put, b"key2".to_vec(), b"val3".to_vec()),
+            ],
+            changes
+        );
+        assert_eq!(4, high);
+        assert_eq!((Vec::new(), 4), db.poll_changes(high, 10).unwrap());
+
+        let (changes, high) = db.poll_changes(1, 2).unwrap();
+        assert_eq!(vec![2, 3], changes.iter().map(|c| c.0).collect::<Vec<_>>());
+        assert_eq!(3, high);
+        db.close();
+
+        // Changes aren't recorded unless the feed is enabled.
+        let db = Database::new().open(&dbpath).unwrap();
+        db.update(|txn| txn.put(&db, b"key3", b"val3", WriteFlags::empty()))
+            .unwrap();
+        assert_eq!(4, db.poll_changes(0, 10).unwrap().1);
+    }
+
     #[test]
     fn test_reopen() {
         let dir = TempDir::new("test").unwrap();
@@ -369,6 +3089,205 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_try_clone() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().set_cache_size(16).open(&dbpath).unwrap();
+        let clone = db.try_clone().unwrap();
+        assert_ne!(db.dbi(), clone.dbi());
+        assert_eq!(16, clone.stat().unwrap().max_cache);
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.commit().unwrap();
+        db.close();
+
+        let txn = clone.begin_ro_txn().unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&clone, b"key1").unwrap());
+    }
+
+    #[test]
+    fn test_temporary() {
+        let db = Database::temporary().unwrap();
+        let path = db.path().to_path_buf();
+        assert!(path.exists());
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.commit().unwrap();
+
+        // Locking it from another handle leaves a lock file behind.
+        drop(Database::new().lock_exclusive().open(&path).unwrap());
+        assert!(lock_path(&path).exists());
+
+        let other = Database::temporary().unwrap();
+        assert_ne!(path, other.path());
+        drop(db);
+        assert!(!path.exists());
+        assert!(!lock_path(&path).exists());
+        assert!(other.path().exists());
+    }
+
+    #[test]
+    fn test_maintain() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let mut db = Database::new().open(dbpath.as_path()).unwrap();
+        db.update(|txn| txn.put(&db, b"key1", b"val1", WriteFlags::empty()))
+            .unwrap();
+
+        assert_eq!(42, db.maintain(|_| Ok(42)).unwrap());
+        db.maintain(|db| db.compact()).unwrap();
+        db.update(|txn| txn.put(&db, b"key2", b"val2", WriteFlags::empty()))
+            .unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert_eq!(b"val2".to_vec(), txn.get(&db, b"key2").unwrap());
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        assert!(db.verify().unwrap().is_ok());
+
+        for round in 0..4u32 {
+            let mut txn = db.begin_rw_txn().unwrap();
+            for i in 0..2000u32 {
+                let key = format!("key{:05}", (i * 7919 + round) % 5000);
+                let val = if i % 250 == 0 {
+                    vec![round as u8; 9000]
+                } else {
+                    format!("val{}", i).into_bytes()
+                };
+                txn.put(&db, &key, &val, WriteFlags::empty()).unwrap();
+            }
+            for i in 0..500u32 {
+                let key = format!("key{:05}", (i * 13 + round) % 5000);
+                let _ = txn.del_opt(&db, &key).unwrap();
+            }
+            txn.commit().unwrap();
+        }
+
+        let report = db.verify().unwrap();
+        let stat = db.stat().unwrap();
+        assert!(report.is_ok(), "{:?}", report.corrupt_pages);
+        assert_eq!(4, report.meta_pages);
+        assert_eq!(stat.branch_pages, report.branch_pages);
+        assert_eq!(stat.leaf_pages, report.leaf_pages);
+        // btree doesn't count overflow pages going out of use, every large
+        // value takes three of them.
+        let large = (&db).into_iter().filter(|(_, val)| val.len() > 10).count();
+        assert_eq!(large as u32 * 3, report.overflow_pages);
+        let file_size = fs::metadata(&dbpath).unwrap().len();
+        assert_eq!(file_size / u64::from(stat.psize), report.pages.into());
+    }
+
+    #[test]
+    fn test_verify_corrupt() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let psize = {
+            let db = Database::new().open(&dbpath).unwrap();
+            for i in 0..3 {
+                let mut txn = db.begin_rw_txn().unwrap();
+                txn.put(&db, b"key", &[i], WriteFlags::empty()).unwrap();
+                txn.commit().unwrap();
+            }
+            db.stat().unwrap().psize as usize
+        };
+
+        let mut data = fs::read(&dbpath).unwrap();
+        let field = |data: &[u8], offset: usize| {
+            let mut buf = [0u8; 4];
+            buf.copy_from_slice(&data[offset..offset + 4]);
+            u32::from_ne_bytes(buf)
+        };
+        let meta_pages: Vec<usize> = (1..data.len() / psize)
+            .filter(|pgno| field(&data, pgno * psize + 4) == 0x08)
+            .collect();
+        assert_eq!(3, meta_pages.len());
+        let last_meta = *meta_pages.last().unwrap();
+        let root = field(&data, last_meta * psize + PAGE_HEADER_SIZE + 4);
+
+        // Break the checksum of an old meta page, and the page number of
+        // the current root page.
+        data[meta_pages[0] * psize + PAGE_HEADER_SIZE] ^= 0xff;
+        data[root as usize * psize] ^= 0xff;
+        fs::write(&dbpath, &data).unwrap();
+
+        let db = Database::new().open(&dbpath).unwrap();
+        let report = db.verify().unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(vec![meta_pages[0] as u32, root], report.corrupt_pages);
+    }
+
+    #[test]
+    fn test_dump() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        let mut dump = Vec::new();
+        assert_eq!(0, db.dump(&mut dump).unwrap());
+        assert!(dump.is_empty());
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..100u32 {
+            let val = vec![i as u8; i as usize * 100];
+            txn.put(&db, &format!("key{:03}", i), &val, WriteFlags::empty())
+                .unwrap();
+        }
+        txn.commit().unwrap();
+        db.set_meta(b"meta").unwrap();
+
+        assert_eq!(101, db.dump(&mut dump).unwrap());
+        let mut records = 0;
+        let mut rest = &dump[..];
+        while !rest.is_empty() {
+            for _ in 0..2 {
+                let mut len = [0u8; 4];
+                len.copy_from_slice(&rest[..4]);
+                rest = &rest[4 + u32::from_be_bytes(len) as usize..];
+            }
+            records += 1;
+        }
+        assert_eq!(101, records);
+        assert!(dump.starts_with(b"\0\0\0\x06\0meta\0\0\0\0\x04meta"));
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = TempDir::new("test").unwrap();
+        let src = Database::new().open(&dir.path().join("src")).unwrap();
+        let mut txn = src.begin_rw_txn().unwrap();
+        for i in 0..500u32 {
+            let val = vec![i as u8; i as usize * 20];
+            txn.put(&src, &format!("key{:03}", i), &val, WriteFlags::empty())
+                .unwrap();
+        }
+        txn.commit().unwrap();
+        src.set_meta(b"meta").unwrap();
+        let mut dump = Vec::new();
+        assert_eq!(501, src.dump(&mut dump).unwrap());
+
+        let dest = Database::new().open(&dir.path().join("dest")).unwrap();
+        assert_eq!(501, dest.load(&dump[..]).unwrap());
+        let items = |db: &Database| {
+            let txn = db.begin_ro_txn().unwrap();
+            let mut cursor = txn.open_ro_cursor(db).unwrap();
+            cursor.iter_raw().collect::<Vec<_>>()
+        };
+        assert_eq!(items(&src), items(&dest));
+        assert_eq!(Some(b"meta".to_vec()), dest.get_meta().unwrap());
+
+        // A truncated dump stores nothing.
+        let other = Database::new().open(&dir.path().join("other")).unwrap();
+        let err = other.load(&dump[..dump.len() - 1]).unwrap_err();
+        assert_eq!(ErrorKind::InvalidArgument, err.kind());
+        assert!(items(&other).is_empty());
+    }
+
     #[test]
     fn test_compact() {
         let dir = TempDir::new("test").unwrap();
@@ -377,7 +3296,7 @@ mod test {
         {
             assert!(db.begin_rw_txn().is_ok());
         }
-        assert!(db.compact().is_ok());
+        assert!(db.compact().unwrap().reopen_required);
         {
             match db.begin_ro_txn() {
                 Ok(_) => panic!("begin_rw_txn succeeded after compact"),
@@ -401,4 +3320,155 @@ mod test {
             assert!(db.begin_rw_txn().is_ok());
         }
     }
+
+    #[test]
+    fn test_auto_reopen_on_stale() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new()
+            .auto_reopen_on_stale()
+            .open(dbpath.as_path())
+            .unwrap();
+        db.update(|txn| txn.put(&db, b"key1", b"val1", WriteFlags::empty()))
+            .unwrap();
+
+        assert!(db.compact().unwrap().reopen_required);
+        db.update(|txn| txn.put(&db, b"key2", b"val2", WriteFlags::empty()))
+            .unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert_eq!(b"val2".to_vec(), txn.get(&db, b"key2").unwrap());
+        drop(txn);
+
+        // Compacted through another handle.
+        let other = db.try_clone().unwrap();
+        other.compact().unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"val2".to_vec(), txn.get(&db, b"key2").unwrap());
+        drop(txn);
+
+        let plain = Database::new().open(dbpath.as_path()).unwrap();
+        plain.compact().unwrap();
+        let err = plain.begin_ro_txn().err().unwrap();
+        assert_eq!(ErrorKind::StaleHandle, err.kind());
+    }
+
+    #[test]
+    fn test_auto_reopen_open_txn() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new()
+            .auto_reopen_on_stale()
+            .open(dbpath.as_path())
+            .unwrap();
+        db.update(|txn| txn.put(&db, b"key1", b"val1", WriteFlags::empty()))
+            .unwrap();
+
+        let old = db.begin_ro_txn().unwrap();
+        let stale = db.dbi();
+        let other = db.try_clone().unwrap();
+        other.compact().unwrap();
+        other
+            .update(|txn| {
+                txn.put(&other, b"key2", b"val2", WriteFlags::empty())
+            })
+            .unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert_ne!(stale, db.dbi());
+        assert_eq!(b"val2".to_vec(), txn.get(&db, b"key2").unwrap());
+        drop(txn);
+
+        // The transaction started before the reopen keeps its handle.
+        assert_eq!(b"val1".to_vec(), old.get(&db, b"key1").unwrap());
+        let err = old.get(&db, b"key2").unwrap_err();
+        assert_eq!(ErrorKind::NotFound, err.kind());
+        let mut cursor = old.open_ro_cursor(&db).unwrap();
+        let keys: Vec<_> = cursor.iter().map(|(key, _)| key).collect();
+        assert_eq!(vec![b"key1".to_vec()], keys);
+        let err = old.get(&other, b"key1").unwrap_err();
+        assert_eq!(ErrorKind::InvalidArgument, err.kind());
+        assert_eq!(vec![stale], *db.retired.lock().unwrap());
+
+        // Closed when its last transaction ends.
+        drop(cursor);
+        drop(old);
+        assert!(db.retired.lock().unwrap().is_empty());
+
+        // And right away when there is none.
+        other.compact().unwrap();
+        db.begin_ro_txn().unwrap();
+        assert!(db.retired.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_auto_reopen_release_while_pinned() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Arc::new(
+            Database::new()
+                .auto_reopen_on_stale()
+                .open(dbpath.as_path())
+                .unwrap(),
+        );
+        let old = db.begin_ro_txn().unwrap();
+        db.try_clone().unwrap().compact().unwrap();
+        db.begin_ro_txn().unwrap();
+        assert_eq!(1, db.retired.lock().unwrap().len());
+
+        // The last transaction on the retired handle ends while another
+        // thread is using the current one.
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let pinned = {
+            let db = db.clone();
+            thread::spawn(move || {
+                db.with_handle(|_| {
+                    entered_tx.send(()).unwrap();
+                    done_rx.recv().unwrap();
+                })
+            })
+        };
+        entered_rx.recv().unwrap();
+        drop(old);
+        assert_eq!(1, db.retired.lock().unwrap().len());
+        done_tx.send(()).unwrap();
+        pinned.join().unwrap();
+
+        // Closed when the next transaction ends, on the current handle.
+        db.begin_ro_txn().unwrap();
+        assert!(db.retired.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_compact_and_reopen() {
+        use std::fs::OpenOptions;
+        use std::os::unix::io::IntoRawFd;
+
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let mut db = Database::new().open(dbpath.as_path()).unwrap();
+        db.update(|txn| txn.put(&db, b"key1", b"val1", WriteFlags::empty()))
+            .unwrap();
+
+        db.compact_and_reopen().unwrap();
+        db.update(|txn| txn.put(&db, b"key2", b"val2", WriteFlags::empty()))
+            .unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert_eq!(b"val2".to_vec(), txn.get(&db, b"key2").unwrap());
+        drop(txn);
+
+        drop(db);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&dbpath)
+            .unwrap();
+        let mut db = Database::new().open_fd(file.into_raw_fd()).unwrap();
+        assert!(db
+            .compact_and_reopen()
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+    }
 }
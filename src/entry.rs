@@ -53,13 +53,25 @@ impl Entry {
     }
 
     pub fn get_value(&self) -> Vec<u8> {
-        let s = unsafe {
-            slice::from_raw_parts(
-                self.btval.data as *const u8,
-                self.btval.size as usize,
-            )
-        };
-        s.to_vec()
+        self.as_slice().to_vec()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        if self.btval.data.is_null() {
+            return &[];
+        }
+        unsafe {
+            slice::from_raw_parts(self.btval.data as *const u8, self.btval.size)
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.btval.size
+    }
+
+    pub fn get_value_prefix(&self, max: usize) -> Vec<u8> {
+        let s = self.as_slice();
+        s[..s.len().min(max)].to_vec()
     }
 
     pub fn as_ptr(&self) -> *const u8 {
@@ -1,5 +1,8 @@
+use std::any::Any;
+use std::cell::RefCell;
 use std::error::Error as StdError;
 use std::result;
+use std::sync::Arc;
 use std::{fmt, io};
 
 use errno;
@@ -12,28 +15,40 @@ use cursor::Position;
 #[derive(Clone, Eq, PartialEq)]
 pub(crate) enum Op {
     Compact,          // btree_compact
+    CurDel,           // btree_cursor_del
     CurGet(Position), // btree_cursor_get
+    CurPrev,          // btree_cursor_get(BT_PREV)
     CurOpen,          // btree_txn_cursor_open
     Open,             // btree_open
     Revert,           // btree_revert
+    Stat,             // btree_stat
     Sync,             // btree_sync
     TxnBegin,         // btree_txn_begin
     TxnCommit,        // btree_txn_commit
     TxnDel,           // btree_txn_del
     TxnGet,           // btree_txn_get
     TxnPut,           // btree_txn_put
+    Verify,           // btree_verify
     Other(String),
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Categories of errors, see `Error::kind`.
+///
+/// More kinds may be added in the future, so matches on them need a
+/// wildcard `_ =>` arm.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
 pub enum ErrorKind {
     AlreadyExists,
     BadHandle,
     Busy,
+    FileTooLarge,
     InputOutput,
     InvalidArgument,
+    NoSpace,
     NotFound,
     PermissionDenied,
+    ReadOnlyFilesystem,
     StaleHandle,
     Other,
 }
@@ -43,29 +58,88 @@ pub struct Error {
     errno: errno::Errno,
     kind: ErrorKind,
     op: Op,
+    // Replaces the description of the operation, see `Error::context`.
+    msg: Option<String>,
+    // The I/O error this one was converted from, see `Error::from_io`.
+    source: Option<Arc<io::Error>>,
 }
 
-// If the error kind is not a wildcard value, that's good enough for us,
-// otherwise test errno values regardless of the context if they're set.
-// If all fails, resort to comparing operation types.
+/// Errors are equal when they have the same kind and errno and come from
+/// the same operation.  The message set with `Error::context` and the
+/// source aren't compared.
+///
+/// Equality used to depend on which of the errors had a wildcard kind,
+/// which made comparisons with errors made up from an `ErrorKind`
+/// unpredictable.  Use `Error::same_kind` to only compare kinds.
 impl PartialEq for Error {
     fn eq(&self, other: &Error) -> bool {
-        if self.kind == ErrorKind::Other || other.kind == ErrorKind::Other {
-            if self.errno.0 > 0 && other.errno.0 > 0 {
-                self.errno == other.errno
-            } else {
-                self.op == other.op
-            }
-        } else {
-            self.kind == other.kind
-        }
+        self.kind == other.kind
+            && self.errno == other.errno
+            && self.op == other.op
     }
 }
 
 impl Eq for Error {}
 
 impl StdError for Error {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_ref()
+            .map(|err| &**err as &(dyn StdError + 'static))
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}: {}", self.message(), self.errno)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.errno.0 != 0 {
+            write!(fmt, "{}: {}", self.message(), self.errno)
+        } else {
+            write!(fmt, "{}", self.message())
+        }
+    }
+}
+
+impl From<Error> for ErrorKind {
+    fn from(error: Error) -> Self {
+        error.kind
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        if let Some(source) = error.source {
+            return Arc::try_unwrap(source).unwrap_or_else(
+                |source| match source.raw_os_error() {
+                    Some(errno) => io::Error::from_raw_os_error(errno),
+                    None => io::Error::new(source.kind(), source.to_string()),
+                },
+            );
+        }
+        match (error.msg, error.op) {
+            (Some(errstr), _) | (None, Op::Other(errstr)) => {
+                io::Error::other(errstr)
+            }
+            _ => io::Error::from_raw_os_error(error.errno.0),
+        }
+    }
+}
+
+/// Errors that didn't come from the OS are reported with the closest
+/// errno value, so that they get a matching error kind.
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::from_io(err, Op::Other("I/O operation failed".to_string()))
+    }
+}
+
+impl Error {
+    fn message(&self) -> &str {
         match self.op {
             _ if self.kind == ErrorKind::BadHandle => {
                 "Failed to perform an operation on a bad \
@@ -77,16 +151,22 @@ impl StdError for Error {
                 "Failed to perform an operation on a stale \
                  database handle"
             }
+            _ if self.msg.is_some() => self.msg.as_ref().unwrap(),
             Op::Compact => "Failed to compact the database",
+            Op::CurDel => "Failed to delete the key at the cursor",
             Op::CurGet(ref position) => match position {
                 Position::Current => "Failed to get data at the cursor",
                 Position::Exact => "Failed to get data exactly at the cursor",
                 Position::First => "Failed to get the first key",
                 Position::Next => "Failed to get the next key",
+                Position::Last => "Failed to get the last key",
+                Position::GetCurrent => "Failed to get the current key",
             },
+            Op::CurPrev => "Failed to get the previous key",
             Op::CurOpen => "Failed to create a new cursor",
             Op::Open => "Failed to open the database",
             Op::Revert => "Failed to revert last change",
+            Op::Stat => "Failed to get database statistics",
             Op::Sync => "Failed to sync the database",
             Op::TxnBegin => "Failed to start a transaction",
             Op::TxnCommit => "Failed to commit a transaction",
@@ -99,55 +179,57 @@ impl StdError for Error {
                 }
             }
             Op::TxnPut => "Failed to store value",
+            Op::Verify => "Failed to verify the database",
             Op::Other(ref errstr) => errstr.as_str(),
         }
     }
-}
-
-impl fmt::Debug for Error {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "{}: {}", self.description(), self.errno)
-    }
-}
-
-impl fmt::Display for Error {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "{}", self.description())
-    }
-}
-
-impl From<Error> for ErrorKind {
-    fn from(error: Error) -> Self {
-        error.kind
-    }
-}
 
-impl From<Error> for io::Error {
-    fn from(error: Error) -> Self {
-        match error.op {
-            Op::Other(ref errstr) => {
-                io::Error::new(io::ErrorKind::Other, errstr.clone())
-            }
-            _ => io::Error::from_raw_os_error(error.errno.0),
-        }
+    pub(crate) fn new(op: Op) -> Self {
+        Self::from_errno(errno::errno().0, op)
     }
-}
 
-impl Error {
-    pub(crate) fn new(op: Op) -> Self {
-        let errno = errno::errno();
-        let kind = match errno.0 {
+    /// Creates an error for the given errno value rather than the one
+    /// left behind by the last failed call.
+    pub(crate) fn from_errno(errno: libc::c_int, op: Op) -> Self {
+        let kind = match errno {
             libc::EEXIST => ErrorKind::AlreadyExists,
             libc::EBADF => ErrorKind::BadHandle,
             libc::EBUSY => ErrorKind::Busy,
+            libc::EFBIG => ErrorKind::FileTooLarge,
             libc::EIO => ErrorKind::InputOutput,
             libc::EINVAL => ErrorKind::InvalidArgument,
+            libc::ENOSPC => ErrorKind::NoSpace,
             libc::ENOENT => ErrorKind::NotFound,
-            libc::EPERM => ErrorKind::PermissionDenied,
+            libc::EACCES | libc::EPERM => ErrorKind::PermissionDenied,
+            libc::EROFS => ErrorKind::ReadOnlyFilesystem,
             libc::ESTALE => ErrorKind::StaleHandle,
             _ => ErrorKind::Other,
         };
-        Self { errno, kind, op }
+        Self {
+            errno: errno::Errno(errno),
+            kind,
+            op,
+            msg: None,
+            source: None,
+        }
+    }
+
+    /// Creates an error for an I/O error met while performing the
+    /// operation, and keeps it as the source of the error.
+    pub(crate) fn from_io(err: io::Error, op: Op) -> Self {
+        let errno = err.raw_os_error().unwrap_or(match err.kind() {
+            io::ErrorKind::NotFound => libc::ENOENT,
+            io::ErrorKind::PermissionDenied => libc::EACCES,
+            io::ErrorKind::AlreadyExists => libc::EEXIST,
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => {
+                libc::EINVAL
+            }
+            _ => libc::EIO,
+        });
+        Self {
+            source: Some(Arc::new(err)),
+            ..Self::from_errno(errno, op)
+        }
     }
 
     pub(crate) fn other(errstr: String) -> Self {
@@ -155,21 +237,72 @@ impl Error {
             errno: errno::Errno(0),
             kind: ErrorKind::Other,
             op: Op::Other(errstr),
+            msg: None,
+            source: None,
+        }
+    }
+
+    /// Replaces the error message, keeping the error kind, errno and
+    /// the failed operation.
+    pub(crate) fn context(self, errstr: String) -> Self {
+        Self {
+            msg: Some(errstr),
+            ..self
         }
     }
 
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
+
+    /// Returns whether the error is of the given kind, regardless of its
+    /// errno and the operation that failed.
+    pub fn same_kind(&self, kind: ErrorKind) -> bool {
+        self.kind == kind
+    }
+
+    /// Returns the OS error number behind the error, if any.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        if self.errno.0 != 0 {
+            Some(self.errno.0)
+        } else {
+            None
+        }
+    }
+
+    /// Returns a short name of the operation that failed, such as
+    /// `"txn_put"`, or `"other"` for errors that didn't come from btree.
+    pub fn op_name(&self) -> &'static str {
+        match self.op {
+            Op::Compact => "compact",
+            Op::CurDel => "cursor_del",
+            Op::CurGet(_) | Op::CurPrev => "cursor_get",
+            Op::CurOpen => "cursor_open",
+            Op::Open => "open",
+            Op::Revert => "revert",
+            Op::Stat => "stat",
+            Op::Sync => "sync",
+            Op::TxnBegin => "txn_begin",
+            Op::TxnCommit => "txn_commit",
+            Op::TxnDel => "txn_del",
+            Op::TxnGet => "txn_get",
+            Op::TxnPut => "txn_put",
+            Op::Verify => "verify",
+            Op::Other(_) => "other",
+        }
+    }
 }
 
-// This should be used only for the PartialEq situations
+/// Makes up an error of the given kind, without an errno or a failed
+/// operation.  It only equals errors made up the same way.
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Self {
         Self {
             errno: errno::Errno(0),
-            kind: kind,
+            kind,
             op: Op::Other("Not an actual error".to_string()),
+            msg: None,
+            source: None,
         }
     }
 }
@@ -182,22 +315,151 @@ impl PartialEq<ErrorKind> for Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+thread_local! {
+    // A panic caught in the key comparator during the last btree call on
+    // this thread, see `DatabaseBuilder::set_comparator`.
+    static COMPARATOR_PANIC: RefCell<Option<Box<dyn Any + Send>>> =
+        RefCell::new(None);
+}
+
+/// Keeps a panic caught in the key comparator for the btree call it
+/// happened in to report, unless one is kept already.
+pub(crate) fn set_comparator_panic(payload: Box<dyn Any + Send>) {
+    COMPARATOR_PANIC.with(|panic| {
+        panic.borrow_mut().get_or_insert(payload);
+    })
+}
+
+/// Takes the panic caught in the key comparator during the last btree
+/// call, if any.
+pub(crate) fn take_comparator_panic() -> Option<Box<dyn Any + Send>> {
+    COMPARATOR_PANIC.with(|panic| panic.borrow_mut().take())
+}
+
 pub(crate) fn clear_error() {
-    errno::set_errno(errno::Errno(0))
+    errno::set_errno(errno::Errno(0));
+    take_comparator_panic();
+}
+
+/// Returns the error reporting a panic of the key comparator during the
+/// btree call, if there was one.
+fn comparator_error(op: &Op) -> Option<Error> {
+    take_comparator_panic().map(|_| {
+        Error::from_errno(0, op.clone())
+            .context("The key comparator panicked".to_string())
+    })
 }
 
 pub(crate) fn result_from_int(res: libc::c_int, op: Op) -> Result<()> {
-    if res == ffi::BT_SUCCESS {
-        return Ok(());
+    if let Some(err) = comparator_error(&op) {
+        Err(err)
+    } else if res == ffi::BT_SUCCESS {
+        Ok(())
     } else {
         Err(Error::new(op))
     }
 }
 
 pub(crate) fn result_from_ptr<T>(res: *mut T, op: Op) -> Result<*mut T> {
-    if !res.is_null() {
-        Ok(res as *mut T)
+    if let Some(err) = comparator_error(&op) {
+        Err(err)
+    } else if !res.is_null() {
+        Ok(res)
     } else {
         Err(Error::new(op))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_errno_kinds() {
+        let kinds = [
+            (libc::ENOSPC, ErrorKind::NoSpace),
+            (libc::EROFS, ErrorKind::ReadOnlyFilesystem),
+            (libc::EACCES, ErrorKind::PermissionDenied),
+            (libc::EPERM, ErrorKind::PermissionDenied),
+            (libc::ENOENT, ErrorKind::NotFound),
+            (libc::EAGAIN, ErrorKind::Other),
+        ];
+        for &(errno, kind) in kinds.iter() {
+            let err = Error::from_errno(errno, Op::TxnPut);
+            assert_eq!(kind, err.kind());
+            assert_eq!(Some(errno), err.raw_os_error());
+        }
+    }
+
+    #[test]
+    fn test_error_eq() {
+        let err = Error::from_errno(libc::ENOENT, Op::TxnGet);
+        assert_eq!(err, Error::from_errno(libc::ENOENT, Op::TxnGet));
+        assert_eq!(
+            err,
+            Error::from_errno(libc::ENOENT, Op::TxnGet)
+                .context("Missing key".to_string())
+        );
+        assert_ne!(err, Error::from_errno(libc::ENOENT, Op::TxnDel));
+        assert_ne!(err, Error::from_errno(libc::EIO, Op::TxnGet));
+        assert_ne!(err, Error::from(ErrorKind::NotFound));
+        assert!(err.same_kind(ErrorKind::NotFound));
+        assert!(!err.same_kind(ErrorKind::Other));
+
+        let err = Error::from_errno(libc::EAGAIN, Op::TxnPut);
+        assert_ne!(err, Error::from_errno(libc::EBUSY, Op::TxnPut));
+        assert_ne!(err, Error::from(ErrorKind::Other));
+        assert_eq!(
+            Error::other("a".to_string()),
+            Error::other("a".to_string())
+        );
+        assert_ne!(
+            Error::other("a".to_string()),
+            Error::other("b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_error_kind_hash() {
+        use std::collections::HashMap;
+
+        let errors = [
+            Error::from_errno(libc::ENOENT, Op::TxnGet),
+            Error::from_errno(libc::EBUSY, Op::TxnBegin),
+            Error::from_errno(libc::ENOENT, Op::CurGet(Position::Exact)),
+            Error::from_errno(libc::EAGAIN, Op::TxnPut),
+            Error::from_errno(libc::ENOENT, Op::TxnDel),
+        ];
+        let mut counts = HashMap::new();
+        for err in errors.iter() {
+            *counts.entry(err.kind()).or_insert(0) += 1;
+        }
+        assert_eq!(3, counts.len());
+        assert_eq!(Some(&3), counts.get(&ErrorKind::NotFound));
+        assert_eq!(Some(&1), counts.get(&ErrorKind::Busy));
+        assert_eq!(Some(&1), counts.get(&ErrorKind::Other));
+        assert_eq!(None, counts.get(&ErrorKind::NoSpace));
+    }
+
+    #[test]
+    fn test_io_error() {
+        let err = Error::from(io::Error::from_raw_os_error(libc::ENOSPC));
+        assert_eq!(ErrorKind::NoSpace, err.kind());
+        assert_eq!(Some(libc::ENOSPC), err.raw_os_error());
+        let source = err.source().unwrap().downcast_ref::<io::Error>();
+        assert_eq!(Some(libc::ENOSPC), source.unwrap().raw_os_error());
+        let copy = err.clone();
+        assert_eq!(Some(libc::ENOSPC), io::Error::from(err).raw_os_error());
+        assert_eq!(Some(libc::ENOSPC), io::Error::from(copy).raw_os_error());
+
+        let err = io::Error::new(io::ErrorKind::InvalidData, "bad");
+        let err = Error::from(err);
+        assert_eq!(ErrorKind::InvalidArgument, err.kind());
+        let err = io::Error::from(err);
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!("bad", err.to_string());
+
+        let err = Error::from_errno(libc::EIO, Op::Sync);
+        assert!(err.source().is_none());
+    }
+}
@@ -0,0 +1,127 @@
+//! Encodings of integers as keys that sort in numeric order.
+//!
+//! btree compares keys as byte strings, so integers have to be stored
+//! big-endian for their keys to sort numerically.  Signed integers also
+//! have their sign bit flipped, so that negative numbers sort before
+//! positive ones.
+//!
+//! The encodings of small unsigned integers, below 2^56 for 64-bit ones
+//! and 2^24 for 32-bit ones, start with a NUL byte, as do those of the
+//! most negative signed ones.  Such keys look reserved to the database,
+//! and cursor iterators skip them while `DatabaseBuilder::hide_reserved`
+//! is on, which it is by default.  Turn it off, or put the integers
+//! after a prefix that doesn't start with a NUL byte.
+
+const SIGN_BIT_64: u64 = 1 << 63;
+const SIGN_BIT_32: u32 = 1 << 31;
+
+/// Encodes an unsigned 64-bit integer as a key.
+pub fn encode_u64_be(n: u64) -> [u8; 8] {
+    n.to_be_bytes()
+}
+
+/// Decodes a key encoded with `encode_u64_be`, returning `None` if it
+/// isn't 8 bytes long.
+pub fn decode_u64_be(key: &[u8]) -> Option<u64> {
+    let mut buf = [0u8; 8];
+    if key.len() != buf.len() {
+        return None;
+    }
+    buf.copy_from_slice(key);
+    Some(u64::from_be_bytes(buf))
+}
+
+/// Encodes a signed 64-bit integer as a key.
+pub fn encode_i64_be(n: i64) -> [u8; 8] {
+    encode_u64_be(n as u64 ^ SIGN_BIT_64)
+}
+
+/// Decodes a key encoded with `encode_i64_be`, returning `None` if it
+/// isn't 8 bytes long.
+pub fn decode_i64_be(key: &[u8]) -> Option<i64> {
+    decode_u64_be(key).map(|n| (n ^ SIGN_BIT_64) as i64)
+}
+
+/// Encodes an unsigned 32-bit integer as a key.
+pub fn encode_u32_be(n: u32) -> [u8; 4] {
+    n.to_be_bytes()
+}
+
+/// Decodes a key encoded with `encode_u32_be`, returning `None` if it
+/// isn't 4 bytes long.
+pub fn decode_u32_be(key: &[u8]) -> Option<u32> {
+    let mut buf = [0u8; 4];
+    if key.len() != buf.len() {
+        return None;
+    }
+    buf.copy_from_slice(key);
+    Some(u32::from_be_bytes(buf))
+}
+
+/// Encodes a signed 32-bit integer as a key.
+pub fn encode_i32_be(n: i32) -> [u8; 4] {
+    encode_u32_be(n as u32 ^ SIGN_BIT_32)
+}
+
+/// Decodes a key encoded with `encode_i32_be`, returning `None` if it
+/// isn't 4 bytes long.
+pub fn decode_i32_be(key: &[u8]) -> Option<i32> {
+    decode_u32_be(key).map(|n| (n ^ SIGN_BIT_32) as i32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unsigned_order() {
+        let nums = [0, 1, 255, 256, 65535, 1 << 32, u64::MAX - 1];
+        for pair in nums.windows(2) {
+            assert!(encode_u64_be(pair[0]) < encode_u64_be(pair[1]));
+        }
+        for &n in nums.iter() {
+            assert_eq!(Some(n), decode_u64_be(&encode_u64_be(n)));
+        }
+
+        let nums = [0, 1, 255, 256, 65535, u32::MAX];
+        for pair in nums.windows(2) {
+            assert!(encode_u32_be(pair[0]) < encode_u32_be(pair[1]));
+        }
+        for &n in nums.iter() {
+            assert_eq!(Some(n), decode_u32_be(&encode_u32_be(n)));
+        }
+        assert_eq!(None, decode_u64_be(b"short"));
+        assert_eq!(None, decode_u32_be(b"toolong"));
+    }
+
+    #[test]
+    fn test_signed_order() {
+        let nums = [
+            i64::MIN,
+            i64::MIN + 1,
+            -65536,
+            -256,
+            -1,
+            0,
+            1,
+            256,
+            65536,
+            i64::MAX,
+        ];
+        for pair in nums.windows(2) {
+            assert!(encode_i64_be(pair[0]) < encode_i64_be(pair[1]));
+        }
+        for &n in nums.iter() {
+            assert_eq!(Some(n), decode_i64_be(&encode_i64_be(n)));
+        }
+
+        let nums = [i32::MIN, -256, -1, 0, 1, 256, i32::MAX];
+        for pair in nums.windows(2) {
+            assert!(encode_i32_be(pair[0]) < encode_i32_be(pair[1]));
+        }
+        for &n in nums.iter() {
+            assert_eq!(Some(n), decode_i32_be(&encode_i32_be(n)));
+        }
+        assert_eq!(None, decode_i64_be(b"short"));
+    }
+}
@@ -1,19 +1,44 @@
 #[macro_use]
 extern crate bitflags;
+#[cfg(feature = "serde")]
+extern crate bincode;
 extern crate btree as ffi;
 extern crate errno;
+#[cfg(feature = "compression")]
+extern crate flate2;
 extern crate libc;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 
+#[cfg(all(test, feature = "serde"))]
+#[macro_use]
+extern crate serde_derive;
 #[cfg(test)]
 extern crate tempdir;
 
-pub use cursor::{Cursor, RoCursor};
-pub use database::{Database, DatabaseFlags};
+pub use cursor::{Cursor, RoCursor, RwCursor};
+pub use database::{ChangeOp, Database, DatabaseFlags, META_KEY};
 pub use error::{Error, ErrorKind, Result};
-pub use transaction::{RoTransaction, RwTransaction, Transaction, WriteFlags};
+pub use stat::{
+    CompactOutcome, CompactionEstimate, LengthDistribution, LengthStats, Stat,
+    VerifyReport,
+};
+pub use transaction::{
+    CommitStatus, DataRef, NestedTransaction, ResetRoTransaction,
+    RoTransaction, RwTransaction, Snapshot, Transaction, WriteFlags,
+};
 
+mod crc;
 mod cursor;
 mod database;
 mod entry;
 mod error;
+pub mod keys;
+mod stat;
 mod transaction;
+pub mod typed;
+mod wal;
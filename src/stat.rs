@@ -0,0 +1,232 @@
+use std::cmp::Ordering;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ffi;
+
+/// Database statistics, see `Database::stat`.
+///
+/// Every lookup of a page counts either as a hit, if the page was found
+/// in the page cache, or as a read, if it had to be read from disk.  The
+/// counters start at zero when the database is opened.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stat {
+    /// Number of page lookups served from the page cache.
+    pub hits: u64,
+    /// Number of page lookups that missed the page cache and read the page
+    /// from disk.
+    pub reads: u64,
+    /// Maximum number of pages kept in the page cache.
+    pub max_cache: u32,
+    /// Number of pages currently in the page cache.
+    pub cache_size: u32,
+    /// Number of branch pages in the tree.
+    pub branch_pages: u32,
+    /// Number of leaf pages in the tree.
+    pub leaf_pages: u32,
+    /// Number of overflow pages holding large values.
+    pub overflow_pages: u32,
+    /// Number of committed revisions.
+    pub revisions: u32,
+    /// Depth of the tree, 0 for an empty database.
+    pub depth: u32,
+    /// Number of items in the database.
+    pub entries: u64,
+    /// Page size in bytes.
+    pub psize: u32,
+    /// Time the database file was created, with a resolution of one
+    /// second.  Compaction keeps it.  `None` for files created before it
+    /// was recorded, including after they are compacted.
+    pub created_at: Option<SystemTime>,
+}
+
+impl From<&ffi::btree_stat> for Stat {
+    // The widths of the C types vary between platforms.
+    #[allow(clippy::unnecessary_cast)]
+    fn from(stat: &ffi::btree_stat) -> Stat {
+        // time_t is only 32 bits wide on some platforms, and signed.
+        let secs = stat.created_at as i64;
+        // 0 means it wasn't recorded.
+        let created_at = match secs.cmp(&0) {
+            Ordering::Greater => {
+                Some(UNIX_EPOCH + Duration::from_secs(secs as u64))
+            }
+            Ordering::Less => {
+                Some(UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()))
+            }
+            Ordering::Equal => None,
+        };
+        Stat {
+            hits: stat.hits as u64,
+            reads: stat.reads as u64,
+            max_cache: stat.max_cache,
+            cache_size: stat.cache_size,
+            branch_pages: stat.branch_pages,
+            leaf_pages: stat.leaf_pages,
+            overflow_pages: stat.overflow_pages,
+            revisions: stat.revisions,
+            depth: stat.depth,
+            entries: stat.entries as u64,
+            psize: stat.psize,
+            created_at,
+        }
+    }
+}
+
+impl Stat {
+    /// Returns the fraction of page lookups served from the page cache,
+    /// from 0 to 1, or 0 if no pages were looked up yet.
+    ///
+    /// A ratio staying low under a steady workload suggests raising
+    /// `DatabaseBuilder::set_cache_size`.
+    pub fn cache_hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.reads;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+}
+
+/// Number of buckets in `LengthStats::buckets`, enough for any `u64`
+/// length.
+const LENGTH_BUCKETS: usize = 65;
+
+/// Distribution of key or value lengths, see `Database::length_distribution`.
+///
+/// Lengths are tallied into power-of-two buckets: bucket 0 counts empty
+/// keys or values, and bucket `i` counts lengths from `2^(i-1)` up to
+/// `2^i - 1` bytes.  Percentiles are approximated by the largest length
+/// of the bucket they fall into.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LengthStats {
+    /// Number of lengths tallied.
+    pub count: u64,
+    /// Sum of all lengths in bytes.
+    pub total: u64,
+    /// Number of lengths in each bucket.
+    pub buckets: Vec<u64>,
+}
+
+impl Default for LengthStats {
+    fn default() -> Self {
+        LengthStats {
+            count: 0,
+            total: 0,
+            buckets: vec![0; LENGTH_BUCKETS],
+        }
+    }
+}
+
+impl LengthStats {
+    /// Returns the index of the bucket a length belongs to.
+    fn bucket(len: u64) -> usize {
+        64 - len.leading_zeros() as usize
+    }
+
+    /// Returns the largest length that falls into the bucket.
+    fn bucket_max(bucket: usize) -> u64 {
+        match bucket {
+            0 => 0,
+            64 => u64::MAX,
+            _ => (1 << bucket) - 1,
+        }
+    }
+
+    pub(crate) fn add(&mut self, len: usize) {
+        let len = len as u64;
+        self.count += 1;
+        self.total += len;
+        self.buckets[Self::bucket(len)] += 1;
+    }
+
+    /// Returns the mean length, or 0 if nothing was tallied.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.total as f64 / self.count as f64
+    }
+
+    /// Returns the approximate length below which the given percentage of
+    /// lengths fall, or 0 if nothing was tallied.
+    pub fn percentile(&self, pct: f64) -> u64 {
+        let rank = (self.count as f64 * pct / 100.0).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::bucket_max(bucket);
+            }
+        }
+        0
+    }
+
+    /// Returns the approximate median length.
+    pub fn p50(&self) -> u64 {
+        self.percentile(50.0)
+    }
+
+    /// Returns the approximate 90th percentile length.
+    pub fn p90(&self) -> u64 {
+        self.percentile(90.0)
+    }
+
+    /// Returns the approximate 99th percentile length.
+    pub fn p99(&self) -> u64 {
+        self.percentile(99.0)
+    }
+}
+
+/// Distributions of key and value lengths in a database.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LengthDistribution {
+    /// Distribution of key lengths.
+    pub keys: LengthStats,
+    /// Distribution of value lengths.
+    pub values: LengthStats,
+}
+
+/// Space `Database::compact` is expected to reclaim, see
+/// `Database::compaction_estimate`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CompactionEstimate {
+    /// Current size of the database file in bytes.
+    pub file_size: u64,
+    /// Expected size of the compacted file in bytes.
+    pub compacted_size: u64,
+    /// Expected number of bytes reclaimed by compaction.
+    pub reclaimable: u64,
+}
+
+/// Result of `Database::compact`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CompactOutcome {
+    /// Whether the handle was left stale and has to be reopened with
+    /// `Database::reopen` before further use.
+    pub reopen_required: bool,
+}
+
+/// Results of an integrity check, see `Database::verify`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct VerifyReport {
+    /// Number of pages in the file, including the header page.
+    pub pages: u32,
+    /// Number of meta pages in the file, one for every committed revision
+    /// since the last compaction.
+    pub meta_pages: u32,
+    /// Number of branch pages in the last revision of the tree.
+    pub branch_pages: u32,
+    /// Number of leaf pages in the last revision of the tree.
+    pub leaf_pages: u32,
+    /// Number of overflow pages in the last revision of the tree.
+    pub overflow_pages: u32,
+    /// Numbers of the corrupt pages, in ascending order.
+    pub corrupt_pages: Vec<u32>,
+}
+
+impl VerifyReport {
+    /// Returns whether no corrupt pages were found.
+    pub fn is_ok(&self) -> bool {
+        self.corrupt_pages.is_empty()
+    }
+}
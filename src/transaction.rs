@@ -1,19 +1,59 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+#[cfg(feature = "compression")]
+use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::mem;
+use std::ops::{Bound, Deref, RangeBounds};
+use std::result;
 
+#[cfg(feature = "compression")]
+use flate2::read::ZlibDecoder;
+#[cfg(feature = "compression")]
+use flate2::write::ZlibEncoder;
+#[cfg(feature = "compression")]
+use flate2::Compression;
 use libc;
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use ffi;
 
-use cursor::RoCursor;
-use database::Database;
+use crc::crc32;
+use cursor::{prefix_successor, Cursor, RoCursor, RwCursor};
+use database::{encode_change, is_reserved, ChangeOp, Database, DatabaseFlags};
+use database::{FEED_PREFIX, FEED_SEQ_KEY, LOG_PREFIX, LOG_SEQ_KEY};
 use entry::Entry;
 use error::{clear_error, result_from_int, result_from_ptr};
-use error::{Op, Result};
+use error::{Error, ErrorKind, Op, Result};
+use typed::{Bytes, FromBytes};
+
+/// Header byte of a value stored by `RwTransaction::put_compressed` as is.
+#[cfg(feature = "compression")]
+const STORED: u8 = 0;
+
+/// Header byte of a value stored by `RwTransaction::put_compressed` in the
+/// zlib format.
+#[cfg(feature = "compression")]
+const ZLIB: u8 = 1;
+
+/// Commits the btree transaction, which frees it either way.
+fn commit_txn<T: Transaction>(txn: T) -> Result<()> {
+    unsafe {
+        let res =
+            result_from_int(ffi::btree_txn_commit(txn.txn()), Op::TxnCommit);
+        mem::forget(txn);
+        res
+    }
+}
 
 /// A database transaction.
 ///
-/// All database operations require a transaction.
+/// All database operations require a transaction.  A transaction may only
+/// be used with the database it was started on: passing another database
+/// to its methods fails with `ErrorKind::InvalidArgument`.
 pub trait Transaction: Sized {
     /// Returns a raw pointer to the underlying btree transaction.
     ///
@@ -27,14 +67,7 @@ pub trait Transaction: Sized {
     ///
     /// Any pending operations will be saved.
     fn commit(self) -> Result<()> {
-        unsafe {
-            let res = result_from_int(
-                ffi::btree_txn_commit(self.txn()),
-                Op::TxnCommit,
-            );
-            mem::forget(self);
-            res
-        }
+        commit_txn(self)
     }
 
     /// Gets an item from a database.
@@ -46,21 +79,304 @@ pub trait Transaction: Sized {
     where
         K: AsRef<[u8]>,
     {
+        self.get_ref(db, key).map(|data| data.to_vec())
+    }
+
+    /// Gets an item from a database, converting the key and the value with
+    /// their `Bytes` and `FromBytes` implementations.
+    ///
+    /// A value that fails to decode is reported as `ErrorKind::Other`.
+    fn get_as<K, V>(&self, db: &Database, key: &K) -> Result<V>
+    where
+        K: Bytes + ?Sized,
+        V: FromBytes,
+    {
+        V::from_bytes(&self.get_ref(db, &key.to_bytes())?)
+    }
+
+    /// Gets an item from a database without copying it.
+    ///
+    /// The returned `DataRef` points into the page cache, or into a
+    /// private buffer for values spilling into overflow pages.  It keeps
+    /// the page referenced until dropped and borrows the transaction, so
+    /// the transaction can be neither modified nor committed while the
+    /// data is in use.
+    fn get_ref<'txn, K>(
+        &'txn self,
+        db: &Database,
+        key: &K,
+    ) -> Result<DataRef<'txn>>
+    where
+        K: AsRef<[u8]>,
+    {
+        db.check_key_len(key.as_ref(), Op::TxnGet)?;
+        let mut keyent = Entry::from_slice(key);
+        let mut dataent = Entry::new();
+        unsafe {
+            clear_error();
+            result_from_int(
+                ffi::btree_txn_get(
+                    db.txn_handle(self.txn()),
+                    self.txn(),
+                    keyent.inner_mut(),
+                    dataent.inner_mut(),
+                ),
+                Op::TxnGet,
+            )?;
+        }
+        Ok(DataRef {
+            entry: dataent,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Gets an item from a database, if present.
+    ///
+    /// Like `Transaction::get`, but a missing item is reported as
+    /// `Ok(None)` rather than an error.
+    fn get_opt<K>(&self, db: &Database, key: &K) -> Result<Option<Vec<u8>>>
+    where
+        K: AsRef<[u8]>,
+    {
+        match self.get(db, key) {
+            Ok(data) => Ok(Some(data)),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Gets an item from a database into a buffer, and returns whether it
+    /// was present.
+    ///
+    /// The buffer is cleared first, and holds the value afterwards if it
+    /// was found.  Reusing one buffer across lookups saves allocating one
+    /// for every value.
+    fn get_into<K>(
+        &self,
+        db: &Database,
+        key: &K,
+        buf: &mut Vec<u8>,
+    ) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+    {
+        buf.clear();
+        match self.get_ref(db, key) {
+            Ok(data) => {
+                buf.extend_from_slice(&data);
+                Ok(true)
+            }
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Gets an item stored with `RwTransaction::put_checked` from a
+    /// database.
+    ///
+    /// The checksum stored in front of the value is verified and stripped.
+    /// A mismatch is reported as `ErrorKind::InputOutput`.
+    fn get_checked<K>(&self, db: &Database, key: &K) -> Result<Vec<u8>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let mut data = self.get(db, key)?;
+        let valid = data.len() >= 4 && {
+            let (crc, value) = data.split_at(4);
+            crc == crc32(value).to_be_bytes()
+        };
+        if !valid {
+            return Err(Error::from_errno(libc::EIO, Op::TxnGet).context(
+                format!(
+                    "Checksum mismatch for key {}",
+                    key.as_ref().escape_ascii()
+                ),
+            ));
+        }
+        data.drain(..4);
+        Ok(data)
+    }
+
+    /// Gets an item stored with `RwTransaction::put_serde` from a database
+    /// and deserializes it with bincode.
+    #[cfg(feature = "serde")]
+    fn get_serde<K, V>(&self, db: &Database, key: &K) -> Result<V>
+    where
+        K: AsRef<[u8]>,
+        V: DeserializeOwned,
+    {
+        let data = self.get_ref(db, key)?;
+        bincode::deserialize(&data).map_err(|err| {
+            Error::other(format!("Failed to deserialize the value: {}", err))
+        })
+    }
+
+    /// Gets an item stored with `RwTransaction::put_compressed` from a
+    /// database, decompressing it if needed.
+    ///
+    /// The header byte in front of the value is checked and stripped.  An
+    /// unknown header or a corrupt compressed value is reported as
+    /// `ErrorKind::Other`.
+    ///
+    /// Only use this on values written by `put_compressed`.  The header
+    /// is a single byte and can't be told apart from the data of other
+    /// values: one stored with `RwTransaction::put` that happens to start
+    /// with a 0 byte comes back without it, and one starting with a 1 byte
+    /// most likely fails to decompress.
+    #[cfg(feature = "compression")]
+    fn get_compressed<K>(&self, db: &Database, key: &K) -> Result<Vec<u8>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let data = self.get_ref(db, key)?;
+        match data.split_first() {
+            Some((&STORED, value)) => Ok(value.to_vec()),
+            Some((&ZLIB, value)) => {
+                let mut out = Vec::new();
+                ZlibDecoder::new(value).read_to_end(&mut out).map_err(
+                    |err| {
+                        Error::other(format!(
+                            "Failed to decompress the value: {}",
+                            err
+                        ))
+                    },
+                )?;
+                Ok(out)
+            }
+            _ => Err(Error::other(format!(
+                "Value of key {} wasn't stored compressed",
+                key.as_ref().escape_ascii()
+            ))),
+        }
+    }
+
+    /// Checks whether an item is present in a database.
+    ///
+    /// The value isn't copied out, although btree still reads it.
+    fn contains_key<K>(&self, db: &Database, key: &K) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+    {
+        db.check_key_len(key.as_ref(), Op::TxnGet)?;
+        let mut keyent = Entry::from_slice(key);
+        let mut dataent = Entry::new();
+        let res = unsafe {
+            clear_error();
+            result_from_int(
+                ffi::btree_txn_get(
+                    db.txn_handle(self.txn()),
+                    self.txn(),
+                    keyent.inner_mut(),
+                    dataent.inner_mut(),
+                ),
+                Op::TxnGet,
+            )
+        };
+        match res {
+            Ok(()) => Ok(true),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Gets the length of an item's value, if present.
+    ///
+    /// The value isn't copied out, although btree still reads it when it
+    /// spans overflow pages.
+    fn value_len<K>(&self, db: &Database, key: &K) -> Result<Option<usize>>
+    where
+        K: AsRef<[u8]>,
+    {
+        db.check_key_len(key.as_ref(), Op::TxnGet)?;
+        let mut keyent = Entry::from_slice(key);
+        let mut dataent = Entry::new();
+        let res = unsafe {
+            clear_error();
+            result_from_int(
+                ffi::btree_txn_get(
+                    db.txn_handle(self.txn()),
+                    self.txn(),
+                    keyent.inner_mut(),
+                    dataent.inner_mut(),
+                ),
+                Op::TxnGet,
+            )
+        };
+        match res {
+            Ok(()) => Ok(Some(dataent.size())),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Gets at most the first `max` bytes of an item's value.
+    ///
+    /// Only the returned prefix is copied out of the value btree hands
+    /// back, which still has to be read in full when it spans overflow
+    /// pages.
+    fn get_prefix_bytes<K>(
+        &self,
+        db: &Database,
+        key: &K,
+        max: usize,
+    ) -> Result<Vec<u8>>
+    where
+        K: AsRef<[u8]>,
+    {
+        db.check_key_len(key.as_ref(), Op::TxnGet)?;
         let mut keyent = Entry::from_slice(key);
         let mut dataent = Entry::new();
         unsafe {
             clear_error();
             result_from_int(
                 ffi::btree_txn_get(
-                    db.dbi(),
+                    db.txn_handle(self.txn()),
                     self.txn(),
                     keyent.inner_mut(),
                     dataent.inner_mut(),
                 ),
                 Op::TxnGet,
             )?;
-            Ok(dataent.get_value())
+            Ok(dataent.get_value_prefix(max))
+        }
+    }
+
+    /// Checks whether looking up the given key is served entirely from
+    /// the page cache.
+    ///
+    /// btree doesn't expose per-page cache residency, so this is only a
+    /// heuristic: the key is looked up and the number of pages read from
+    /// disk is compared before and after.  Since the lookup itself pulls
+    /// the pages into the cache, asking twice will always return `true`
+    /// the second time unless the pages were evicted in between.  Absent
+    /// keys are handled the same way, the answer then applies to the pages
+    /// visited while searching for the key.
+    fn is_cached<K>(&self, db: &Database, key: &K) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+    {
+        db.check_key_len(key.as_ref(), Op::TxnGet)?;
+        let mut keyent = Entry::from_slice(key);
+        let mut dataent = Entry::new();
+        let reads = db.page_reads();
+        unsafe {
+            clear_error();
+            let res = result_from_int(
+                ffi::btree_txn_get(
+                    db.txn_handle(self.txn()),
+                    self.txn(),
+                    keyent.inner_mut(),
+                    dataent.inner_mut(),
+                ),
+                Op::TxnGet,
+            );
+            if let Err(err) = res {
+                if err.kind() != ErrorKind::NotFound {
+                    return Err(err);
+                }
+            }
         }
+        Ok(db.page_reads() == reads)
     }
 
     /// Open a new read-only cursor on the given database.
@@ -70,17 +386,119 @@ pub trait Transaction: Sized {
     ) -> Result<RoCursor<'txn>> {
         RoCursor::new(self, db)
     }
+
+    /// Calls the closure on every item of a database, in key order, until
+    /// it returns `false`.
+    ///
+    /// The closure is handed slices borrowed from the database pages, so
+    /// no memory is allocated per item, see `Cursor::iter_ref`.  Reserved
+    /// keys are skipped unless `DatabaseBuilder::hide_reserved` is turned
+    /// off.
+    fn for_each<F>(&self, db: &Database, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        let mut cursor = self.open_ro_cursor(db)?;
+        let mut iter = cursor.iter_ref();
+        while let Some((key, data)) = iter.try_next()? {
+            if !f(key, data) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts the items with keys within `range`, see `Cursor::range`.
+    ///
+    /// btree doesn't keep track of the number of items below a page, so
+    /// this visits every key in the range, taking time proportional to
+    /// their number.  Values aren't read, and nothing is copied.
+    fn count_range<'k, R>(&self, db: &Database, range: R) -> Result<u64>
+    where
+        R: RangeBounds<&'k [u8]>,
+    {
+        let mut cursor = self.open_ro_cursor(db)?;
+        cursor.range(range).try_count()
+    }
+
+    /// Collects the items in each of the `[start, end)` ranges.
+    ///
+    /// The results are in the order of the ranges.  All ranges are read
+    /// with a single cursor, visiting them in the order of their start
+    /// keys, and see the same state of the database.
+    #[allow(clippy::type_complexity)]
+    fn multi_range<K>(
+        &self,
+        db: &Database,
+        ranges: &[(K, K)],
+    ) -> Result<Vec<Vec<(Vec<u8>, Vec<u8>)>>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let mut order: Vec<usize> = (0..ranges.len()).collect();
+        order.sort_by(|&a, &b| ranges[a].0.as_ref().cmp(ranges[b].0.as_ref()));
+        let mut cursor = self.open_ro_cursor(db)?;
+        let mut results = vec![Vec::new(); ranges.len()];
+        for i in order {
+            let (ref start, ref end) = ranges[i];
+            results[i] = cursor.iter_range(start, end).collect();
+        }
+        Ok(results)
+    }
+}
+
+/// A value borrowed from a transaction by `Transaction::get_ref`.
+///
+/// Dereferences to the value bytes.
+pub struct DataRef<'txn> {
+    entry: Entry,
+    _marker: PhantomData<&'txn ()>,
+}
+
+impl<'txn> Deref for DataRef<'txn> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.entry.as_slice()
+    }
+}
+
+impl<'txn> AsRef<[u8]> for DataRef<'txn> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Checks that writing `len` more bytes on top of `written` stays within
+/// `max_bytes`, see `Database::begin_rw_txn_bounded`.
+pub(crate) fn check_write_limit(
+    written: usize,
+    max_bytes: Option<usize>,
+    len: usize,
+    op: Op,
+) -> Result<()> {
+    match max_bytes {
+        Some(max) if written + len > max => Err(Error::from_errno(
+            libc::EFBIG,
+            op,
+        )
+        .context(format!("Transaction write limit of {} bytes exceeded", max))),
+        _ => Ok(()),
+    }
 }
 
 /// A read-only transaction.
 pub struct RoTransaction<'db> {
     txn: *mut ffi::btree_txn,
-    _marker: PhantomData<&'db ()>,
+    db: &'db Database,
 }
 
 impl<'db> Drop for RoTransaction<'db> {
     fn drop(&mut self) {
-        unsafe { ffi::btree_txn_abort(self.txn) }
+        unsafe {
+            ffi::btree_txn_abort(self.txn);
+        }
+        self.db.release_retired();
     }
 }
 
@@ -88,34 +506,152 @@ impl<'db> Transaction for RoTransaction<'db> {
     fn txn(&self) -> *mut ffi::btree_txn {
         self.txn
     }
+
+    /// Commits the transaction, closing the handles replaced by reopens
+    /// that no transaction uses any more.
+    fn commit(self) -> Result<()> {
+        let db = self.db;
+        let res = commit_txn(self);
+        db.release_retired();
+        res
+    }
 }
 
 impl<'db> RoTransaction<'db> {
     /// Creates a new read-only transaction in the given database.
     pub(crate) fn new(db: &'db Database) -> Result<RoTransaction<'db>> {
         clear_error();
-        let txn = unsafe {
+        let txn = db.with_handle(|bt| unsafe {
             result_from_ptr::<ffi::btree_txn>(
-                ffi::btree_txn_begin(db.dbi(), 1),
+                ffi::btree_txn_begin(bt, 1),
                 Op::TxnBegin,
-            )?
+            )
+        })?;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(op = "txn_begin", read_only = true);
+        Ok(RoTransaction { txn, db })
+    }
+
+    /// Creates a new read-only transaction on the database as it was after
+    /// the given revision was committed.
+    pub(crate) fn new_at(
+        db: &'db Database,
+        revision: u64,
+    ) -> Result<RoTransaction<'db>> {
+        let revision = match libc::c_uint::try_from(revision) {
+            Ok(revision) => revision,
+            Err(_) => {
+                return Err(Error::from_errno(libc::ENOENT, Op::TxnBegin))
+            }
         };
-        Ok(RoTransaction {
-            txn,
-            _marker: PhantomData,
-        })
+        clear_error();
+        let txn = db.with_handle(|bt| unsafe {
+            result_from_ptr::<ffi::btree_txn>(
+                ffi::btree_txn_begin_at(bt, revision),
+                Op::TxnBegin,
+            )
+        })?;
+        Ok(RoTransaction { txn, db })
+    }
+
+    /// Creates a new read-only transaction seeing the same tree as `other`,
+    /// a transaction on another handle of the same file.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn new_like(
+        db: &'db Database,
+        other: *mut ffi::btree_txn,
+    ) -> Result<RoTransaction<'db>> {
+        clear_error();
+        let txn = db.with_handle(|bt| unsafe {
+            result_from_ptr::<ffi::btree_txn>(
+                ffi::btree_txn_begin_like(bt, other),
+                Op::TxnBegin,
+            )
+        })?;
+        Ok(RoTransaction { txn, db })
+    }
+
+    /// Ends the transaction, keeping what's needed to start a new one on
+    /// the same database with `ResetRoTransaction::renew`.
+    ///
+    /// btree has no way to refresh the snapshot of a transaction, so this
+    /// frees the transaction and `renew` begins a new one, which costs as
+    /// much as `Database::begin_ro_txn`.
+    pub fn reset(self) -> ResetRoTransaction<'db> {
+        ResetRoTransaction { db: self.db }
+    }
+}
+
+/// A read-only transaction parked by `RoTransaction::reset`.
+pub struct ResetRoTransaction<'db> {
+    db: &'db Database,
+}
+
+impl<'db> ResetRoTransaction<'db> {
+    /// Starts the transaction again, on the latest committed state of the
+    /// database.
+    pub fn renew(self) -> Result<RoTransaction<'db>> {
+        RoTransaction::new(self.db)
+    }
+}
+
+/// A consistent view of a database, as of the moment it was taken with
+/// `Database::snapshot`.
+///
+/// All reads through a snapshot see the same committed state, however
+/// many writes are committed meanwhile.  It is a read-only transaction
+/// under the hood, and stays open for as long as the snapshot lives.
+pub struct Snapshot<'db> {
+    txn: RoTransaction<'db>,
+}
+
+impl<'db> Snapshot<'db> {
+    pub(crate) fn new(txn: RoTransaction<'db>) -> Snapshot<'db> {
+        Snapshot { txn }
+    }
+
+    /// Gets an item from the snapshot.
+    pub fn get<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.txn.get(self.txn.db, key)
+    }
+
+    /// Checks whether an item is present in the snapshot.
+    pub fn contains_key<K>(&self, key: &K) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.txn.contains_key(self.txn.db, key)
+    }
+
+    /// Opens a cursor over the snapshot.
+    pub fn cursor(&self) -> Result<RoCursor<'_>> {
+        self.txn.open_ro_cursor(self.txn.db)
+    }
+
+    /// Returns the underlying transaction, for the reads not covered by
+    /// the methods of the snapshot.
+    pub fn transaction(&self) -> &RoTransaction<'db> {
+        &self.txn
     }
 }
 
 /// A read-write transaction.
 pub struct RwTransaction<'db> {
     txn: *mut ffi::btree_txn,
-    _marker: PhantomData<&'db ()>,
+    written: usize,
+    max_bytes: Option<usize>,
+    db: &'db Database,
 }
 
 impl<'db> Drop for RwTransaction<'db> {
     fn drop(&mut self) {
-        unsafe { ffi::btree_txn_abort(self.txn) }
+        unsafe {
+            ffi::btree_txn_abort(self.txn);
+        }
+        self.db.release_retired();
     }
 }
 
@@ -123,6 +659,53 @@ impl<'db> Transaction for RwTransaction<'db> {
     fn txn(&self) -> *mut ffi::btree_txn {
         self.txn
     }
+
+    /// Commits the transaction, closing the handles replaced by reopens
+    /// that no transaction uses any more.
+    ///
+    /// With the `tracing` feature, reports the number of items and the
+    /// revision of the database after it as a `DEBUG` event.
+    fn commit(self) -> Result<()> {
+        let db = self.db;
+        #[cfg(feature = "tracing")]
+        let written = self.written;
+        let res = commit_txn(self);
+        db.release_retired();
+        #[cfg(feature = "tracing")]
+        match res {
+            Ok(()) if tracing::enabled!(tracing::Level::DEBUG) => {
+                if let Ok(stat) = db.stat() {
+                    tracing::debug!(
+                        op = "txn_commit",
+                        bytes_written = written,
+                        entries = stat.entries,
+                        revision = stat.revisions,
+                        cache_hits = stat.hits,
+                        cache_reads = stat.reads,
+                        "Committed a transaction"
+                    );
+                }
+            }
+            Ok(()) => (),
+            Err(ref err) => tracing::warn!(
+                op = err.op_name(),
+                error = %err,
+                "Failed to commit a transaction"
+            ),
+        }
+        res
+    }
+}
+
+/// Outcome of `RwTransaction::commit_with_status`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CommitStatus {
+    /// Whether the commit was synced to disk, i.e. the database wasn't
+    /// opened with `NO_SYNC`.
+    pub durable: bool,
+    /// Number of committed revisions after the commit, see
+    /// `Stat::revisions`.
+    pub revision: u64,
 }
 
 bitflags! {
@@ -132,25 +715,85 @@ bitflags! {
         #[doc="Insert the new item only if the key does not already "]
         #[doc="appear in the database."]
         const NO_OVERWRITE = ffi::BT_NOOVERWRITE;
+        #[doc="Insert the new item only if the key sorts after all keys "]
+        #[doc="in the database, failing with `ErrorKind::InvalidArgument` "]
+        #[doc="otherwise. Use it to check the order when loading sorted "]
+        #[doc="keys."]
+        const APPEND = ffi::BT_APPEND;
     }
 }
 
 impl<'db> RwTransaction<'db> {
     /// Creates a new read-write transaction in the given database.
+    ///
+    /// btree lets write transactions begin on read-only databases, so they
+    /// are rejected here instead.
     pub(crate) fn new(db: &'db Database) -> Result<RwTransaction<'db>> {
+        if db.flags().contains(DatabaseFlags::READ_ONLY) {
+            return Err(Error::from_errno(libc::EPERM, Op::TxnBegin).context(
+                "Cannot write to a database opened read-only".to_string(),
+            ));
+        }
         clear_error();
-        let txn = unsafe {
+        let txn = db.with_handle(|bt| unsafe {
             result_from_ptr::<ffi::btree_txn>(
-                ffi::btree_txn_begin(db.dbi(), 0),
+                ffi::btree_txn_begin(bt, 0),
                 Op::TxnBegin,
-            )?
-        };
+            )
+        })?;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(op = "txn_begin", read_only = false);
         Ok(RwTransaction {
             txn,
-            _marker: PhantomData,
+            written: 0,
+            max_bytes: None,
+            db,
         })
     }
 
+    /// Commits the transaction and reports whether it is durable and the
+    /// revision it produced.
+    pub fn commit_with_status(self) -> Result<CommitStatus> {
+        let db = self.db;
+        self.commit()?;
+        Ok(CommitStatus {
+            durable: !db.flags().contains(DatabaseFlags::NO_SYNC),
+            revision: u64::from(db.stat()?.revisions),
+        })
+    }
+
+    /// Commits the transaction and returns the revision it produced.
+    ///
+    /// Revisions increase by one with every commit to the database, so
+    /// they can serve as a handle for replicating changes.  Compacting the
+    /// database starts the count over.
+    pub fn commit_with_revision(self) -> Result<u64> {
+        self.commit_with_status().map(|status| status.revision)
+    }
+
+    /// Limits the number of bytes the transaction may write.
+    pub(crate) fn set_max_bytes(&mut self, max_bytes: usize) {
+        self.max_bytes = Some(max_bytes);
+    }
+
+    /// Returns the number of bytes written by the transaction so far,
+    /// counting the key and value lengths of every put and the key length
+    /// of every delete, including deletes through `RwCursor::del`.
+    pub fn bytes_written(&self) -> usize {
+        self.written
+    }
+
+    /// Checks that writing `len` more bytes stays within the limit.
+    fn check_limit(&self, len: usize, op: Op) -> Result<()> {
+        check_write_limit(self.written, self.max_bytes, len, op)
+    }
+
+    /// Returns the count of bytes written and the limit, for a cursor to
+    /// keep track of its deletes.
+    pub(crate) fn write_budget(&mut self) -> (&mut usize, Option<usize>) {
+        (&mut self.written, self.max_bytes)
+    }
+
     /// Stores an item into a database.
     pub fn put<K, D>(
         &mut self,
@@ -163,21 +806,199 @@ impl<'db> RwTransaction<'db> {
         K: AsRef<[u8]>,
         D: AsRef<[u8]>,
     {
+        db.check_key_len(key.as_ref(), Op::TxnPut)?;
+        let len = key.as_ref().len() + data.as_ref().len();
+        self.check_limit(len, Op::TxnPut)?;
         let mut keyent = Entry::from_slice(key);
         let mut dataent = Entry::from_slice(data);
         unsafe {
             clear_error();
             result_from_int(
                 ffi::btree_txn_put(
-                    db.dbi(),
+                    db.txn_handle(self.txn()),
                     self.txn(),
                     keyent.inner_mut(),
                     dataent.inner_mut(),
                     flags.bits(),
                 ),
                 Op::TxnPut,
-            )
+            )?;
         }
+        self.written += len;
+        self.record_change(db, ChangeOp::Put, key.as_ref(), data.as_ref())
+    }
+
+    /// Stores a sequence of items into a database, and returns how many
+    /// were stored.
+    ///
+    /// Stops at the first item that fails to be stored.  The items stored
+    /// before it are kept in the transaction, and the error is returned
+    /// along with their number.
+    pub fn put_batch<I, K, D>(
+        &mut self,
+        db: &Database,
+        items: I,
+        flags: WriteFlags,
+    ) -> result::Result<usize, (usize, Error)>
+    where
+        I: IntoIterator<Item = (K, D)>,
+        K: AsRef<[u8]>,
+        D: AsRef<[u8]>,
+    {
+        let mut stored = 0;
+        for (key, data) in items {
+            self.put(db, &key, &data, flags)
+                .map_err(|err| (stored, err))?;
+            stored += 1;
+        }
+        Ok(stored)
+    }
+
+    /// Appends a value to the database log and returns its sequence
+    /// number.
+    ///
+    /// Sequence numbers start at 1 and increase with every append, so
+    /// `Database::iter_log` returns the values in the order they were
+    /// appended.  Values are kept under reserved keys made of a `\0log\0`
+    /// prefix followed by the big-endian sequence number.
+    pub fn append<D>(&mut self, db: &Database, data: &D) -> Result<u64>
+    where
+        D: AsRef<[u8]>,
+    {
+        let seq = self.next_seq(db, LOG_SEQ_KEY)?;
+        let mut key = LOG_PREFIX.to_vec();
+        key.extend_from_slice(&seq.to_be_bytes());
+        self.put(db, &key, data, WriteFlags::empty())?;
+        Ok(seq)
+    }
+
+    /// Allocates the next number of the sequence whose last number is kept
+    /// under `seq_key`.
+    fn next_seq(&mut self, db: &Database, seq_key: &[u8]) -> Result<u64> {
+        let last = match self.get_opt(db, &seq_key)? {
+            Some(ref seq) if seq.len() == 8 => {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(seq);
+                u64::from_be_bytes(buf)
+            }
+            Some(_) => {
+                return Err(Error::other("Invalid sequence number".to_string()))
+            }
+            None => 0,
+        };
+        let seq = last + 1;
+        self.put(db, &seq_key, &seq.to_be_bytes(), WriteFlags::empty())?;
+        Ok(seq)
+    }
+
+    /// Records a change in the change feed, if the database keeps one.
+    fn record_change(
+        &mut self,
+        db: &Database,
+        op: ChangeOp,
+        key: &[u8],
+        data: &[u8],
+    ) -> Result<()> {
+        if !db.records_changes() || is_reserved(key) {
+            return Ok(());
+        }
+        let seq = self.next_seq(db, FEED_SEQ_KEY)?;
+        let mut feedkey = FEED_PREFIX.to_vec();
+        feedkey.extend_from_slice(&seq.to_be_bytes());
+        let record = encode_change(op, key, data);
+        self.put(db, &feedkey, &record, WriteFlags::empty())
+    }
+
+    /// Stores an item into a database, converting the key and the value
+    /// with their `Bytes` implementations.
+    pub fn put_as<K, D>(
+        &mut self,
+        db: &Database,
+        key: &K,
+        data: &D,
+        flags: WriteFlags,
+    ) -> Result<()>
+    where
+        K: Bytes + ?Sized,
+        D: Bytes + ?Sized,
+    {
+        self.put(db, &key.to_bytes(), &data.to_bytes(), flags)
+    }
+
+    /// Stores an item into a database along with a checksum of its value.
+    ///
+    /// The value is stored with a big-endian CRC-32 of it in front, taking
+    /// 4 more bytes.  Read it back with `Transaction::get_checked`, which
+    /// verifies the checksum; `Transaction::get` returns the framed value
+    /// as is.
+    pub fn put_checked<K, D>(
+        &mut self,
+        db: &Database,
+        key: &K,
+        data: &D,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        D: AsRef<[u8]>,
+    {
+        let data = data.as_ref();
+        let mut framed = Vec::with_capacity(data.len() + 4);
+        framed.extend_from_slice(&crc32(data).to_be_bytes());
+        framed.extend_from_slice(data);
+        self.put(db, key, &framed, WriteFlags::empty())
+    }
+
+    /// Serializes a value with bincode and stores it into a database.
+    #[cfg(feature = "serde")]
+    pub fn put_serde<K, V>(
+        &mut self,
+        db: &Database,
+        key: &K,
+        value: &V,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        V: Serialize + ?Sized,
+    {
+        let data = bincode::serialize(value).map_err(|err| {
+            Error::other(format!("Failed to serialize the value: {}", err))
+        })?;
+        self.put(db, key, &data, WriteFlags::empty())
+    }
+
+    /// Compresses a value with zlib and stores it into a database.
+    ///
+    /// A header byte in front of the value tells whether it is
+    /// compressed: values that don't shrink, like small or random ones,
+    /// are stored as they are.  Read it back with
+    /// `Transaction::get_compressed`; other values in the database are
+    /// unaffected, so plain and compressed values can be mixed as long as
+    /// each is read back the way it was written.
+    #[cfg(feature = "compression")]
+    pub fn put_compressed<K, D>(
+        &mut self,
+        db: &Database,
+        key: &K,
+        data: &D,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        D: AsRef<[u8]>,
+    {
+        let data = data.as_ref();
+        let mut encoder = ZlibEncoder::new(vec![ZLIB], Compression::default());
+        let mut framed = encoder
+            .write_all(data)
+            .and_then(|_| encoder.finish())
+            .map_err(|err| {
+                Error::other(format!("Failed to compress the value: {}", err))
+            })?;
+        if framed.len() > data.len() {
+            framed.clear();
+            framed.push(STORED);
+            framed.extend_from_slice(data);
+        }
+        self.put(db, key, &framed, WriteFlags::empty())
     }
 
     /// Deletes an item from a database.
@@ -185,21 +1006,337 @@ impl<'db> RwTransaction<'db> {
     where
         K: AsRef<[u8]>,
     {
+        db.check_key_len(key.as_ref(), Op::TxnDel)?;
+        let len = key.as_ref().len();
+        self.check_limit(len, Op::TxnDel)?;
         let mut keyent = Entry::from_slice(key);
         let mut dataent = Entry::new();
         unsafe {
             clear_error();
             result_from_int(
                 ffi::btree_txn_del(
-                    db.dbi(),
+                    db.txn_handle(self.txn()),
                     self.txn(),
                     keyent.inner_mut(),
                     dataent.inner_mut(),
                 ),
                 Op::TxnDel,
+            )?;
+        }
+        self.written += len;
+        self.record_change(db, ChangeOp::Del, key.as_ref(), &[])
+    }
+
+    /// Deletes all items from the database.
+    ///
+    /// The tree is dropped as a whole rather than item by item, so this
+    /// takes no time and writes nothing but the meta page on commit; the
+    /// space of the old pages is reclaimed by `Database::compact`, and
+    /// nothing counts towards `RwTransaction::bytes_written`.  Reserved
+    /// keys go too, including the metadata blob and the log.
+    ///
+    /// Fails with `ErrorKind::InvalidArgument` if the database keeps a
+    /// change feed, see `DatabaseBuilder::change_feed`, which would go
+    /// along with everything else without a record of the deletes.
+    pub fn clear(&mut self, db: &Database) -> Result<()> {
+        if db.records_changes() {
+            return Err(Error::from_errno(libc::EINVAL, Op::TxnDel).context(
+                "Clearing a database would drop its change feed".to_string(),
+            ));
+        }
+        unsafe {
+            clear_error();
+            result_from_int(
+                ffi::btree_txn_clear(db.txn_handle(self.txn()), self.txn()),
+                Op::TxnDel,
             )
         }
     }
+
+    /// Deletes an item from a database, if present.
+    ///
+    /// Like `RwTransaction::del`, but returns whether the item was there
+    /// rather than failing with `ErrorKind::NotFound`.
+    pub fn del_opt<K>(&mut self, db: &Database, key: &K) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+    {
+        match self.del(db, key) {
+            Ok(()) => Ok(true),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Deletes all items with keys starting with `prefix`, and returns
+    /// their number.
+    ///
+    /// Every key goes through `RwTransaction::del`, so the change feed and
+    /// the limit of `Database::begin_rw_txn_bounded` apply to each of
+    /// them.  Keys are assumed to be ordered bytewise, which doesn't hold
+    /// for databases opened with `REVERSE_KEY`, nor for every comparator
+    /// set with `DatabaseBuilder::set_comparator`.
+    pub fn del_prefix<P>(&mut self, db: &Database, prefix: P) -> Result<u64>
+    where
+        P: AsRef<[u8]>,
+    {
+        let succ = prefix_successor(prefix.as_ref());
+        let end = succ
+            .as_ref()
+            .map_or(Bound::Unbounded, |succ| Bound::Excluded(&succ[..]));
+        let mut start = prefix.as_ref().to_vec();
+        let mut count = 0;
+        loop {
+            // Deleting invalidates the cursor, so look up every key anew,
+            // starting from the one deleted last.
+            let key = {
+                let mut cursor = self.open_ro_cursor(db)?;
+                let mut iter = cursor.range((Bound::Included(&start[..]), end));
+                iter.try_next_key()?
+            };
+            match key {
+                Some(key) => {
+                    self.del(db, &key)?;
+                    start = key;
+                    count += 1;
+                }
+                None => return Ok(count),
+            }
+        }
+    }
+
+    /// Moves an item to another key, unless that key is already taken.
+    ///
+    /// Returns whether the item was moved.  Nothing is changed if `to` is
+    /// present, and `ErrorKind::NotFound` is returned if `from` is not.
+    pub fn move_if_absent<K>(
+        &mut self,
+        db: &Database,
+        from: &K,
+        to: &K,
+    ) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+    {
+        let data = self.get(db, from)?;
+        match self.put(db, to, &data, WriteFlags::NO_OVERWRITE) {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == ErrorKind::AlreadyExists => {
+                return Ok(false)
+            }
+            Err(err) => return Err(err),
+        }
+        self.del(db, from)?;
+        Ok(true)
+    }
+
+    /// Replaces an item if its value is the expected one.
+    ///
+    /// `None` as the expected value means that the item must be absent,
+    /// and `None` as the new value deletes the item.  Returns whether the
+    /// swap took place; nothing is changed otherwise.
+    pub fn compare_and_swap<K>(
+        &mut self,
+        db: &Database,
+        key: &K,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+    {
+        let matches = match self.get_ref(db, key) {
+            Ok(data) => expected == Some(&data[..]),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {
+                expected.is_none()
+            }
+            Err(err) => return Err(err),
+        };
+        if !matches {
+            return Ok(false);
+        }
+        match new {
+            Some(data) => self.put(db, key, &data, WriteFlags::empty())?,
+            None => {
+                self.del_opt(db, key)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Gets an item from a database, storing `default` under the key first
+    /// if it is absent.
+    ///
+    /// Returns the value found, or else `default`.  The default is neither
+    /// written nor counted against `Database::begin_rw_txn_bounded` limits
+    /// when the key is present.
+    pub fn get_or_put<K, D>(
+        &mut self,
+        db: &Database,
+        key: &K,
+        default: D,
+    ) -> Result<Vec<u8>>
+    where
+        K: AsRef<[u8]>,
+        D: AsRef<[u8]>,
+    {
+        if let Some(data) = self.get_opt(db, key)? {
+            return Ok(data);
+        }
+        self.put(db, key, &default, WriteFlags::NO_OVERWRITE)?;
+        Ok(default.as_ref().to_vec())
+    }
+
+    /// Adds `delta` to a counter and returns its new value.
+    ///
+    /// The counter is stored as a big-endian 64-bit integer, and starts at
+    /// 0 if the item is absent.  Fails with `ErrorKind::InvalidArgument` if
+    /// the value isn't 8 bytes long or the counter would overflow.
+    pub fn increment<K>(
+        &mut self,
+        db: &Database,
+        key: &K,
+        delta: i64,
+    ) -> Result<i64>
+    where
+        K: AsRef<[u8]>,
+    {
+        let value = match self.get_ref(db, key) {
+            Ok(data) => match <[u8; 8]>::try_from(&data[..]) {
+                Ok(buf) => i64::from_be_bytes(buf),
+                Err(_) => {
+                    return Err(Error::from_errno(libc::EINVAL, Op::TxnGet)
+                        .context(format!(
+                            "Counter {} isn't a 64-bit integer",
+                            key.as_ref().escape_ascii()
+                        )))
+                }
+            },
+            Err(ref err) if err.kind() == ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+        let value = value.checked_add(delta).ok_or_else(|| {
+            Error::from_errno(libc::EINVAL, Op::TxnPut).context(format!(
+                "Counter {} would overflow",
+                key.as_ref().escape_ascii()
+            ))
+        })?;
+        self.put(db, key, &value.to_be_bytes(), WriteFlags::empty())?;
+        Ok(value)
+    }
+
+    /// Open a new read-write cursor on the given database.
+    ///
+    /// The cursor borrows the transaction mutably, so items can only be
+    /// modified through the cursor while it is open.
+    pub fn open_rw_cursor<'txn>(
+        &'txn mut self,
+        db: &Database,
+    ) -> Result<RwCursor<'txn>> {
+        RwCursor::new(self, db)
+    }
+
+    /// Begin a child transaction.
+    ///
+    /// btree has no sub-transactions, so the child keeps its changes in
+    /// memory and sees them on top of the parent's state.  Committing the
+    /// child applies them to the parent, and dropping it discards them.
+    pub fn begin_nested<'txn>(&'txn mut self) -> NestedTransaction<'txn, 'db> {
+        NestedTransaction {
+            parent: self,
+            writes: BTreeMap::new(),
+        }
+    }
+}
+
+/// A child of a read-write transaction, see `RwTransaction::begin_nested`.
+///
+/// Changes are buffered, with `None` marking a deleted key, until the
+/// child is committed.
+pub struct NestedTransaction<'txn, 'db: 'txn> {
+    parent: &'txn mut RwTransaction<'db>,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'txn, 'db> NestedTransaction<'txn, 'db> {
+    /// Gets an item, as changed by this transaction, from a database.
+    pub fn get<K>(&self, db: &Database, key: &K) -> Result<Vec<u8>>
+    where
+        K: AsRef<[u8]>,
+    {
+        match self.writes.get(key.as_ref()) {
+            Some(Some(data)) => Ok(data.clone()),
+            Some(None) => Err(Error::from_errno(libc::ENOENT, Op::TxnGet)),
+            None => self.parent.get(db, key),
+        }
+    }
+
+    /// Checks whether an item, as changed by this transaction, is present
+    /// in a database.
+    pub fn contains_key<K>(&self, db: &Database, key: &K) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+    {
+        match self.writes.get(key.as_ref()) {
+            Some(data) => Ok(data.is_some()),
+            None => self.parent.contains_key(db, key),
+        }
+    }
+
+    /// Stores an item into a database once the transaction is committed.
+    pub fn put<K, D>(
+        &mut self,
+        db: &Database,
+        key: &K,
+        data: &D,
+        flags: WriteFlags,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        D: AsRef<[u8]>,
+    {
+        db.check_key_len(key.as_ref(), Op::TxnPut)?;
+        if flags.contains(WriteFlags::NO_OVERWRITE)
+            && self.contains_key(db, key)?
+        {
+            return Err(Error::from_errno(libc::EEXIST, Op::TxnPut));
+        }
+        self.writes
+            .insert(key.as_ref().to_vec(), Some(data.as_ref().to_vec()));
+        Ok(())
+    }
+
+    /// Deletes an item from a database once the transaction is committed.
+    pub fn del<K>(&mut self, db: &Database, key: &K) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+    {
+        db.check_key_len(key.as_ref(), Op::TxnDel)?;
+        if !self.contains_key(db, key)? {
+            return Err(Error::from_errno(libc::ENOENT, Op::TxnDel));
+        }
+        self.writes.insert(key.as_ref().to_vec(), None);
+        Ok(())
+    }
+
+    /// Applies the changes to the parent transaction.
+    ///
+    /// If this fails, the parent may hold some of the changes and should
+    /// be dropped.
+    pub fn commit(self) -> Result<()> {
+        let db = self.parent.db;
+        for (key, data) in self.writes {
+            match data {
+                Some(data) => {
+                    self.parent.put(db, &key, &data, WriteFlags::empty())?
+                }
+                None => {
+                    self.parent.del_opt(db, &key)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -210,7 +1347,6 @@ mod test {
     use tempdir::TempDir;
 
     use super::*;
-    use error::ErrorKind;
 
     #[test]
     fn test_put_get_del() {
@@ -228,37 +1364,1068 @@ mod test {
         assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
         assert_eq!(b"val2".to_vec(), txn.get(&db, b"key2").unwrap());
         assert_eq!(b"val3".to_vec(), txn.get(&db, b"key3").unwrap());
-        assert_eq!(txn.get(&db, b"key"), Err(ErrorKind::NotFound.into()));
+        assert!(txn
+            .get(&db, b"key")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
 
         txn.del(&db, b"key1").unwrap();
-        assert_eq!(txn.get(&db, b"key1"), Err(ErrorKind::NotFound.into()));
+        assert!(txn
+            .get(&db, b"key1")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
     }
 
     #[test]
-    fn test_concurrent_readers_single_writer() {
+    fn test_mismatched_database() {
+        let dir = TempDir::new("test").unwrap();
+        let db = Database::new().open(&dir.path().join("db")).unwrap();
+        let other = Database::new().open(&dir.path().join("other")).unwrap();
+
+        let mut txn = other.begin_rw_txn().unwrap();
+        txn.put(&other, b"key1", b"val1", WriteFlags::empty())
+            .unwrap();
+        txn.commit().unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        let err = txn
+            .put(&other, b"key1", b"val2", WriteFlags::empty())
+            .unwrap_err();
+        assert_eq!(ErrorKind::InvalidArgument, err.kind());
+        let err = txn.get(&other, b"key1").unwrap_err();
+        assert_eq!(ErrorKind::InvalidArgument, err.kind());
+        let err = txn.del(&other, b"key1").unwrap_err();
+        assert_eq!(ErrorKind::InvalidArgument, err.kind());
+        let err = txn.open_ro_cursor(&other).unwrap_err();
+        assert_eq!(ErrorKind::InvalidArgument, err.kind());
+        let err = txn.open_rw_cursor(&other).unwrap_err();
+        assert_eq!(ErrorKind::InvalidArgument, err.kind());
+        txn.commit().unwrap();
+
+        let txn = other.begin_ro_txn().unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&other, b"key1").unwrap());
+    }
+
+    #[test]
+    fn test_bounded_txn() {
         let dir = TempDir::new("test").unwrap();
         let dbpath = dir.path().join("test");
-        let db: Arc<Database> =
-            Arc::new(Database::new().open(dbpath.as_path()).unwrap());
+        let db = Database::new().open(dbpath.as_path()).unwrap();
 
-        let n = 10usize; // Number of concurrent readers
-        let barrier = Arc::new(Barrier::new(n + 1));
-        let mut threads: Vec<JoinHandle<bool>> = Vec::with_capacity(n);
+        let mut txn = db.begin_rw_txn_bounded(20).unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
+        assert_eq!(16, txn.bytes_written());
+        assert!(txn
+            .put(&db, b"key3", b"val3", WriteFlags::empty())
+            .unwrap_err()
+            .same_kind(ErrorKind::FileTooLarge));
+        assert_eq!(16, txn.bytes_written());
+        txn.del(&db, b"key1").unwrap();
+        assert!(txn
+            .del(&db, b"key2")
+            .unwrap_err()
+            .same_kind(ErrorKind::FileTooLarge));
+        txn.commit().unwrap();
 
-        let key = b"key";
-        let val = b"val";
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(None, txn.get_opt(&db, b"key1").unwrap());
+        assert_eq!(b"val2".to_vec(), txn.get(&db, b"key2").unwrap());
+        assert_eq!(None, txn.get_opt(&db, b"key3").unwrap());
+    }
 
-        for _ in 0..n {
-            let reader = db.clone();
-            let readbar = barrier.clone();
+    #[test]
+    fn test_get_opt() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
 
-            threads.push(thread::spawn(move || {
-                {
-                    let txn = reader.begin_ro_txn().unwrap();
-                    assert_eq!(
-                        txn.get(&reader, key),
-                        Err(ErrorKind::NotFound.into())
-                    );
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        assert_eq!(Some(b"val1".to_vec()), txn.get_opt(&db, b"key1").unwrap());
+        assert_eq!(None, txn.get_opt(&db, b"key2").unwrap());
+        assert!(txn
+            .get_opt(&db, b"")
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+    }
+
+    #[test]
+    fn test_put_get_checked() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put_checked(&db, b"key1", b"val1").unwrap();
+        txn.put_checked(&db, b"key2", b"").unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get_checked(&db, b"key1").unwrap());
+        assert_eq!(b"".to_vec(), txn.get_checked(&db, b"key2").unwrap());
+        assert_eq!(8, txn.get(&db, b"key1").unwrap().len());
+
+        let mut data = txn.get(&db, b"key1").unwrap();
+        data[5] ^= 0x20;
+        txn.put(&db, b"key1", &data, WriteFlags::empty()).unwrap();
+        assert!(txn
+            .get_checked(&db, b"key1")
+            .unwrap_err()
+            .same_kind(ErrorKind::InputOutput));
+
+        txn.put(&db, b"key3", b"abc", WriteFlags::empty()).unwrap();
+        assert!(txn
+            .get_checked(&db, b"key3")
+            .unwrap_err()
+            .same_kind(ErrorKind::InputOutput));
+        assert!(txn
+            .get_checked(&db, b"key4")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_get_ref() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key2", b"", WriteFlags::empty()).unwrap();
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let val1 = txn.get_ref(&db, b"key1").unwrap();
+        let val2 = txn.get_ref(&db, b"key2").unwrap();
+        assert_eq!(b"val1", &*val1);
+        assert!(val2.is_empty());
+        // Both lookups point at the same cached page instead of copies.
+        let again = txn.get_ref(&db, b"key1").unwrap();
+        assert_eq!(val1.as_ptr(), again.as_ptr());
+        assert!(txn.get_ref(&db, b"key3").is_err());
+    }
+
+    #[test]
+    fn test_get_into() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"value1", WriteFlags::empty())
+            .unwrap();
+        txn.put(&db, b"key2", b"v2", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key3", &vec![3; 10000], WriteFlags::empty())
+            .unwrap();
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let mut buf = Vec::new();
+        assert!(txn.get_into(&db, b"key1", &mut buf).unwrap());
+        assert_eq!(b"value1".to_vec(), buf);
+        assert!(txn.get_into(&db, b"key2", &mut buf).unwrap());
+        assert_eq!(b"v2".to_vec(), buf);
+        assert!(txn.get_into(&db, b"key3", &mut buf).unwrap());
+        assert_eq!(vec![3; 10000], buf);
+        assert!(!txn.get_into(&db, b"key4", &mut buf).unwrap());
+        assert!(buf.is_empty());
+        assert!(buf.capacity() >= 10000);
+    }
+
+    #[test]
+    fn test_value_len() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"value1", WriteFlags::empty())
+            .unwrap();
+        txn.put(&db, b"key2", b"", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key3", &vec![3; 10000], WriteFlags::empty())
+            .unwrap();
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(Some(6), txn.value_len(&db, b"key1").unwrap());
+        assert_eq!(Some(0), txn.value_len(&db, b"key2").unwrap());
+        assert_eq!(Some(10000), txn.value_len(&db, b"key3").unwrap());
+        assert_eq!(None, txn.value_len(&db, b"key4").unwrap());
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        assert_eq!(1, txn.append(&db, b"zebra").unwrap());
+        assert_eq!(2, txn.append(&db, b"apple").unwrap());
+        txn.put(&db, b"key", b"val", WriteFlags::empty()).unwrap();
+        txn.commit().unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        assert_eq!(3, txn.append(&db, b"mango").unwrap());
+        txn.commit().unwrap();
+
+        let log: Vec<_> = db.iter_log().unwrap().collect();
+        assert_eq!(
+            vec![
+                (1, b"zebra".to_vec()),
+                (2, b"apple".to_vec()),
+                (3, b"mango".to_vec()),
+            ],
+            log
+        );
+    }
+
+    #[test]
+    fn test_del_opt() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        assert!(txn.del_opt(&db, b"key1").unwrap());
+        assert!(!txn.del_opt(&db, b"key1").unwrap());
+        assert!(!txn.del_opt(&db, b"key2").unwrap());
+        assert!(!txn.contains_key(&db, b"key1").unwrap());
+    }
+
+    #[test]
+    fn test_del_prefix() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..100 {
+            let session = format!("/sessions/{:03}", i);
+            let user = format!("/users/{:03}", i);
+            txn.put(&db, &session, b"val", WriteFlags::empty()).unwrap();
+            txn.put(&db, &user, b"val", WriteFlags::empty()).unwrap();
+        }
+        txn.put(&db, b"/sessions", b"val", WriteFlags::empty())
+            .unwrap();
+        txn.put(&db, b"/sessions0", b"val", WriteFlags::empty())
+            .unwrap();
+        txn.commit().unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        assert_eq!(100, txn.del_prefix(&db, b"/sessions/").unwrap());
+        assert_eq!(0, txn.del_prefix(&db, b"/sessions/").unwrap());
+        assert_eq!(0, txn.del_prefix(&db, b"/groups/").unwrap());
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(&db).unwrap();
+        let keys: Vec<_> = cursor.iter_start().map(|(key, _)| key).collect();
+        assert_eq!(102, keys.len());
+        assert_eq!(b"/sessions".to_vec(), keys[0]);
+        assert_eq!(b"/sessions0".to_vec(), keys[1]);
+        assert!(keys[2..].iter().all(|key| key.starts_with(b"/users/")));
+        assert_eq!(102, db.len().unwrap());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_put_get_compressed() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let text = "{\"key\": \"value\"}, ".repeat(1000).into_bytes();
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let random: Vec<u8> = (0..10000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put_compressed(&db, b"text", &text).unwrap();
+        txn.put_compressed(&db, b"random", &random).unwrap();
+        txn.put_compressed(&db, b"empty", b"").unwrap();
+        txn.put(&db, b"plain", b"plain", WriteFlags::empty())
+            .unwrap();
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(text, txn.get_compressed(&db, b"text").unwrap());
+        assert!(txn.get(&db, b"text").unwrap().len() < text.len() / 10);
+        assert_eq!(random, txn.get_compressed(&db, b"random").unwrap());
+        assert_eq!(random.len() + 1, txn.get(&db, b"random").unwrap().len());
+        assert!(txn.get_compressed(&db, b"empty").unwrap().is_empty());
+        assert_eq!(b"plain".to_vec(), txn.get(&db, b"plain").unwrap());
+        let err = txn.get_compressed(&db, b"plain").unwrap_err();
+        assert_eq!(ErrorKind::Other, err.kind());
+        drop(txn);
+
+        // Plain values starting with a header byte are misread.
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"plain0", b"\0plain", WriteFlags::empty())
+            .unwrap();
+        txn.put(&db, b"plain1", b"\x01plain", WriteFlags::empty())
+            .unwrap();
+        assert_eq!(
+            b"plain".to_vec(),
+            txn.get_compressed(&db, b"plain0").unwrap()
+        );
+        let err = txn.get_compressed(&db, b"plain1").unwrap_err();
+        assert_eq!(ErrorKind::Other, err.kind());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_put_get_serde() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Point {
+            x: i32,
+            y: i32,
+            label: String,
+        }
+
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let point = Point {
+            x: 3,
+            y: -4,
+            label: "origin".to_string(),
+        };
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put_serde(&db, b"point", &point).unwrap();
+        assert_eq!(point, txn.get_serde(&db, b"point").unwrap());
+
+        txn.put(&db, b"short", b"\x01", WriteFlags::empty())
+            .unwrap();
+        let err = txn.get_serde::<_, Point>(&db, b"short").unwrap_err();
+        assert_eq!(ErrorKind::Other, err.kind());
+    }
+
+    #[test]
+    fn test_fixed_key_len() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new()
+            .fixed_key_len(4)
+            .open(dbpath.as_path())
+            .unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert!(txn
+            .put(&db, b"key11", b"val11", WriteFlags::empty())
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+        assert!(txn
+            .get(&db, b"key")
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+        assert!(txn
+            .del(&db, b"key11")
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+        assert!(txn
+            .contains_key(&db, b"key")
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+        assert!(txn
+            .value_len(&db, b"key")
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+        assert!(txn
+            .get_prefix_bytes(&db, b"key", 2)
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+        assert!(txn
+            .is_cached(&db, b"key")
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+        assert!(txn.contains_key(&db, b"key1").unwrap());
+        txn.del(&db, b"key1").unwrap();
+        txn.commit().unwrap();
+        db.set_meta(b"meta").unwrap();
+        drop(db);
+
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key11", b"val11", WriteFlags::empty())
+            .unwrap();
+        assert_eq!(b"val11".to_vec(), txn.get(&db, b"key11").unwrap());
+    }
+
+    #[test]
+    fn test_error_details() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new()
+            .fixed_key_len(4)
+            .open(dbpath.as_path())
+            .unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        let err = txn.get(&db, b"key1").unwrap_err();
+        assert_eq!(Some(libc::ENOENT), err.raw_os_error());
+        assert_eq!("txn_get", err.op_name());
+        let err = txn.put(&db, b"key", b"val", WriteFlags::empty());
+        let err = err.unwrap_err();
+        assert_eq!(Some(libc::EINVAL), err.raw_os_error());
+        assert_eq!("txn_put", err.op_name());
+        assert_eq!(None, Error::from(ErrorKind::Other).raw_os_error());
+    }
+
+    #[test]
+    fn test_move_if_absent() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
+
+        assert!(txn.move_if_absent(&db, b"key1", b"key3").unwrap());
+        assert!(!txn.contains_key(&db, b"key1").unwrap());
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key3").unwrap());
+
+        assert!(!txn.move_if_absent(&db, b"key2", b"key3").unwrap());
+        assert_eq!(b"val2".to_vec(), txn.get(&db, b"key2").unwrap());
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key3").unwrap());
+
+        assert!(txn
+            .move_if_absent(&db, b"key1", b"key4")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+        assert!(!txn.contains_key(&db, b"key4").unwrap());
+    }
+
+    #[test]
+    fn test_compare_and_swap() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        assert!(txn
+            .compare_and_swap(&db, b"key1", None, Some(b"val1"))
+            .unwrap());
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert!(!txn
+            .compare_and_swap(&db, b"key1", None, Some(b"val2"))
+            .unwrap());
+
+        assert!(txn
+            .compare_and_swap(&db, b"key1", Some(b"val1"), Some(b"val2"))
+            .unwrap());
+        assert_eq!(b"val2".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert!(!txn
+            .compare_and_swap(&db, b"key1", Some(b"val1"), Some(b"val3"))
+            .unwrap());
+        assert_eq!(b"val2".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert!(!txn
+            .compare_and_swap(&db, b"key2", Some(b"val1"), Some(b"val3"))
+            .unwrap());
+        assert!(!txn.contains_key(&db, b"key2").unwrap());
+
+        assert!(txn
+            .compare_and_swap(&db, b"key1", Some(b"val2"), None)
+            .unwrap());
+        assert!(!txn.contains_key(&db, b"key1").unwrap());
+        assert!(txn.compare_and_swap(&db, b"key1", None, None).unwrap());
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_commit_event() {
+        use std::fmt::Debug;
+        use std::sync::Mutex;
+
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        type Fields = Vec<(String, String)>;
+
+        struct Visitor(Fields);
+
+        impl Visit for Visitor {
+            fn record_str(&mut self, field: &Field, value: &str) {
+                self.0.push((field.name().to_string(), value.to_string()));
+            }
+
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                let value = format!("{:?}", value);
+                self.0.push((field.name().to_string(), value));
+            }
+        }
+
+        // Collects the fields of every event.
+        struct Events(Arc<Mutex<Vec<Fields>>>);
+
+        impl Subscriber for Events {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+
+            fn new_span(&self, _: &Attributes) -> Id {
+                Id::from_u64(1)
+            }
+
+            fn record(&self, _: &Id, _: &Record) {}
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, event: &Event) {
+                let mut visitor = Visitor(Vec::new());
+                event.record(&mut visitor);
+                self.0.lock().unwrap().push(visitor.0);
+            }
+
+            fn enter(&self, _: &Id) {}
+
+            fn exit(&self, _: &Id) {}
+        }
+
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(Events(events.clone()), || {
+            let mut txn = db.begin_rw_txn().unwrap();
+            txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+            txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
+            txn.commit().unwrap();
+        });
+
+        let events = events.lock().unwrap();
+        let field = |fields: &Fields, name: &str| {
+            fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value.clone())
+        };
+        let op = |fields: &Fields| field(fields, "op");
+        assert_eq!(Some("txn_begin".to_string()), op(&events[0]));
+        let commit = events
+            .iter()
+            .find(|fields| op(fields).as_deref() == Some("txn_commit"))
+            .unwrap();
+        assert_eq!(Some("16".to_string()), field(commit, "bytes_written"));
+        assert_eq!(Some("2".to_string()), field(commit, "entries"));
+        assert_eq!(Some("1".to_string()), field(commit, "revision"));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
+        txn.commit().unwrap();
+
+        let snapshot = db.snapshot().unwrap();
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"new1", WriteFlags::empty()).unwrap();
+        txn.del(&db, b"key2").unwrap();
+        txn.put(&db, b"key3", b"val3", WriteFlags::empty()).unwrap();
+        txn.commit().unwrap();
+
+        assert_eq!(b"val1".to_vec(), snapshot.get(b"key1").unwrap());
+        assert_eq!(b"val2".to_vec(), snapshot.get(b"key2").unwrap());
+        assert!(!snapshot.contains_key(b"key3").unwrap());
+        let mut cursor = snapshot.cursor().unwrap();
+        let keys: Vec<_> = cursor.iter_start().map(|(key, _)| key).collect();
+        assert_eq!(vec![b"key1".to_vec(), b"key2".to_vec()], keys);
+        assert_eq!(2, snapshot.transaction().count_range(&db, ..).unwrap());
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"new1".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert!(!txn.contains_key(&db, b"key2").unwrap());
+        assert!(txn.contains_key(&db, b"key3").unwrap());
+    }
+
+    #[test]
+    fn test_count_range() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().change_feed(true).open(&dbpath).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..1000 {
+            let key = format!("key{:03}", i);
+            txn.put(&db, &key, b"val", WriteFlags::empty()).unwrap();
+        }
+        txn.commit().unwrap();
+
+        // The change feed adds reserved keys, which aren't counted.
+        let txn = db.begin_ro_txn().unwrap();
+        let (start, end) = (&b"key100"[..], &b"key350"[..]);
+        assert_eq!(250, txn.count_range(&db, start..end).unwrap());
+        assert_eq!(251, txn.count_range(&db, start..=end).unwrap());
+        assert_eq!(900, txn.count_range(&db, start..).unwrap());
+        assert_eq!(350, txn.count_range(&db, ..end).unwrap());
+        assert_eq!(1000, txn.count_range(&db, ..).unwrap());
+        assert_eq!(0, txn.count_range(&db, end..start).unwrap());
+    }
+
+    #[test]
+    fn test_for_each() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..100usize {
+            let key = format!("key{:03}", i);
+            txn.put(&db, &key, &vec![b'x'; i], WriteFlags::empty())
+                .unwrap();
+        }
+        txn.commit().unwrap();
+        db.set_meta(b"meta").unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let mut total = 0;
+        txn.for_each(&db, |_, data| {
+            total += data.len();
+            true
+        })
+        .unwrap();
+        assert_eq!((0..100).sum::<usize>(), total);
+
+        let mut keys = Vec::new();
+        txn.for_each(&db, |key, _| {
+            keys.push(key.to_vec());
+            keys.len() < 3
+        })
+        .unwrap();
+        assert_eq!(
+            vec![b"key000".to_vec(), b"key001".to_vec(), b"key002".to_vec()],
+            keys
+        );
+    }
+
+    #[test]
+    fn test_get_or_put() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn_bounded(16).unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        let written = txn.bytes_written();
+
+        assert_eq!(
+            b"val1".to_vec(),
+            txn.get_or_put(&db, b"key1", b"new").unwrap()
+        );
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert_eq!(written, txn.bytes_written());
+
+        assert_eq!(
+            b"new".to_vec(),
+            txn.get_or_put(&db, b"key2", b"new").unwrap()
+        );
+        assert_eq!(b"new".to_vec(), txn.get(&db, b"key2").unwrap());
+        assert_eq!(
+            b"new".to_vec(),
+            txn.get_or_put(&db, b"key2", b"other").unwrap()
+        );
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"new".to_vec(), txn.get(&db, b"key2").unwrap());
+    }
+
+    #[test]
+    fn test_increment() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        assert_eq!(5, txn.increment(&db, b"count", 5).unwrap());
+        assert_eq!(
+            5i64.to_be_bytes().to_vec(),
+            txn.get(&db, b"count").unwrap()
+        );
+        assert_eq!(7, txn.increment(&db, b"count", 2).unwrap());
+        assert_eq!(-3, txn.increment(&db, b"count", -10).unwrap());
+        txn.commit().unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        assert_eq!(-2, txn.increment(&db, b"count", 1).unwrap());
+
+        txn.put(&db, b"short", b"1", WriteFlags::empty()).unwrap();
+        let err = txn.increment(&db, b"short", 1).unwrap_err();
+        assert_eq!(ErrorKind::InvalidArgument, err.kind());
+        assert_eq!(b"1".to_vec(), txn.get(&db, b"short").unwrap());
+
+        assert_eq!(i64::MAX, txn.increment(&db, b"max", i64::MAX).unwrap());
+        let err = txn.increment(&db, b"max", 1).unwrap_err();
+        assert_eq!(ErrorKind::InvalidArgument, err.kind());
+    }
+
+    #[test]
+    fn test_multi_range() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..10 {
+            let key = format!("key{}", i);
+            txn.put(&db, &key, &key, WriteFlags::empty()).unwrap();
+        }
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let ranges = [("key6", "key9"), ("key1", "key3"), ("key2", "key7")];
+        let results = txn.multi_range(&db, &ranges).unwrap();
+        let keys: Vec<Vec<_>> = results
+            .iter()
+            .map(|items| items.iter().map(|item| item.0.clone()).collect())
+            .collect();
+        let expect = |range: &[u8]| -> Vec<_> {
+            range
+                .iter()
+                .map(|i| format!("key{}", i).into_bytes())
+                .collect()
+        };
+        assert_eq!(
+            vec![
+                expect(&[6, 7, 8]),
+                expect(&[1, 2]),
+                expect(&[2, 3, 4, 5, 6])
+            ],
+            keys
+        );
+        assert_eq!(results[1][0].0, results[1][0].1);
+    }
+
+    #[test]
+    fn test_reset_renew() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert!(!txn.contains_key(&db, b"key1").unwrap());
+        let reset = txn.reset();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.commit().unwrap();
+
+        let txn = reset.renew().unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
+    }
+
+    #[test]
+    fn test_commit_with_status() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        let status = txn.commit_with_status().unwrap();
+        assert!(status.durable);
+        assert_eq!(u64::from(db.stat().unwrap().revisions), status.revision);
+        db.close();
+
+        let db = Database::new()
+            .set_flags(DatabaseFlags::NO_SYNC)
+            .open(dbpath.as_path())
+            .unwrap();
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
+        let next = txn.commit_with_status().unwrap();
+        assert!(!next.durable);
+        assert_eq!(status.revision + 1, next.revision);
+    }
+
+    #[test]
+    fn test_put_append() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..1000 {
+            let key = format!("key{:04}", i);
+            txn.put(&db, &key, &key, WriteFlags::APPEND).unwrap();
+        }
+        txn.commit().unwrap();
+        assert_eq!(1000, db.len().unwrap());
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for key in &["key0500", "key0999", "key", "a"] {
+            let err =
+                txn.put(&db, key, b"val", WriteFlags::APPEND).unwrap_err();
+            assert_eq!(ErrorKind::InvalidArgument, err.kind());
+        }
+        txn.put(&db, b"key1000", b"val", WriteFlags::APPEND)
+            .unwrap();
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"key0500".to_vec(), txn.get(&db, b"key0500").unwrap());
+        assert!(txn
+            .get(&db, b"a")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+        assert_eq!(1001, db.len().unwrap());
+    }
+
+    #[test]
+    fn test_clear() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        for i in 0..1000 {
+            let key = format!("key{:04}", i);
+            txn.put(&db, &key, &key, WriteFlags::empty()).unwrap();
+        }
+        txn.commit().unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.clear(&db).unwrap();
+        assert!(txn
+            .get(&db, b"key0001")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+        drop(txn);
+        assert_eq!(1000, db.len().unwrap());
+
+        let size = db.file_size().unwrap();
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.clear(&db).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(0, db.len().unwrap());
+        assert!(db.file_size().unwrap() <= size + 4096);
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(0, txn.open_ro_cursor(&db).unwrap().iter_raw().count());
+        drop(txn);
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(1, db.len().unwrap());
+    }
+
+    #[test]
+    fn test_clear_change_feed() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().change_feed(true).open(&dbpath).unwrap();
+        db.update(|txn| txn.put(&db, b"key1", b"val1", WriteFlags::empty()))
+            .unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        assert!(txn
+            .clear(&db)
+            .unwrap_err()
+            .same_kind(ErrorKind::InvalidArgument));
+        txn.commit().unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert_eq!(1, db.poll_changes(0, 10).unwrap().0.len());
+    }
+
+    #[test]
+    fn test_commit_with_revision() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        let first = txn.commit_with_revision().unwrap();
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
+        let second = txn.commit_with_revision().unwrap();
+        assert!(second > first);
+        assert_eq!(u64::from(db.stat().unwrap().revisions), second);
+    }
+
+    #[test]
+    fn test_nested_txn() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
+
+        {
+            let mut child = txn.begin_nested();
+            child
+                .put(&db, b"key3", b"val3", WriteFlags::empty())
+                .unwrap();
+            child.del(&db, b"key1").unwrap();
+            assert_eq!(b"val3".to_vec(), child.get(&db, b"key3").unwrap());
+            assert!(!child.contains_key(&db, b"key1").unwrap());
+            assert!(child
+                .put(&db, b"key2", b"val", WriteFlags::NO_OVERWRITE)
+                .unwrap_err()
+                .same_kind(ErrorKind::AlreadyExists));
+            assert!(child
+                .del(&db, b"key1")
+                .unwrap_err()
+                .same_kind(ErrorKind::NotFound));
+            child.commit().unwrap();
+        }
+        assert!(!txn.contains_key(&db, b"key1").unwrap());
+        assert_eq!(b"val3".to_vec(), txn.get(&db, b"key3").unwrap());
+
+        {
+            let mut child = txn.begin_nested();
+            child
+                .put(&db, b"key4", b"val4", WriteFlags::empty())
+                .unwrap();
+            child.del(&db, b"key2").unwrap();
+        }
+        assert!(!txn.contains_key(&db, b"key4").unwrap());
+        assert_eq!(b"val2".to_vec(), txn.get(&db, b"key2").unwrap());
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert!(!txn.contains_key(&db, b"key1").unwrap());
+        assert!(txn.contains_key(&db, b"key2").unwrap());
+        assert!(txn.contains_key(&db, b"key3").unwrap());
+        assert!(!txn.contains_key(&db, b"key4").unwrap());
+    }
+
+    #[test]
+    fn test_put_batch() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let items: Vec<_> = (0..1000)
+            .map(|i| (format!("key{}", i), format!("val{}", i)))
+            .collect();
+        let mut txn = db.begin_rw_txn().unwrap();
+        let stored =
+            txn.put_batch(&db, items.iter().cloned(), WriteFlags::empty());
+        assert_eq!(1000, stored.unwrap());
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        for (key, data) in &items {
+            assert_eq!(data.as_bytes(), &txn.get(&db, key).unwrap()[..]);
+        }
+        drop(txn);
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        let dups = vec![("new1", "val"), ("key1", "val"), ("new2", "val")];
+        let (stored, err) = txn
+            .put_batch(&db, dups, WriteFlags::NO_OVERWRITE)
+            .unwrap_err();
+        assert_eq!(1, stored);
+        assert_eq!(ErrorKind::AlreadyExists, err.kind());
+        assert!(txn.contains_key(&db, b"new1").unwrap());
+        assert!(!txn.contains_key(&db, b"new2").unwrap());
+    }
+
+    #[test]
+    fn test_contains_key() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
+        txn.del(&db, b"key2").unwrap();
+        assert!(txn.contains_key(&db, b"key1").unwrap());
+        assert!(!txn.contains_key(&db, b"key2").unwrap());
+        assert!(!txn.contains_key(&db, b"key3").unwrap());
+    }
+
+    #[test]
+    fn test_get_prefix_bytes() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+        let val: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", &val, WriteFlags::empty()).unwrap();
+        txn.put(&db, b"key2", b"val2", WriteFlags::empty()).unwrap();
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        let prefix = txn.get_prefix_bytes(&db, b"key1", 16).unwrap();
+        assert_eq!(&val[..16], &prefix[..]);
+        assert_eq!(
+            b"val2".to_vec(),
+            txn.get_prefix_bytes(&db, b"key2", 16).unwrap()
+        );
+        assert!(txn
+            .get_prefix_bytes(&db, b"key3", 16)
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_is_cached() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        txn.put(&db, b"key1", b"val1", WriteFlags::empty()).unwrap();
+        txn.commit().unwrap();
+
+        // A second handle starts out with a cold cache.
+        let db = Database::new().open(dbpath.as_path()).unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert!(!txn.is_cached(&db, b"key1").unwrap());
+        assert!(txn.is_cached(&db, b"key1").unwrap());
+        txn.get(&db, b"key1").unwrap();
+        assert!(txn.is_cached(&db, b"key1").unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_readers_single_writer() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db: Arc<Database> =
+            Arc::new(Database::new().open(dbpath.as_path()).unwrap());
+
+        let n = 10usize; // Number of concurrent readers
+        let barrier = Arc::new(Barrier::new(n + 1));
+        let mut threads: Vec<JoinHandle<bool>> = Vec::with_capacity(n);
+
+        let key = b"key";
+        let val = b"val";
+
+        for _ in 0..n {
+            let reader = db.clone();
+            let readbar = barrier.clone();
+
+            threads.push(thread::spawn(move || {
+                {
+                    let txn = reader.begin_ro_txn().unwrap();
+                    assert!(txn
+                        .get(&reader, key)
+                        .unwrap_err()
+                        .same_kind(ErrorKind::NotFound));
                 }
                 readbar.wait();
                 readbar.wait();
@@ -0,0 +1,285 @@
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::str;
+
+use database::Database;
+use error::{Error, Result};
+use keys;
+use transaction::{RwTransaction, Transaction, WriteFlags};
+
+/// Conversion of keys and values to and from their stored representation.
+///
+/// Implemented for all types implementing `Bytes` and `FromBytes`.
+pub trait Codec: Sized {
+    /// Encodes the value into bytes.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Decodes a value from bytes, returning `None` if they don't hold a
+    /// valid encoding.
+    fn decode(data: &[u8]) -> Option<Self>;
+}
+
+/// Every type converting to and from bytes is a `Codec`, so only `Bytes`
+/// and `FromBytes` need implementing.
+impl<T: Bytes + FromBytes> Codec for T {
+    fn encode(&self) -> Vec<u8> {
+        self.to_bytes().into_owned()
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        T::from_bytes(data).ok()
+    }
+}
+
+/// Conversion of keys and values into bytes, see `Transaction::get_as`.
+///
+/// Unlike `Codec`, the bytes are borrowed where possible.
+pub trait Bytes {
+    /// Returns the stored representation of the value.
+    fn to_bytes(&self) -> Cow<'_, [u8]>;
+}
+
+/// Conversion of keys and values from bytes, see `Transaction::get_as`.
+pub trait FromBytes: Sized {
+    /// Decodes a value from bytes, failing with `ErrorKind::Other` if they
+    /// don't hold a valid encoding.
+    fn from_bytes(data: &[u8]) -> Result<Self>;
+}
+
+fn decode_error(what: &str) -> Error {
+    Error::other(format!("Failed to decode the value as {}", what))
+}
+
+impl Bytes for [u8] {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Bytes for Vec<u8> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl FromBytes for Vec<u8> {
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        Ok(data.to_vec())
+    }
+}
+
+impl Bytes for str {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+}
+
+impl Bytes for &str {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+}
+
+impl Bytes for String {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+}
+
+impl FromBytes for String {
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        str::from_utf8(data)
+            .map(str::to_string)
+            .map_err(|_| decode_error("a UTF-8 string"))
+    }
+}
+
+/// Integers are stored big-endian, with the sign bit of signed ones
+/// flipped, so that their keys sort numerically, see the `keys` module.
+impl Bytes for u64 {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(keys::encode_u64_be(*self).to_vec())
+    }
+}
+
+impl FromBytes for u64 {
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        keys::decode_u64_be(data).ok_or_else(|| decode_error("u64"))
+    }
+}
+
+impl Bytes for u32 {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(keys::encode_u32_be(*self).to_vec())
+    }
+}
+
+impl FromBytes for u32 {
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        keys::decode_u32_be(data).ok_or_else(|| decode_error("u32"))
+    }
+}
+
+impl Bytes for i64 {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(keys::encode_i64_be(*self).to_vec())
+    }
+}
+
+impl FromBytes for i64 {
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        keys::decode_i64_be(data).ok_or_else(|| decode_error("i64"))
+    }
+}
+
+impl Bytes for i32 {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(keys::encode_i32_be(*self).to_vec())
+    }
+}
+
+impl FromBytes for i32 {
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        keys::decode_i32_be(data).ok_or_else(|| decode_error("i32"))
+    }
+}
+
+/// A typed view of a database.
+///
+/// Keys and values are converted with their `Codec` implementations on
+/// the way in and out, and otherwise go through the transaction methods
+/// unchanged.
+pub struct Table<'db, K, V> {
+    db: &'db Database,
+    _marker: PhantomData<fn(K, V)>,
+}
+
+impl<'db, K, V> Table<'db, K, V>
+where
+    K: Codec,
+    V: Codec,
+{
+    /// Creates a typed view of the database.
+    pub fn new(db: &'db Database) -> Self {
+        Table {
+            db,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the underlying database.
+    pub fn database(&self) -> &'db Database {
+        self.db
+    }
+
+    /// Gets the value stored under the key, if present.
+    ///
+    /// A value that fails to decode is reported as `ErrorKind::Other`.
+    pub fn get<T>(&self, txn: &T, key: &K) -> Result<Option<V>>
+    where
+        T: Transaction,
+    {
+        match txn.get_opt(self.db, &key.encode())? {
+            Some(data) => V::decode(&data).map(Some).ok_or_else(|| {
+                Error::other("Failed to decode the value".to_string())
+            }),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores the value under the key.
+    pub fn put(&self, txn: &mut RwTransaction, key: &K, val: &V) -> Result<()> {
+        txn.put(self.db, &key.encode(), &val.encode(), WriteFlags::empty())
+    }
+
+    /// Deletes the value stored under the key, and returns whether it was
+    /// present.
+    pub fn del(&self, txn: &mut RwTransaction, key: &K) -> Result<bool> {
+        txn.del_opt(self.db, &key.encode())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempdir::TempDir;
+
+    use error::ErrorKind;
+
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+        let table: Table<String, u64> = Table::new(&db);
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        table.put(&mut txn, &"apples".to_string(), &3).unwrap();
+        table
+            .put(&mut txn, &"pears".to_string(), &(1 << 40))
+            .unwrap();
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(Some(3), table.get(&txn, &"apples".to_string()).unwrap());
+        assert_eq!(
+            Some(1 << 40),
+            table.get(&txn, &"pears".to_string()).unwrap()
+        );
+        assert_eq!(None, table.get(&txn, &"plums".to_string()).unwrap());
+        drop(txn);
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        assert!(table.del(&mut txn, &"apples".to_string()).unwrap());
+        txn.put(&db, b"plums", b"many", WriteFlags::empty())
+            .unwrap();
+        assert_eq!(None, table.get(&txn, &"apples".to_string()).unwrap());
+        let err = table.get(&txn, &"plums".to_string()).unwrap_err();
+        assert_eq!(ErrorKind::Other, err.kind());
+    }
+
+    #[test]
+    fn test_bytes() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+        let db = Database::new().open(&dbpath).unwrap();
+
+        let mut txn = db.begin_rw_txn().unwrap();
+        let name = "apples".to_string();
+        txn.put_as(&db, &name, &3u64, WriteFlags::empty()).unwrap();
+        txn.put_as(&db, &(1u64 << 40), "pears", WriteFlags::empty())
+            .unwrap();
+        txn.put_as(&db, &-5i32, &-7i64, WriteFlags::empty())
+            .unwrap();
+        txn.put_as(&db, "plums", &b"\xff"[..], WriteFlags::empty())
+            .unwrap();
+        txn.commit().unwrap();
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(3u64, txn.get_as(&db, &name).unwrap());
+        assert_eq!(3u64, txn.get_as(&db, "apples").unwrap());
+        let pears: String = txn.get_as(&db, &(1u64 << 40)).unwrap();
+        assert_eq!("pears", pears);
+        assert_eq!(-7i64, txn.get_as(&db, &-5i32).unwrap());
+        assert_eq!(vec![0xff], txn.get_as::<_, Vec<u8>>(&db, "plums").unwrap());
+
+        let err = txn.get_as::<_, u32>(&db, "apples").unwrap_err();
+        assert_eq!(ErrorKind::Other, err.kind());
+        let err = txn.get_as::<_, String>(&db, "plums").unwrap_err();
+        assert_eq!(ErrorKind::Other, err.kind());
+        let err = txn.get_as::<_, u64>(&db, "figs").unwrap_err();
+        assert_eq!(ErrorKind::NotFound, err.kind());
+
+        assert_eq!(-1i64, i64::from_bytes(&(-1i64).to_bytes()).unwrap());
+        assert!((-1i32).to_bytes() < 0i32.to_bytes());
+        assert_eq!(vec![0, 0, 0, 7], 7u32.to_bytes().into_owned());
+
+        // Codec goes through the same encoding.
+        assert_eq!((-5i32).to_bytes().into_owned(), (-5i32).encode());
+        assert_eq!(Some(-7i64), i64::decode(&(-7i64).to_bytes()));
+        assert_eq!(None, String::decode(b"\xff"));
+        let table: Table<i32, i64> = Table::new(&db);
+        assert_eq!(Some(-7), table.get(&txn, &-5).unwrap());
+    }
+}
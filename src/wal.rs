@@ -0,0 +1,171 @@
+use std::io::{self, Read};
+
+use database::Database;
+use error::{Error, ErrorKind, Result};
+use transaction::WriteFlags;
+
+/// Record type of a `PUT key val` operation.
+pub(crate) const OP_PUT: u8 = b'P';
+/// Record type of a `DEL key` operation.
+pub(crate) const OP_DEL: u8 = b'D';
+
+/// A single operation read from a write-ahead log.
+enum Record {
+    Put(Vec<u8>, Vec<u8>),
+    Del(Vec<u8>),
+}
+
+fn read_error(err: io::Error) -> Error {
+    Error::other(format!("Failed to read the write-ahead log: {}", err))
+}
+
+/// Reads a field prefixed with its big-endian 32-bit length.  Returns
+/// `None` if the log ends before the field does.
+fn read_field<R: Read>(wal: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match wal.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(None)
+        }
+        Err(err) => return Err(read_error(err)),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    let mut field = Vec::new();
+    wal.take(len as u64)
+        .read_to_end(&mut field)
+        .map_err(read_error)?;
+    if field.len() < len {
+        return Ok(None);
+    }
+    Ok(Some(field))
+}
+
+/// Reads the next record.  Returns `None` at the end of the log, or if the
+/// last record is incomplete.
+fn read_record<R: Read>(wal: &mut R) -> Result<Option<Record>> {
+    let mut op = [0u8; 1];
+    loop {
+        match wal.read(&mut op) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(read_error(err)),
+        }
+    }
+    match op[0] {
+        OP_PUT => {
+            let key = match read_field(wal)? {
+                Some(key) => key,
+                None => return Ok(None),
+            };
+            Ok(read_field(wal)?.map(|val| Record::Put(key, val)))
+        }
+        OP_DEL => Ok(read_field(wal)?.map(Record::Del)),
+        op => Err(Error::other(format!(
+            "Invalid write-ahead log record type {:#04x}",
+            op
+        ))),
+    }
+}
+
+/// Applies all operations from the log in a single write transaction and
+/// returns their number.
+pub(crate) fn replay<R: Read>(db: &Database, mut wal: R) -> Result<usize> {
+    db.update(|txn| {
+        let mut count = 0;
+        while let Some(record) = read_record(&mut wal)? {
+            match record {
+                Record::Put(key, val) => {
+                    txn.put(db, &key, &val, WriteFlags::empty())?
+                }
+                Record::Del(key) => match txn.del(db, &key) {
+                    Err(ref err) if err.kind() == ErrorKind::NotFound => (),
+                    res => res?,
+                },
+            }
+            count += 1;
+        }
+        Ok(count)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tempdir::TempDir;
+
+    use transaction::Transaction;
+
+    use super::*;
+
+    fn field(wal: &mut Vec<u8>, data: &[u8]) {
+        wal.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        wal.extend_from_slice(data);
+    }
+
+    fn put(wal: &mut Vec<u8>, key: &[u8], val: &[u8]) {
+        wal.push(OP_PUT);
+        field(wal, key);
+        field(wal, val);
+    }
+
+    fn del(wal: &mut Vec<u8>, key: &[u8]) {
+        wal.push(OP_DEL);
+        field(wal, key);
+    }
+
+    #[test]
+    fn test_replay_wal() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+
+        {
+            let db = Database::new().open(&dbpath).unwrap();
+            let mut txn = db.begin_rw_txn().unwrap();
+            txn.put(&db, b"key1", b"old1", WriteFlags::empty()).unwrap();
+            txn.put(&db, b"key2", b"old2", WriteFlags::empty()).unwrap();
+            txn.commit().unwrap();
+        }
+
+        let mut wal = Vec::new();
+        put(&mut wal, b"key1", b"val1");
+        del(&mut wal, b"key2");
+        put(&mut wal, b"key3", b"val3");
+        del(&mut wal, b"key4");
+        // A record torn by a crash while appending to the log.
+        let len = wal.len();
+        put(&mut wal, b"key5", b"val5");
+        wal.truncate(len + 7);
+
+        let db = Database::new().replay_wal(&dbpath, &wal[..]).unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(b"val1".to_vec(), txn.get(&db, b"key1").unwrap());
+        assert!(txn
+            .get(&db, b"key2")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+        assert_eq!(b"val3".to_vec(), txn.get(&db, b"key3").unwrap());
+        assert!(txn
+            .get(&db, b"key5")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_replay_wal_invalid() {
+        let dir = TempDir::new("test").unwrap();
+        let dbpath = dir.path().join("test");
+
+        let mut wal = Vec::new();
+        put(&mut wal, b"key1", b"val1");
+        wal.push(b'X');
+
+        assert!(Database::new().replay_wal(&dbpath, &wal[..]).is_err());
+        let db = Database::new().open(&dbpath).unwrap();
+        let txn = db.begin_ro_txn().unwrap();
+        assert!(txn
+            .get(&db, b"key1")
+            .unwrap_err()
+            .same_kind(ErrorKind::NotFound));
+    }
+}